@@ -0,0 +1,52 @@
+#!/usr/bin/env bash
+# Measures throughput of bloom in lines per second.
+#
+# Usage: benches/throughput.sh [BINARY] [NUM_LINES] [NUM_RUNS]
+#
+# Generates NUM_LINES (10M by default) log-like lines, about half of them repeated, and runs BINARY (release build of
+# the working tree by default) over them NUM_RUNS (5 by default) times with several container types. Runs of the
+# container types are interleaved, so a slower period of the machine affects all of them. Median, minimum, maximum and
+# relative standard deviation of the throughput are reported for each.
+
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+binary=${1:-target/release/bloom}
+num_lines=${2:-10000000}
+num_runs=${3:-5}
+input=$(mktemp)
+rates=$(mktemp)
+trap 'rm -f "$input" "$rates"' EXIT
+
+if [ "$binary" = target/release/bloom ]; then
+  cargo build --release --quiet
+  echo "Commit: $(git rev-parse --short HEAD)$(git diff --quiet HEAD -- src Cargo.toml || echo ' (modified)')"
+fi
+echo "Binary: $binary, lines: $num_lines, runs: $num_runs, CPU: $(grep -m1 'model name' /proc/cpuinfo 2>/dev/null | cut -d: -f2- | xargs || uname -m)"
+
+awk -v n="$num_lines" 'BEGIN { for (i = 1; i <= n; i++) print "2026-10-18T12:00:00 GET /api/items/" (i * 7919) % (n / 2) " 200" }' > "$input"
+
+options=("-xls 10M,64MiB" "-bls 10M,32MiB" "-el 10M")
+
+for (( run = 0; run < num_runs; run++ )); do
+  for args in "${options[@]}"; do
+    start=$(date +%s%N)
+    # shellcheck disable=SC2086
+    "$binary" $args < "$input" > /dev/null
+    end=$(date +%s%N)
+    echo "$args	$(( num_lines * 1000000000 / (end - start) ))" >> "$rates"
+  done
+done
+
+printf "%-16s %12s %12s %12s %8s\n" "Options" "Median" "Min" "Max" "Stdev"
+for args in "${options[@]}"; do
+  grep -F -e "$args	" "$rates" | cut -f2 | sort -n | awk -v args="$args" '
+    { rate[NR] = $1; sum += $1; sum_sq += $1 * $1 }
+    END {
+      median = NR % 2 ? rate[(NR + 1) / 2] : (rate[NR / 2] + rate[NR / 2 + 1]) / 2
+      mean = sum / NR
+      stdev = NR > 1 ? sqrt((sum_sq - NR * mean * mean) / (NR - 1)) : 0
+      printf "%-16s %12d %12d %12d %7.1f%%\n", args, median, rate[1], rate[NR], 100 * stdev / mean
+    }'
+done
@@ -0,0 +1,130 @@
+use crate::bloom::containers::container_memory_exact::MemoryContainerExact;
+use crate::bloom::containers::container_memory_hybrid::MemoryContainerHybrid;
+use crate::bloom::containers::container_memory_timed::MemoryContainerTimed;
+use crate::bloom::sizing::{bloom_error_rate, bloom_size_for_error_rate, format_size, xxh_error_rate};
+use crate::{ConstructionDetails, ConstructionType, Params};
+
+/// Returns number of bytes allocated by the container with given construction details.
+pub fn footprint(construction_details: &ConstructionDetails) -> u64 {
+    let limit = construction_details.limit;
+    match construction_details.construction_type {
+        // Size is only known once the filter is created.
+        ConstructionType::BloomLinesAndErrorRate if construction_details.size == 0 => bloom_size_for_error_rate(limit, construction_details.error_rate),
+        ConstructionType::BloomLinesAndErrorRate | ConstructionType::BloomLinesAndSize => construction_details.size,
+        ConstructionType::XXHLimitAndSize | ConstructionType::CountMinLimitAndSize => construction_details.size,
+        ConstructionType::ExactLimit64 => MemoryContainerExact::calc_size_for_limit(limit, 1),
+        ConstructionType::ExactLimit128 => MemoryContainerExact::calc_size_for_limit(limit, 2),
+        ConstructionType::TimedLimitAndTtl => MemoryContainerTimed::calc_size_for_limit(limit),
+        // Both phases are allocated while spilling, and the filter may grow up to all of its layers.
+        ConstructionType::HybridLimitAndSize => construction_details.size + MemoryContainerHybrid::calc_filter_size_and_error_rate(limit, construction_details.size).0,
+    }
+}
+
+/// Checks whether size of the container type depends only on its limit.
+fn is_fixed_size(construction_type: ConstructionType) -> bool {
+    matches!(construction_type, ConstructionType::ExactLimit64 | ConstructionType::ExactLimit128 | ConstructionType::TimedLimitAndTtl)
+}
+
+/// Returns construction details of the container type with the lowest false positive rate for given limit and size.
+fn best_construction_details(limit: u64, size: u64) -> ConstructionDetails {
+    let exact = ConstructionDetails {
+        construction_type: ConstructionType::ExactLimit64,
+        limit,
+        size: 0,
+        error_rate: 0.0,
+        ttl: 0,
+        shards: 1,
+    };
+
+    if footprint(&exact) <= size {
+        // Exact container has practically no false positives.
+        return exact;
+    }
+
+    let construction_type = if bloom_error_rate(size, limit) <= xxh_error_rate(size, limit) {
+        ConstructionType::BloomLinesAndSize
+    } else {
+        ConstructionType::XXHLimitAndSize
+    };
+
+    ConstructionDetails {
+        construction_type,
+        size,
+        ..exact
+    }
+}
+
+/// Sizes containers which will be created from given construction details, so they fit into the budget together with
+/// already existing ones. Number of containers created from each construction details is passed in num_containers.
+/// Budget left after fixed-size containers is split proportionally to the limits, which keeps the same number of bytes
+/// per line (and so similar false positive rate) in all containers. When choose_type is set, the container type is also
+/// picked to minimize false positive rate.
+pub fn fit_to_budget(constructions_details: &mut [ConstructionDetails], num_containers: &[u64], existing: u64, budget: u64, choose_type: bool) {
+    let mut fixed = existing;
+    let mut total_weight: u128 = 0;
+
+    for (construction_details, num) in constructions_details.iter().zip(num_containers) {
+        if is_fixed_size(construction_details.construction_type) {
+            fixed = fixed.saturating_add(footprint(construction_details).saturating_mul(*num));
+        }
+        else {
+            total_weight += construction_details.limit as u128 * *num as u128;
+        }
+    }
+
+    if fixed > budget {
+        eprintln!("Error: Existing and fixed-size containers need {}, which exceeds memory budget of {}.", format_size(fixed), format_size(budget));
+        std::process::exit(1);
+    }
+
+    let remaining = budget - fixed;
+
+    for (construction_details, num) in constructions_details.iter_mut().zip(num_containers) {
+        if *num == 0 || is_fixed_size(construction_details.construction_type) {
+            continue;
+        }
+
+        let share = (remaining as u128 * construction_details.limit as u128 / total_weight.max(1)) as u64;
+
+        if share == 0 {
+            eprintln!("Error: Memory budget of {} leaves nothing for container with limit {}.", format_size(budget), construction_details.limit);
+            std::process::exit(1);
+        }
+
+        if choose_type {
+            *construction_details = best_construction_details(construction_details.limit, share);
+            continue;
+        }
+
+        match construction_details.construction_type {
+            ConstructionType::BloomLinesAndSize | ConstructionType::BloomLinesAndErrorRate => {
+                // Error rate is implied by the size now.
+                construction_details.construction_type = ConstructionType::BloomLinesAndSize;
+                construction_details.size = share;
+                construction_details.error_rate = 0.0;
+            }
+            ConstructionType::XXHLimitAndSize | ConstructionType::CountMinLimitAndSize => construction_details.size = share,
+            ConstructionType::HybridLimitAndSize => {
+                // Exact phase gets what the filter leaves.
+                construction_details.size = MemoryContainerHybrid::calc_size_for_budget(construction_details.limit, share).unwrap_or_else(|| {
+                    eprintln!("Error: Hybrid container with limit {} needs more than {} of memory budget.", construction_details.limit, format_size(share));
+                    std::process::exit(1);
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns number of bytes allocated by all the containers.
+pub fn containers_footprint(params: &mut Params) -> u64 {
+    params.containers.iter_mut().map(|container| footprint(&container.get_container_details().construction_details)).sum()
+}
+
+/// Checks whether container with given construction details fits into the budget together with existing containers.
+pub fn fits_budget(params: &mut Params, construction_details: &ConstructionDetails) -> bool {
+    match params.memory_budget {
+        Some(budget) => containers_footprint(params) + footprint(construction_details) <= budget,
+        None => true,
+    }
+}
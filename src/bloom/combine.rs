@@ -0,0 +1,407 @@
+use crate::bloom::containers::container::{Container, FillEstimate};
+use crate::bloom::partition::{partition_name, Partition};
+use crate::{ConstructionType, ContainerDetails};
+
+/// Set operation combining filter files.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SetOperation {
+    // Lines of any of the files.
+    Union,
+    // Lines of all the files.
+    Intersection,
+    // Lines of the first file which aren't in any of the other files.
+    Difference,
+}
+
+impl SetOperation {
+    /// Returns name of the subcommand running the operation.
+    fn name(&self) -> &'static str {
+        match self {
+            SetOperation::Union => "merge",
+            SetOperation::Intersection => "intersect",
+            SetOperation::Difference => "subtract",
+        }
+    }
+
+    /// Returns message about the file which can't be combined with the first one, e.g. Can't merge "b" into "a".
+    fn failure(&self, input: &str, first: &str) -> String {
+        match self {
+            SetOperation::Union => format!("Can't merge \"{}\" into \"{}\"", input, first),
+            SetOperation::Intersection => format!("Can't intersect \"{}\" with \"{}\"", first, input),
+            SetOperation::Difference => format!("Can't subtract \"{}\" from \"{}\"", input, first),
+        }
+    }
+}
+
+fn print_help(operation: SetOperation) {
+    println!("USAGE:");
+    println!("  bloom {} -o OUTPUT FILE FILE [FILE...]", operation.name());
+    println!();
+    match operation {
+        SetOperation::Union => {
+            println!("Writes union of the given filter files into OUTPUT, so lines written into any of them are found in it. Files");
+            println!("must be compatible: Bloom filters of the same size, number of hash functions and keys (copies of the same empty");
+            println!("file), xxHash filters of the same size, exact sets of the same digest width, count-min sketches of the same");
+            println!("width or sharded containers of the same number of shards. Files holding all partitions of the input (see");
+            println!("--partition) may be merged into the file of the whole input.");
+        }
+        SetOperation::Intersection => {
+            println!("Writes intersection of the given filter files into OUTPUT, so only lines written into all of them are found");
+            println!("in it. Files must be compatible as for \"bloom merge\". Intersection of Bloom filters keeps bits set by");
+            println!("different lines of the files too, so it has more false positives than a filter of the common lines would.");
+            println!("Lines found in the first xxHash filter only because all slots of their lookup are occupied were never");
+            println!("stored, so they're lost.");
+        }
+        SetOperation::Difference => {
+            println!("Writes lines of the first filter file which aren't in any of the other files into OUTPUT. Only xxHash filters");
+            println!("and exact sets can be subtracted and files must be compatible as for \"bloom merge\". Lines whose lookup in");
+            println!("the other files is a false positive are removed too. Lines found in the first xxHash filter only because");
+            println!("all slots of their lookup are occupied were never stored, so they're lost.");
+        }
+    }
+    println!();
+    println!("Number of lines and false positive rate of the result are estimated from its bit or slot fill.");
+    println!();
+    println!("OPTIONS:");
+    println!("  -o,   --output FILE       File the result is written into. May be one of the given files.");
+    println!("  -sw,  --silent-warnings   Silences warnings.");
+}
+
+/// Returns name of the container type shown in messages.
+fn kind_name(construction_type: ConstructionType) -> &'static str {
+    match construction_type {
+        ConstructionType::BloomLinesAndSize | ConstructionType::BloomLinesAndErrorRate => "Bloom filter",
+        ConstructionType::XXHLimitAndSize => "xxHash filter",
+        ConstructionType::ExactLimit64 | ConstructionType::ExactLimit128 => "exact set",
+        ConstructionType::HybridLimitAndSize => "hybrid",
+        ConstructionType::TimedLimitAndTtl => "time-aware",
+        ConstructionType::CountMinLimitAndSize => "count-min sketch",
+    }
+}
+
+/// Returns partition of the union of the files holding given partitions. Files may hold the same partition, or all of
+/// them together may hold all partitions of the input, in which case union holds the whole input.
+fn union_partition(partitions: &[Option<Partition>]) -> Result<Option<Partition>, String> {
+    if partitions.iter().all(|partition| *partition == partitions[0]) {
+        return Ok(partitions[0]);
+    }
+
+    let count = partitions.iter().flatten().map(|partition| partition.count).next().unwrap();
+    let covers_input = partitions.iter().all(|partition| partition.is_some_and(|partition| partition.count == count))
+        && (1 ..= count).all(|idx| partitions.contains(&Some(Partition { idx, count })));
+    if covers_input {
+        return Ok(None);
+    }
+
+    let names: Vec<String> = partitions.iter().map(|partition| partition_name(*partition)).collect();
+    Err(format!("files hold different partitions of the input ({}), which together are neither one partition nor the whole input", names.join(", ")))
+}
+
+/// Returns partition of the result of the files holding given partitions, which must all be the same.
+fn same_partition(partitions: &[Option<Partition>]) -> Result<Option<Partition>, String> {
+    if partitions.iter().all(|partition| *partition == partitions[0]) {
+        return Ok(partitions[0]);
+    }
+
+    let names: Vec<String> = partitions.iter().map(|partition| partition_name(*partition)).collect();
+    Err(format!("files hold different partitions of the input ({}), which have no lines in common", names.join(", ")))
+}
+
+/// Adds details of the other container to details of the union. Union lives as long as the longest living of the
+/// files.
+fn merge_details(details: &mut ContainerDetails, other_details: &ContainerDetails) {
+    details.hll.merge(&other_details.hll);
+    if other_details.created_at != 0 && (details.created_at == 0 || other_details.created_at < details.created_at) {
+        details.created_at = other_details.created_at;
+    }
+    if details.expires_at != 0 {
+        details.expires_at = if other_details.expires_at == 0 { 0 } else { details.expires_at.max(other_details.expires_at) };
+    }
+}
+
+/// Runs "bloom merge", "bloom intersect" or "bloom subtract" subcommand with given arguments (following the
+/// subcommand).
+pub fn combine(operation: SetOperation, args: &[String]) {
+    let mut output: Option<String> = None;
+    let mut inputs: Vec<String> = Vec::new();
+    let mut silent_warnings = false;
+
+    let mut idx = 0;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "-o" | "--output" => {
+                output = Some(args.get(idx + 1).cloned().unwrap_or_else(|| {
+                    eprintln!("Error: --output expects path of the resulting file.");
+                    std::process::exit(1);
+                }));
+                idx += 1;
+            }
+            "-sw" | "--silent-warnings" => silent_warnings = true,
+            "-h" | "--help" => {
+                print_help(operation);
+                std::process::exit(0);
+            }
+            arg if arg.starts_with('-') => {
+                eprintln!("Error: Invalid parameter passed to {}: \"{}\".", operation.name(), arg);
+                std::process::exit(1);
+            }
+            path => inputs.push(path.to_string()),
+        }
+        idx += 1;
+    }
+
+    let Some(output) = output else {
+        eprintln!("Error: {} expects --output parameter.", operation.name());
+        std::process::exit(1);
+    };
+    if inputs.len() < 2 {
+        eprintln!("Error: {} expects at least two files.", operation.name());
+        std::process::exit(1);
+    }
+
+    for input in inputs.iter() {
+        if !<dyn Container>::exists(input) {
+            eprintln!("Error: File \"{}\" doesn't exist.", input);
+            std::process::exit(1);
+        }
+    }
+
+    // Content is mapped privately, so none of the given files is modified before the result is saved.
+    let mut result = <dyn Container>::from_file(&inputs[0], false);
+    let mut partitions = vec![result.get_container_details().partition];
+
+    for input in inputs.iter().skip(1) {
+        let mut other = <dyn Container>::from_file(input, false);
+        let fail = |reason: String| -> ! {
+            eprintln!("Error: {}: {}.", operation.failure(input, &inputs[0]), reason);
+            std::process::exit(1);
+        };
+
+        check_compatible(&mut *result, &mut *other).unwrap_or_else(|reason| fail(reason));
+        match operation {
+            SetOperation::Union => result.merge(&*other),
+            SetOperation::Intersection => result.intersect(&*other),
+            SetOperation::Difference => result.subtract(&*other),
+        }.unwrap_or_else(|reason| fail(reason));
+
+        partitions.push(other.get_container_details().partition);
+        // Results of other operations hold only lines of the first file, so they keep its timestamps. Estimator of
+        // distinct lines can't be intersected, so the one of the first file is kept as the upper bound.
+        if operation == SetOperation::Union {
+            merge_details(result.get_container_details(), other.get_container_details());
+        }
+    }
+
+    let partition = match operation {
+        SetOperation::Union => union_partition(&partitions),
+        _ => same_partition(&partitions),
+    };
+    let details = result.get_container_details();
+    details.partition = partition.unwrap_or_else(|reason| {
+        eprintln!("Error: Can't {} files: {}.", operation.name(), reason);
+        std::process::exit(1);
+    });
+    details.path = output.clone();
+    let construction_type = details.construction_details.construction_type;
+
+    result.save();
+
+    if !silent_warnings {
+        match (operation, construction_type) {
+            (SetOperation::Union, ConstructionType::ExactLimit64 | ConstructionType::ExactLimit128) => {}
+            (SetOperation::Union, _) => {
+                eprintln!("Warning: Number of writes of \"{}\" is the sum of the merged files, so lines written into several of them are counted more than once.", output);
+            }
+            (SetOperation::Intersection, ConstructionType::BloomLinesAndSize | ConstructionType::BloomLinesAndErrorRate) => {
+                eprintln!("Warning: Intersection of Bloom filters keeps bits set by different lines of the files, so \"{}\" has more false positives than a filter of the common lines would.", output);
+            }
+            _ => {}
+        }
+    }
+
+    let done = match operation {
+        SetOperation::Union => "Merged",
+        SetOperation::Intersection => "Intersected",
+        SetOperation::Difference => "Subtracted",
+    };
+    println!("{} {} files into \"{}\" ({} writes, {:.2} % used).", done, inputs.len(), output, result.get_num_writes(), result.get_usage());
+    if let Some(estimate) = result.estimate_fill() {
+        println!("Estimated lines: {:.0}, false positive rate implied by fill: {:.1e}.", estimate.num_values, estimate.error_rate);
+    }
+}
+
+/// Checks whether containers have the same type and number of shards. Content of the containers is checked when they
+/// are combined.
+pub(crate) fn check_compatible(container: &mut dyn Container, other: &mut dyn Container) -> Result<(), String> {
+    let details = container.get_container_details().construction_details;
+    let other_details = other.get_container_details().construction_details;
+
+    let (kind, other_kind) = (kind_name(details.construction_type), kind_name(other_details.construction_type));
+    if kind != other_kind {
+        return Err(format!("containers have different types ({} and {})", kind, other_kind));
+    }
+    if details.shards != other_details.shards {
+        return Err(format!("containers have different numbers of shards ({} and {})", details.shards, other_details.shards));
+    }
+    Ok(())
+}
+
+fn print_compare_help() {
+    println!("USAGE:");
+    println!("  bloom compare [--json] FILE FILE");
+    println!();
+    println!("Estimates number of lines of each of the filter files from its bit or slot fill, then number of lines they have");
+    println!("in common, number of lines of their union and their Jaccard similarity (common lines / union lines). Files must");
+    println!("be compatible as for \"bloom merge\" (Bloom filters, xxHash filters or exact sets). Lines of a saturated Bloom");
+    println!("filter (all bits set) can't be estimated, so its estimates are shown as \"saturated\" (null in JSON).");
+    println!();
+    println!("OPTIONS:");
+    println!("  --json                    Prints estimates as a JSON object.");
+}
+
+/// Estimated sizes of two sets and of their intersection and union. Sizes are None if they can't be estimated, as all
+/// bits of the Bloom filter are set.
+struct Comparison {
+    num_values: [Option<f64>; 2],
+    error_rates: [f64; 2],
+    intersection: Option<f64>,
+    union: Option<f64>,
+}
+
+/// Returns estimate of the container content. Exits if it can't be estimated.
+fn estimate_fill(container: &dyn Container, path: &str) -> FillEstimate {
+    container.estimate_fill().unwrap_or_else(|| {
+        eprintln!("Error: Can't compare \"{}\": only Bloom filters, xxHash filters and exact sets can be compared.", path);
+        std::process::exit(1);
+    })
+}
+
+/// Returns estimate of the number of lines, or None if the filter is saturated and its estimate is infinite.
+fn finite(num_values: f64) -> Option<f64> {
+    Some(num_values).filter(|num_values| num_values.is_finite())
+}
+
+/// Formats number with given format, or as JSON null if it's unknown or not finite (JSON has no infinity nor NaN).
+fn json_number(value: Option<f64>, format: impl Fn(f64) -> String) -> String {
+    value.filter(|value| value.is_finite()).map_or_else(|| String::from("null"), format)
+}
+
+/// Formats estimate with given format, or as "saturated" if it's unknown.
+fn format_estimate(value: Option<f64>, format: impl Fn(f64) -> String) -> String {
+    value.map_or_else(|| String::from("saturated"), format)
+}
+
+/// Formats string as JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Runs "bloom compare" subcommand with given arguments (following the "compare" one).
+pub fn compare(args: &[String]) {
+    let mut json = false;
+    let mut paths: Vec<String> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                print_compare_help();
+                std::process::exit(0);
+            }
+            arg if arg.starts_with('-') => {
+                eprintln!("Error: Invalid parameter passed to compare: \"{}\".", arg);
+                std::process::exit(1);
+            }
+            path => paths.push(path.to_string()),
+        }
+    }
+
+    if paths.len() != 2 {
+        eprintln!("Error: compare expects exactly two files.");
+        std::process::exit(1);
+    }
+    for path in paths.iter() {
+        if !<dyn Container>::exists(path) {
+            eprintln!("Error: File \"{}\" doesn't exist.", path);
+            std::process::exit(1);
+        }
+    }
+
+    // Content is mapped privately, so the first file isn't modified when the other one is combined into it.
+    let mut container = <dyn Container>::from_file(&paths[0], false);
+    let mut other = <dyn Container>::from_file(&paths[1], false);
+    let fail = |reason: String| -> ! {
+        eprintln!("Error: Can't compare \"{}\" with \"{}\": {}.", paths[0], paths[1], reason);
+        std::process::exit(1);
+    };
+    check_compatible(&mut *container, &mut *other).unwrap_or_else(|reason| fail(reason));
+
+    let estimates = [estimate_fill(&*container, &paths[0]), estimate_fill(&*other, &paths[1])];
+
+    // Bits of the Bloom filters intersection are set by different lines too, so the union is estimated and the
+    // intersection is derived from it.
+    container.merge(&*other).unwrap_or_else(|reason| fail(reason));
+    let union = estimate_fill(&*container, &paths[0]).num_values;
+
+    // Estimates are clamped, so they stay consistent with each other. Union of a saturated filter is saturated too.
+    let num_values = [finite(estimates[0].num_values), finite(estimates[1].num_values)];
+    let (intersection, union) = match (num_values, finite(union)) {
+        ([Some(first), Some(second)], Some(union)) => (
+            Some((first + second - union).clamp(0.0, first.min(second))),
+            Some(union.clamp(first.max(second), first + second)),
+        ),
+        _ => (None, None),
+    };
+    let comparison = Comparison {
+        num_values,
+        error_rates: [estimates[0].error_rate, estimates[1].error_rate],
+        intersection,
+        union,
+    };
+
+    if json {
+        print_comparison_json(&paths, &comparison);
+    } else {
+        print_comparison(&paths, &comparison);
+    }
+}
+
+/// Returns Jaccard similarity of the compared sets (1 for two empty sets), or None if the filters are saturated.
+fn jaccard(comparison: &Comparison) -> Option<f64> {
+    let (intersection, union) = (comparison.intersection?, comparison.union?);
+    Some(if union == 0.0 { 1.0 } else { intersection / union })
+}
+
+/// Prints comparison in human readable form.
+fn print_comparison(paths: &[String], comparison: &Comparison) {
+    for (idx, path) in paths.iter().enumerate() {
+        println!("File \"{}\": {} (false positive rate {:.1e})", path, format_estimate(comparison.num_values[idx], |lines| format!("{:.0} lines", lines)), comparison.error_rates[idx]);
+    }
+    println!("Common lines: {}", format_estimate(comparison.intersection, |intersection| format!("{:.0}", intersection)));
+    println!("Union lines: {}", format_estimate(comparison.union, |union| format!("{:.0}", union)));
+    println!("Jaccard similarity: {}", format_estimate(jaccard(comparison), |jaccard| format!("{:.4}", jaccard)));
+}
+
+/// Prints comparison as a JSON object. Estimates of saturated filters are null.
+fn print_comparison_json(paths: &[String], comparison: &Comparison) {
+    let files: Vec<String> = paths.iter().enumerate()
+        .map(|(idx, path)| format!("{{\"path\": {}, \"lines\": {}, \"error_rate\": {}}}", json_string(path),
+            json_number(comparison.num_values[idx], |lines| format!("{:.0}", lines)),
+            json_number(Some(comparison.error_rates[idx]), |error_rate| format!("{:e}", error_rate))))
+        .collect();
+    println!("{{\"files\": [{}], \"intersection\": {}, \"union\": {}, \"jaccard\": {}}}",
+        files.join(", "),
+        json_number(comparison.intersection, |intersection| format!("{:.0}", intersection)),
+        json_number(comparison.union, |union| format!("{:.0}", union)),
+        json_number(jaccard(comparison), |jaccard| format!("{:.6}", jaccard)));
+}
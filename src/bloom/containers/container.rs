@@ -1,31 +1,96 @@
-use std::fs::File;
+use std::any::Any;
+use std::fs::{self, File, OpenOptions};
 use std::convert::TryFrom;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use byteorder::LittleEndian;
 
 use crate::{ConstructionDetails, ConstructionType};
 use crate::{ContainerDetails, DataSource};
 use crate::bloom::containers::container_memory_bloom::MemoryContainerBloom;
+use crate::bloom::containers::container_memory_count_min::MemoryContainerCountMin;
+use crate::bloom::containers::container_memory_exact::MemoryContainerExact;
+use crate::bloom::containers::container_memory_hybrid::MemoryContainerHybrid;
+use crate::bloom::containers::container_memory_timed::MemoryContainerTimed;
 use crate::bloom::containers::container_memory_xxh::MemoryContainerXXH;
+use crate::bloom::containers::container_sharded::{discover_shards, header_from_files, shard_path, ShardedContainer};
+use crate::bloom::containers::lazy_memory::{FileMapping, MapMode};
+use crate::bloom::hyperloglog::{HyperLogLog, PRECISION};
+use crate::bloom::partition::Partition;
 
 /// Magic value used as first four bytes of container files.
 const MAGIC: u32 = 0xB1008811;
 
-pub trait Container
+/// Size of the header without the extension.
+const HEADER_SIZE: u64 = 128;
+
+/// Offset of the header word holding number of writes of the processes writing into the file at the same time. It's
+/// placed in the padding, aligned, so it can be updated atomically. Zero when the file is not written concurrently.
+pub(crate) const SHARED_NUM_WRITES_OFFSET: u64 = 72;
+
+/// Number of values of the batch whose positions are computed and prefetched before any of them is resolved.
+pub(crate) const BATCH_SIZE: usize = 16;
+
+/// Gives access to the concrete type of the container, so contents of two containers of the same type can be combined.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub trait Container: Sync + AsAny
 {
-    /// Inserts value into the filter.
-    fn set(&mut self, value: &String);
+    /// Inserts value into the filter. Containers also add values written into them to the estimator of distinct lines
+    /// in their details.
+    fn set(&mut self, value: &str);
 
     /// Checks whether filter could have given value.
-    fn check(&self, value: &String) -> bool;
+    fn check(&self, value: &str) -> bool;
 
     /// Checks whether filter could have given value and if no, inserts the value. Returns true if value could have
     /// existed.
-    fn check_and_set(&mut self, value: &String) -> bool;
+    fn check_and_set(&mut self, value: &str) -> bool;
+
+    /// Checks whether filter could have given values. Result for each value is stored in found. Containers which read
+    /// random memory compute positions of several values and prefetch them first, so the values wait for the memory at
+    /// the same time.
+    fn check_batch(&self, values: &[&str], found: &mut [bool]) {
+        for (value, found) in values.iter().zip(found.iter_mut()) {
+            *found = self.check(value);
+        }
+    }
+
+    /// Same as check_and_set() called for each of the values in order, so value repeated in the batch is found the
+    /// second time. Result for each value is stored in found.
+    fn check_and_set_batch(&mut self, values: &[&str], found: &mut [bool]) {
+        for (value, found) in values.iter().zip(found.iter_mut()) {
+            *found = self.check_and_set(value);
+        }
+    }
 
     /// Checks whether container is full, and we should not insert new values.
     fn is_full(&self) -> bool;
 
+    /// Checks whether all the values could be inserted without the container getting full before the last one, so
+    /// they can be written at once.
+    fn has_room_for(&self, values: &[&str]) -> bool {
+        !self.is_full() && self.get_num_writes() + values.len() as u64 <= self.get_num_max_writes()
+    }
+
+    /// Sets number of the most frequent values to track. Ignored by containers which don't count occurrences.
+    fn set_top_size(&mut self, _size: usize) {}
+
+    /// Returns tracked most frequent values with their estimated counts (in this container only).
+    fn get_top(&self) -> Vec<(String, u64)> {
+        vec![]
+    }
+
     /// Returns construction info used to create this container.
     fn get_container_details(&mut self) -> &mut ContainerDetails;
 
@@ -37,6 +102,25 @@ pub trait Container
         100.0f32 / self.get_num_max_writes() as f32 *  self.get_num_writes() as f32
     }
 
+    /// Returns additional, container-specific status shown in debug output.
+    fn get_status(&self) -> Option<String> {
+        None
+    }
+
+    /// Sets current time (Unix timestamp) used by time-aware containers to expire values.
+    fn set_time(&mut self, _timestamp: u64) {}
+
+    /// Returns estimated number of occurrences of the value. Containers which don't count occurrences return at most 1.
+    fn count(&self, value: &str) -> u64 {
+        self.check(value) as u64
+    }
+
+    /// Increments number of occurrences of the value. Returns estimated count before the increment. Containers which
+    /// don't count occurrences return at most 1.
+    fn count_and_set(&mut self, value: &str) -> u64 {
+        self.check_and_set(value) as u64
+    }
+
     // Returns number of writes into the container.
     fn get_num_writes(&self) -> u64;
 
@@ -49,11 +133,36 @@ pub trait Container
     // Sets maximum number of allowed writes into the container (initialized when container file is opened).
     fn set_num_max_writes(&mut self, value: u64);
 
-    /// Saves (overwrites) container into the file.
+    /// Returns version of the content layout, which is stored in the header. Zero for containers with a single layout.
+    fn get_content_version(&self) -> u8 {
+        0
+    }
+
+    /// Sets version of the content layout (initialized when container file is opened, before its content is read).
+    fn set_content_version(&mut self, _version: u8) {}
+
+    /// Saves (overwrites) container into the file. Content modified in place only gets its changes written. Otherwise,
+    /// the file is replaced, so files mapped by other containers are never truncated under them.
     fn save(&mut self) {
-        let path = &self.get_container_details().path;
+        if self.mapped_file().is_some_and(|mapping| mapping.is_concurrent()) {
+            save_concurrent(self);
+            return;
+        }
 
-        let mut file = File::create(path).unwrap();
+        let path = self.get_container_details().path.clone();
+        let extension_size = self.get_container_details().hll.registers().len() as u64;
+        // Content can't stay in place if it was moved to other path or header extension was added.
+        let in_place = self.mapped_file().is_some_and(|mapping| *mapping.path() == path && mapping.content_offset() == HEADER_SIZE + extension_size);
+        let temp_path = format!("{}.tmp", path);
+
+        let mut file = if in_place {
+            OpenOptions::new().write(true).open(&path)
+        } else {
+            File::create(&temp_path)
+        }.unwrap_or_else(|_| {
+            eprintln!("Error: Can't open file \"{}\" for writing!", path);
+            std::process::exit(1);
+        });
 
         // Writing magic value.
         file.write_u32::<BigEndian>(MAGIC).unwrap();
@@ -64,10 +173,10 @@ pub trait Container
         file.write_u8(container_details.construction_details.construction_type as u8).unwrap();
 
         // Writing size.
-        file.write_u64::<LittleEndian>(container_details.construction_details.size as u64).unwrap();
+        file.write_u64::<LittleEndian>(container_details.construction_details.size).unwrap();
 
         // Writing limit.
-        file.write_u64::<LittleEndian>(container_details.construction_details.limit as u64).unwrap();
+        file.write_u64::<LittleEndian>(container_details.construction_details.limit).unwrap();
 
         // Writing error rate.
         file.write_f64::<LittleEndian>(container_details.construction_details.error_rate).unwrap();
@@ -78,12 +187,56 @@ pub trait Container
         // Writing maximum number of written items.
         file.write_u64::<LittleEndian>(self.get_num_max_writes()).unwrap();
 
+        let content_version = self.get_content_version();
+        let container_details = self.get_container_details();
+
+        // Writing creation timestamp.
+        file.write_u64::<LittleEndian>(container_details.created_at).unwrap();
+
+        // Writing expiration timestamp.
+        file.write_u64::<LittleEndian>(container_details.expires_at).unwrap();
+
+        // Writing number of seconds after which values expire.
+        file.write_u64::<LittleEndian>(container_details.construction_details.ttl).unwrap();
+
+        // Writing precision of the HyperLogLog stored in the header extension (zero if there's no extension).
+        let registers = container_details.hll.registers();
+        file.write_u8(if registers.is_empty() { 0 } else { PRECISION }).unwrap();
+
+        // Writing version of the content layout.
+        file.write_u8(content_version).unwrap();
+
+        // Padding before the partition. Shared number of writes is zeroed too, as file is not written concurrently
+        // anymore.
+        for _ in 0 .. 9 {
+            file.write_u8(0).unwrap();
+        }
+
+        // Writing partition of the input (index and number of partitions, zeros if container holds the whole input).
+        file.write_u32::<LittleEndian>(container_details.partition.map_or(0, |partition| partition.idx)).unwrap();
+        file.write_u32::<LittleEndian>(container_details.partition.map_or(0, |partition| partition.count)).unwrap();
+
         // Aligning to 128 bytes, so structure may grow without affecting content.
-        for _ in 0 .. 83 {
+        for _ in 0 .. 40 {
             file.write_u8(0).unwrap();
         }
 
-        self.save_content(&mut file);
+        // Writing header extension with HyperLogLog registers.
+        file.write_all(registers).unwrap();
+
+        if in_place {
+            self.sync_content(&mut file);
+            // Content may be followed by data of variable length, which may have shrunk.
+            let end = file.stream_position().unwrap();
+            file.set_len(end).unwrap();
+        }
+        else {
+            self.save_content(&mut file);
+            fs::rename(&temp_path, &path).unwrap_or_else(|_| {
+                eprintln!("Error: Can't replace file \"{}\"!", path);
+                std::process::exit(1);
+            });
+        }
 
     }
 
@@ -92,82 +245,357 @@ pub trait Container
 
     /// Loads filter data content from the given, already opened file.
     fn load_content(&mut self, file: &mut File);
+
+    /// Maps filter data content of the given file starting at the offset instead of loading it. Returns false if
+    /// content can't be mapped and has to be loaded.
+    fn map_content(&mut self, _file: &mut File, _path: &str, _offset: u64, _mode: MapMode) -> bool {
+        false
+    }
+
+    /// Returns mapping of the file if content is modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        None
+    }
+
+    /// Writes changes of the content modified in place to the given, already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.save_content(file);
+    }
+
+    /// Adds values of the other container of the same type into this one. Returns reason why containers can't be
+    /// merged.
+    fn merge(&mut self, _other: &dyn Container) -> Result<(), String> {
+        Err("only Bloom filters, xxHash filters, exact sets and count-min sketches can be merged".to_string())
+    }
+
+    /// Keeps only values which the other container of the same type could have too. Returns reason why containers
+    /// can't be intersected.
+    fn intersect(&mut self, _other: &dyn Container) -> Result<(), String> {
+        Err("only Bloom filters, xxHash filters and exact sets can be intersected".to_string())
+    }
+
+    /// Keeps only values which the other container of the same type doesn't have. Returns reason why containers can't
+    /// be subtracted.
+    fn subtract(&mut self, _other: &dyn Container) -> Result<(), String> {
+        Err("only xxHash filters and exact sets can be subtracted".to_string())
+    }
+
+    /// Estimates number of stored values and false positive rate from the content of the container (e.g. number of set
+    /// bits), which unlike number of writes stays correct after containers are combined.
+    fn estimate_fill(&self) -> Option<FillEstimate> {
+        None
+    }
+}
+
+/// Number of stored values and false positive rate estimated from the content of the container.
+#[derive(Copy, Clone)]
+pub struct FillEstimate {
+    pub num_values: f64,
+    pub error_rate: f64,
+}
+
+impl dyn Container + '_ {
+    // Returns container as its concrete type, if it's of that type.
+    pub(crate) fn downcast_ref<T: Container + 'static>(&self) -> Option<&T> {
+        AsAny::as_any(self).downcast_ref()
+    }
 }
 
 impl dyn Container {
     // Creates container from container details.
     pub fn from_details(container_details: ContainerDetails) -> Box<dyn Container> {
-        if matches!(container_details.construction_details.construction_type, ConstructionType::BloomLinesAndErrorRate {..}) {
-            return Box::new(MemoryContainerBloom::new_limit_and_error_rate(container_details));
-        } else if matches!(container_details.construction_details.construction_type, ConstructionType::BloomLinesAndSize {..}) {
-            return Box::new(MemoryContainerBloom::new_limit_and_size(container_details));
-        } else if matches!(container_details.construction_details.construction_type, ConstructionType::XXHLimitAndSize {..}) {
-            return Box::new(MemoryContainerXXH::new_limit_and_size(container_details));
+        if container_details.construction_details.shards > 1 {
+            Box::new(ShardedContainer::new(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::BloomLinesAndErrorRate) {
+            Box::new(MemoryContainerBloom::new_limit_and_error_rate(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::BloomLinesAndSize) {
+            Box::new(MemoryContainerBloom::new_limit_and_size(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::XXHLimitAndSize) {
+            Box::new(MemoryContainerXXH::new_limit_and_size(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::ExactLimit64 | ConstructionType::ExactLimit128) {
+            Box::new(MemoryContainerExact::new_limit(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::HybridLimitAndSize) {
+            Box::new(MemoryContainerHybrid::new_limit_and_size(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::TimedLimitAndTtl) {
+            Box::new(MemoryContainerTimed::new_limit_and_ttl(container_details))
+        } else if matches!(container_details.construction_details.construction_type, ConstructionType::CountMinLimitAndSize) {
+            Box::new(MemoryContainerCountMin::new_limit_and_size(container_details))
         } else {
             eprintln!("Internal Error: Construction type not implemented.");
             std::process::exit(1);
         }
     }
 
-    // Creates container from existing file.
-    pub fn from_file(path: &String) -> Box<dyn Container> {
-        let file = &mut File::open(path).unwrap_or_else(|_| {
+    // Creates container from existing file. Content is mapped when possible, so only the accessed parts are read. In
+    // writable mode, changes are made in place and the file is modified even before it's saved.
+    pub fn from_file(path: &String, writable: bool) -> Box<dyn Container> {
+        if is_sharded(path) {
+            return Box::new(ShardedContainer::from_files(path, writable));
+        }
+
+        // Files which can't be written are opened only for reading, so their content is mapped privately.
+        let writable_file = if writable { OpenOptions::new().read(true).write(true).open(path).ok() } else { None };
+        let mode = if writable_file.is_some() { MapMode::Shared } else { MapMode::Private };
+        let file = &mut writable_file.map_or_else(|| File::open(path), Ok).unwrap_or_else(|_| {
             eprintln!("Error: Can't open file \"{}\" for reading!", path);
             std::process::exit(1);
         });
 
-        // Reading magic value.
-        let magic = file.read_u32::<BigEndian>().unwrap();
+        <dyn Container>::from_opened_file(file, path, mode)
+    }
+
+    // Creates container from the file which other processes write into at the same time. File is created if it doesn't
+    // exist yet (unless other process was faster), so all the processes map the same file.
+    pub fn from_shared_file(container_details: ContainerDetails) -> Box<dyn Container> {
+        let path = container_details.path.clone();
 
-        if magic != MAGIC {
-            eprintln!("Error: File \"{}\" is not a bloom filter file!", path);
-            std::process::exit(1);
+        if container_details.construction_details.shards > 1 || is_sharded(&path) {
+            return Box::new(ShardedContainer::from_shared_files(container_details));
         }
 
-        // Reading construction type, e.g., BloomLinesAndSize, XXHLimitAndSize.
-        let construction_type = ConstructionType::try_from(file.read_u8().unwrap()).unwrap();
-
-        // Reading size.
-        let size = file.read_u64::<LittleEndian>().unwrap();
+        if !Path::new(&path).exists() {
+            // Extension with HyperLogLog registers is created up front, so registers can be merged in place.
+            let new_path = format!("{}.{}.new", path, std::process::id());
+            let mut container = <dyn Container>::from_details(ContainerDetails {
+                path: new_path.clone(),
+                hll: HyperLogLog::new_allocated(),
+                ..container_details
+            });
+            container.save();
+
+            // Hard link is created only if there's no file yet, so file created by other process is never replaced.
+            let linked = fs::hard_link(&new_path, &path);
+            fs::remove_file(&new_path).unwrap();
+            if let Err(error) = linked {
+                if error.kind() != ErrorKind::AlreadyExists {
+                    eprintln!("Error: Can't create file \"{}\"!", path);
+                    std::process::exit(1);
+                }
+            }
+        }
 
-        // Reading limit.
-        let limit = file.read_u64::<LittleEndian>().unwrap();
+        let header = <dyn Container>::header_from_file(&path);
+        if !matches!(header.construction_details.construction_type, ConstructionType::BloomLinesAndSize | ConstructionType::BloomLinesAndErrorRate | ConstructionType::XXHLimitAndSize) {
+            eprintln!("Error: File \"{}\" can't be shared, only Bloom filter and xxHash containers can be written concurrently.", path);
+            std::process::exit(1);
+        }
 
-        // Reading error rate.
-        let error_rate = file.read_f64::<LittleEndian>().unwrap();
+        if matches!(header.construction_details.construction_type, ConstructionType::XXHLimitAndSize) && header.content_version == 0 {
+            eprintln!("Error: File \"{}\" can't be shared, as it was created by older version of bloom, whose xxHash slots can't be written atomically.", path);
+            std::process::exit(1);
+        }
 
-        // Reading number of written items.
-        let num_writes = file.read_u64::<LittleEndian>().unwrap();
+        let file = &mut OpenOptions::new().read(true).write(true).open(&path).unwrap_or_else(|_| {
+            eprintln!("Error: Can't open file \"{}\" for writing!", path);
+            std::process::exit(1);
+        });
 
-        // Reading maximum number of written items.
-        let num_max_writes = file.read_u64::<LittleEndian>().unwrap();
+        let container = <dyn Container>::from_opened_file(file, &path, MapMode::Concurrent);
 
-        let construction_details = ConstructionDetails {
-            construction_type,
-            size,
-            limit,
-            error_rate
+        let Some(mapping) = container.mapped_file() else {
+            eprintln!("Error: File \"{}\" can't be shared, as it couldn't be mapped into memory.", path);
+            std::process::exit(1);
         };
 
-        // Aligning to 128 bytes, so structure may grow without affecting content.
-        for _ in 0 .. 83 {
-            file.read_u8().unwrap();
-        }
+        // First process sharing the file takes number of writes from the header. Fails if it was already taken.
+        let _ = mapping.header_u64(SHARED_NUM_WRITES_OFFSET).compare_exchange(0, header.num_writes, Ordering::Relaxed, Ordering::Relaxed);
+
+        container
+    }
+
+    // Creates container from the file opened for reading (and for writing in shared and concurrent modes).
+    fn from_opened_file(file: &mut File, path: &String, mode: MapMode) -> Box<dyn Container> {
+        let header = read_header(file, path);
 
         let mut container = <dyn Container>::from_details(ContainerDetails {
             path: path.to_string(),
-            construction_details,
-            data_source: DataSource::File
+            construction_details: header.construction_details,
+            data_source: DataSource::File,
+            created_at: header.created_at,
+            expires_at: header.expires_at,
+            read_only: false,
+            hll: header.hll,
+            partition: header.partition,
+            num_promotions: 0,
         });
 
-        container.set_num_writes(num_writes);
+        container.set_num_writes(header.num_writes);
+
+        container.set_num_max_writes(header.num_max_writes);
 
-        container.set_num_max_writes(num_max_writes);
+        container.set_content_version(header.content_version);
 
-        container.load_content(file);
+        let offset = file.stream_position().unwrap();
+        if !container.map_content(file, path, offset, mode) {
+            container.load_content(file);
+        }
 
         container
     }
+
+    // Reads only the header of existing container file.
+    pub fn header_from_file(path: &String) -> ContainerHeader {
+        if is_sharded(path) {
+            return header_from_files(path);
+        }
+
+        let file = &mut File::open(path).unwrap_or_else(|_| {
+            eprintln!("Error: Can't open file \"{}\" for reading!", path);
+            std::process::exit(1);
+        });
+
+        read_header(file, path)
+    }
+
+    // Checks whether container file (or shard files of the sharded container) exists.
+    pub fn exists(path: &str) -> bool {
+        Path::new(path).exists() || is_sharded(path)
+    }
+
+    // Removes container file or all shard files of the sharded container.
+    pub fn remove_files(path: &str) -> std::io::Result<()> {
+        if !is_sharded(path) {
+            return fs::remove_file(path);
+        }
+
+        (0 .. discover_shards(path)).try_for_each(|idx| fs::remove_file(shard_path(path, idx)))
+    }
+}
+
+/// Checks whether given path is stored in shard files instead of a single file.
+fn is_sharded(path: &str) -> bool {
+    !Path::new(path).exists() && Path::new(&shard_path(path, 0)).exists()
+}
+
+/// Saves container whose file other processes write into at the same time. Content and number of writes are shared
+/// through the mapping, so only changes are flushed. HyperLogLog registers are merged with ones of other processes.
+fn save_concurrent<C: Container + ?Sized>(container: &mut C) {
+    // Offset of the number of writes field, which follows magic value, construction type, size, limit and error rate.
+    const NUM_WRITES_OFFSET: u64 = 4 + 1 + 3 * 8;
+
+    let registers = container.get_container_details().hll.registers().to_vec();
+    let mapping = container.mapped_file().unwrap();
+
+    if !registers.is_empty() && mapping.content_offset() == HEADER_SIZE + registers.len() as u64 {
+        for (register, value) in mapping.header_bytes(HEADER_SIZE, registers.len()).iter().zip(registers) {
+            register.fetch_max(value, Ordering::Relaxed);
+        }
+    }
+
+    mapping.flush();
+
+    // Header field is updated too, so file can be used without sharing.
+    let mut file = OpenOptions::new().write(true).open(mapping.path()).unwrap_or_else(|_| {
+        eprintln!("Error: Can't open file \"{}\" for writing!", mapping.path());
+        std::process::exit(1);
+    });
+    let shared_num_writes = mapping.header_u64(SHARED_NUM_WRITES_OFFSET);
+    loop {
+        // Another process may save at the same time, so its older value could overwrite ours. Writing again until the
+        // shared counter doesn't change leaves the latest value there.
+        let num_writes = shared_num_writes.load(Ordering::SeqCst);
+        file.seek(SeekFrom::Start(NUM_WRITES_OFFSET)).unwrap();
+        file.write_u64::<LittleEndian>(num_writes).unwrap();
+        if shared_num_writes.load(Ordering::SeqCst) == num_writes {
+            break;
+        }
+    }
+}
+
+/// Header of the container file.
+pub struct ContainerHeader {
+    pub construction_details: ConstructionDetails,
+    pub num_writes: u64,
+    pub num_max_writes: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub hll: HyperLogLog,
+    pub partition: Option<Partition>,
+    pub content_version: u8,
+}
+
+/// Reads header from the given, already opened file. File position is left at the beginning of the content.
+fn read_header(file: &mut File, path: &String) -> ContainerHeader {
+    // Reading magic value.
+    let magic = file.read_u32::<BigEndian>().unwrap();
+
+    if magic != MAGIC {
+        eprintln!("Error: File \"{}\" is not a bloom filter file!", path);
+        std::process::exit(1);
+    }
+
+    // Reading construction type, e.g., BloomLinesAndSize, XXHLimitAndSize.
+    let construction_type = ConstructionType::try_from(file.read_u8().unwrap()).unwrap();
+
+    // Reading size.
+    let size = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading limit.
+    let limit = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading error rate.
+    let error_rate = file.read_f64::<LittleEndian>().unwrap();
+
+    // Reading number of written items.
+    let num_writes = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading maximum number of written items.
+    let num_max_writes = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading creation timestamp. Zero for files created before timestamps were introduced.
+    let created_at = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading expiration timestamp. Zero means that container never expires.
+    let expires_at = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading number of seconds after which values expire. Zero means that values never expire.
+    let ttl = file.read_u64::<LittleEndian>().unwrap();
+
+    // Reading precision of the HyperLogLog stored in the header extension. Zero for files without the extension.
+    let hll_precision = file.read_u8().unwrap();
+
+    // Reading version of the content layout. Zero for files created before layouts were versioned.
+    let content_version = file.read_u8().unwrap();
+
+    // Skipping padding before the partition.
+    for _ in 0 .. 9 {
+        file.read_u8().unwrap();
+    }
+
+    // Reading partition of the input. Zeros for files holding the whole input.
+    let partition_idx = file.read_u32::<LittleEndian>().unwrap();
+    let partition_count = file.read_u32::<LittleEndian>().unwrap();
+    let partition = (partition_count > 0).then_some(Partition { idx: partition_idx, count: partition_count });
+
+    // Aligning to 128 bytes, so structure may grow without affecting content.
+    for _ in 0 .. 40 {
+        file.read_u8().unwrap();
+    }
+
+    // Reading header extension with HyperLogLog registers.
+    let mut registers = vec![0; if hll_precision == 0 { 0 } else { 1 << hll_precision }];
+    file.read_exact(&mut registers).unwrap_or_else(|_| {
+        eprintln!("Error: File \"{}\" has truncated header!", path);
+        std::process::exit(1);
+    });
+
+    ContainerHeader {
+        construction_details: ConstructionDetails {
+            construction_type,
+            size,
+            limit,
+            error_rate,
+            ttl,
+            shards: 1,
+        },
+        num_writes,
+        num_max_writes,
+        created_at,
+        expires_at,
+        hll: HyperLogLog::from_registers(registers),
+        partition,
+        content_version,
+    }
 }
 
 #[cfg(test)]
@@ -180,15 +608,15 @@ mod tests {
     }
 
     impl Container for MockContainer {
-        fn set(&mut self, value: &String) {
-            self.value = value.clone();
+        fn set(&mut self, value: &str) {
+            self.value = value.to_string();
         }
 
-        fn check(&self, value: &String) -> bool {
+        fn check(&self, value: &str) -> bool {
             self.value == *value
         }
 
-        fn check_and_set(&mut self, value: &String) -> bool {
+        fn check_and_set(&mut self, value: &str) -> bool {
             let exists = self.check(value);
             if !exists {
                 self.set(value);
@@ -240,6 +668,124 @@ mod tests {
         assert!(container.check_and_set(&test_value));
     }
 
+    /// Creates memory container of given construction type holding 1000 values split into given number of shards.
+    fn new_container(construction_type: ConstructionType, shards: usize) -> Box<dyn Container> {
+        <dyn Container>::from_details(ContainerDetails {
+            path: String::new(),
+            data_source: DataSource::Memory,
+            construction_details: ConstructionDetails { construction_type, limit: 1000, error_rate: 0.0, size: 16384, ttl: 0, shards },
+            created_at: 0,
+            expires_at: 0,
+            read_only: false,
+            hll: HyperLogLog::new(),
+            partition: None,
+            num_promotions: 0,
+        })
+    }
+
+    #[test]
+    fn test_batches_match_single_values() {
+        // Values repeat within the batches and across them.
+        let values: Vec<String> = (0 .. 200).map(|i| (i * 7 % 90).to_string()).collect();
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+
+        let construction_types = [ConstructionType::BloomLinesAndSize, ConstructionType::XXHLimitAndSize, ConstructionType::ExactLimit64, ConstructionType::ExactLimit128];
+        for (construction_type, shards) in construction_types.into_iter().flat_map(|construction_type| [(construction_type, 1), (construction_type, 4)]) {
+            let mut single = new_container(construction_type, shards);
+            let expected: Vec<bool> = values.iter().map(|value| single.check_and_set(value)).collect();
+
+            let mut batched = new_container(construction_type, shards);
+            let mut found = vec![false; values.len()];
+            batched.check_and_set_batch(&values, &mut found);
+            assert_eq!(found, expected);
+            assert_eq!(batched.get_num_writes(), 90);
+
+            batched.check_batch(&values, &mut found);
+            assert!(found.iter().all(|found| *found));
+        }
+    }
+
+    #[test]
+    fn test_writes_update_distinct_lines_estimate() {
+        let construction_types = [
+            ConstructionType::BloomLinesAndSize, ConstructionType::XXHLimitAndSize, ConstructionType::ExactLimit64, ConstructionType::HybridLimitAndSize,
+            ConstructionType::CountMinLimitAndSize, ConstructionType::TimedLimitAndTtl,
+        ];
+        for (construction_type, shards) in construction_types.into_iter().flat_map(|construction_type| [(construction_type, 1), (construction_type, 4)]) {
+            let mut container = new_container(construction_type, shards);
+            // Values are written in all the ways, each of them twice.
+            for i in 0 .. 300 {
+                let value = (i % 150).to_string();
+                match i % 3 {
+                    0 => container.set(&value),
+                    1 => { container.check_and_set(&value); }
+                    _ => { container.count_and_set(&value); }
+                }
+            }
+            let values: Vec<String> = (150 .. 200).map(|i| i.to_string()).collect();
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            container.check_and_set_batch(&values, &mut [false; 50]);
+
+            let estimate = container.get_container_details().hll.estimate();
+            assert!((195 ..= 205).contains(&estimate), "{} distinct values estimated for {:?}", estimate, construction_type as u8);
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_values_of_both_containers() {
+        let construction_types = [ConstructionType::XXHLimitAndSize, ConstructionType::ExactLimit64];
+        for (construction_type, shards) in construction_types.into_iter().flat_map(|construction_type| [(construction_type, 1), (construction_type, 4)]) {
+            // Containers are nearly full, so keys of the merged one are often stored away from their home slots.
+            let mut merged = new_container(construction_type, shards);
+            let mut other = new_container(construction_type, shards);
+            for i in 0 .. 500 {
+                merged.set(&format!("a{}", i));
+                other.set(&format!("b{}", i));
+            }
+
+            merged.merge(&*other).unwrap();
+            assert!((0 .. 500).all(|i| merged.check(&format!("a{}", i)) && merged.check(&format!("b{}", i))));
+        }
+
+        // Bloom filters with random keys set different bits for the same value.
+        let mut merged = new_container(ConstructionType::BloomLinesAndSize, 1);
+        assert!(merged.merge(&*new_container(ConstructionType::BloomLinesAndSize, 1)).is_err());
+    }
+
+    #[test]
+    fn test_intersect_and_subtract_keep_matching_values() {
+        let construction_types = [ConstructionType::XXHLimitAndSize, ConstructionType::ExactLimit64];
+        for (construction_type, shards) in construction_types.into_iter().flat_map(|construction_type| [(construction_type, 1), (construction_type, 4)]) {
+            let mut intersection = new_container(construction_type, shards);
+            let mut difference = new_container(construction_type, shards);
+            let mut other = new_container(construction_type, shards);
+            for i in 0 .. 400 {
+                intersection.set(&i.to_string());
+                difference.set(&i.to_string());
+                other.set(&(i + 200).to_string());
+            }
+
+            intersection.intersect(&*other).unwrap();
+            difference.subtract(&*other).unwrap();
+            assert!((0 .. 200).all(|i| !intersection.check(&i.to_string()) && difference.check(&i.to_string())));
+            assert!((200 .. 400).all(|i| intersection.check(&i.to_string()) && !difference.check(&i.to_string())));
+            // Keys of xxHash filters whose home slot is unknown may be kept more than once.
+            assert!((200.0 .. 210.0).contains(&intersection.estimate_fill().unwrap().num_values));
+        }
+    }
+
+    #[test]
+    fn test_exact_containers_count_values_once() {
+        let construction_types = [ConstructionType::ExactLimit64, ConstructionType::ExactLimit128, ConstructionType::HybridLimitAndSize];
+        for (construction_type, shards) in construction_types.into_iter().flat_map(|construction_type| [(construction_type, 1), (construction_type, 4)]) {
+            let mut container = new_container(construction_type, shards);
+            for i in 0 .. 200 {
+                container.set(&(i % 100).to_string());
+            }
+            assert_eq!(container.get_num_writes(), 100, "Values counted more than once for {:?}", construction_type as u8);
+        }
+    }
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
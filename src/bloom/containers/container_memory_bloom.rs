@@ -1,44 +1,184 @@
+use std::collections::hash_map::RandomState;
 use std::fs::File;
-use std::io::{BufWriter, Write, Read};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use bloomfilter::Bloom;
-use crate::bloom::containers::container::Container;
+use siphasher::sip::SipHasher13;
+use crate::bloom::containers::container::{Container, FillEstimate, BATCH_SIZE, SHARED_NUM_WRITES_OFFSET};
+use crate::bloom::containers::lazy_memory::{FileMapping, LazyMemory, MapMode};
+use crate::bloom::sizing::{bloom_error_rate_from_fill, bloom_num_hashes, bloom_num_items_from_fill, bloom_size_for_error_rate};
 use crate::ContainerDetails;
 
 pub(crate) struct MemoryContainerBloom {
     container_details: ContainerDetails,
     num_writes: u64, // Number of written keys/values.
     max_writes: u64, // Maximum number of added keys/values.
-    filter: Bloom<String>, // Bloom filter module.
+    bits: LazyMemory<u8>, // Bits of the filter, most significant bit of each byte first.
+    num_bits: u64, // Total number of bits of the filter.
+    num_hashes: u32, // Number of bits set for each key/value.
+    sips: [SipHasher13; 2], // Keyed hashers. Other hashes are derived from these two.
+}
+
+/// Largest prime lower than 2^64. Derived hashes are taken modulo this prime.
+const HASH_PRIME: u64 = 0xFFFF_FFFF_FFFF_FFC5;
+
+/// Creates keyed hashers with random keys.
+fn random_sips() -> [SipHasher13; 2] {
+    // RandomState is randomly seeded, so hashing anything with it gives random keys.
+    let state = RandomState::new();
+    let key = |idx: u64| state.hash_one(idx);
+    [SipHasher13::new_with_keys(key(0), key(1)), SipHasher13::new_with_keys(key(2), key(3))]
+}
+
+/// Calculates index of the bit for the k-th hash of the value. Hashes are calculated the same way as in the bloomfilter
+/// crate, so files created with it stay compatible.
+fn calc_bit_index(container: &MemoryContainerBloom, hashes: &mut [u64; 2], value: &str, k: u32) -> u64 {
+    if k < 2 {
+        hashes[k as usize] = calc_sip_hash(container, value, k);
+    }
+    bit_index(container, hashes, k)
+}
+
+/// Calculates the k-th of the two sip hashes of the value.
+fn calc_sip_hash(container: &MemoryContainerBloom, value: &str, k: u32) -> u64 {
+    let mut sip = container.sips[k as usize];
+    value.hash(&mut sip);
+    sip.finish()
+}
+
+/// Returns index of the bit for the k-th hash from already calculated sip hashes.
+fn bit_index(container: &MemoryContainerBloom, hashes: &[u64; 2], k: u32) -> u64 {
+    let hash = if k < 2 {
+        hashes[k as usize]
+    } else {
+        hashes[0].wrapping_add((k as u64).wrapping_mul(hashes[1])) % HASH_PRIME
+    };
+    hash % container.num_bits
+}
+
+/// Calculates sip hashes of the values and prefetches bytes of all their bits.
+fn prefetch_values(container: &MemoryContainerBloom, values: &[&str], hashes: &mut [[u64; 2]; BATCH_SIZE]) {
+    for (value, hashes) in values.iter().zip(hashes.iter_mut()) {
+        *hashes = [calc_sip_hash(container, value, 0), calc_sip_hash(container, value, 1)];
+        for k in 0 .. container.num_hashes {
+            container.bits.prefetch((bit_index(container, hashes, k) / 8) as usize);
+        }
+    }
+}
+
+/// Returns bit of given index.
+fn get_bit(container: &MemoryContainerBloom, bit_idx: u64) -> bool {
+    container.bits.load_byte((bit_idx / 8) as usize) & (0x80 >> (bit_idx % 8)) != 0
+}
+
+/// Sets bit of given index. Returns whether bit was already set. In concurrent mode, other processes may set bits of
+/// the same byte, so bit is set atomically.
+fn set_bit(container: &mut MemoryContainerBloom, bit_idx: u64) -> bool {
+    if get_bit(container, bit_idx) {
+        // Bytes are not written when nothing changes, so pages stay clean.
+        return true;
+    }
+
+    let byte_idx = (bit_idx / 8) as usize;
+    let mask = 0x80 >> (bit_idx % 8);
+    if shared_num_writes(container).is_some() {
+        return container.bits.fetch_or_byte(byte_idx, mask) & mask != 0;
+    }
+    container.bits[byte_idx] |= mask;
+    false
+}
+
+/// Returns number of writes shared with other processes writing into the same file at the same time.
+fn shared_num_writes(container: &MemoryContainerBloom) -> Option<&AtomicU64> {
+    container.bits.mapped_file()
+        .filter(|mapping| mapping.is_concurrent())
+        .map(|mapping| mapping.header_u64(SHARED_NUM_WRITES_OFFSET))
+}
+
+/// Counts a write into the container.
+fn add_write(container: &mut MemoryContainerBloom) {
+    match shared_num_writes(container) {
+        Some(num_writes) => { num_writes.fetch_add(1, Ordering::Relaxed); }
+        None => container.num_writes += 1,
+    }
 }
 
 impl Container for MemoryContainerBloom {
     /// Inserts value into the filter.
-    fn set(&mut self, value: &String) {
-        self.filter.set(value);
-        self.num_writes += 1;
+    fn set(&mut self, value: &str) {
+        self.container_details.hll.insert(value);
+        let mut hashes = [0, 0];
+        for k in 0 .. self.num_hashes {
+            let bit_idx = calc_bit_index(self, &mut hashes, value, k);
+            set_bit(self, bit_idx);
+        }
+        add_write(self);
     }
 
     /// Checks whether filter could have given value.
-    fn check(&self, value: &String) -> bool {
-        return self.filter.check(value);
+    fn check(&self, value: &str) -> bool {
+        let mut hashes = [0, 0];
+        (0 .. self.num_hashes).all(|k| get_bit(self, calc_bit_index(self, &mut hashes, value, k)))
     }
 
     /// Checks whether filter could have given value and if no, inserts the value. Returns true if value could have
     /// existed.
-    fn check_and_set(&mut self, value: &String) -> bool {
-        let had_value = self.filter.check_and_set(value);
+    fn check_and_set(&mut self, value: &str) -> bool {
+        self.container_details.hll.insert(value);
+        let mut hashes = [0, 0];
+        let mut had_value = true;
+        for k in 0 .. self.num_hashes {
+            let bit_idx = calc_bit_index(self, &mut hashes, value, k);
+            if !set_bit(self, bit_idx) {
+                had_value = false;
+            }
+        }
 
         if !had_value {
-            self.num_writes += 1;
+            add_write(self);
         }
 
         had_value
     }
 
+    /// Checks whether filter could have given values. Bits of the values are prefetched first.
+    fn check_batch(&self, values: &[&str], found: &mut [bool]) {
+        let mut hashes = [[0, 0]; BATCH_SIZE];
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut hashes);
+            for (found, hashes) in found.iter_mut().zip(hashes.iter()) {
+                *found = (0 .. self.num_hashes).all(|k| get_bit(self, bit_index(self, hashes, k)));
+            }
+        }
+    }
+
+    /// Checks and inserts the values in order. Bits of the values are prefetched first.
+    fn check_and_set_batch(&mut self, values: &[&str], found: &mut [bool]) {
+        let mut hashes = [[0, 0]; BATCH_SIZE];
+        for value in values {
+            self.container_details.hll.insert(value);
+        }
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut hashes);
+            for (found, hashes) in found.iter_mut().zip(hashes.iter()) {
+                *found = true;
+                for k in 0 .. self.num_hashes {
+                    let bit_idx = bit_index(self, hashes, k);
+                    if !set_bit(self, bit_idx) {
+                        *found = false;
+                    }
+                }
+                if !*found {
+                    add_write(self);
+                }
+            }
+        }
+    }
+
     /// Checks whether container is full, and we should not insert new values.
     fn is_full(&self) -> bool {
-        return self.num_writes >= self.max_writes;
+        self.get_num_writes() >= self.max_writes
     }
 
     /// Returns construction info used to create this container.
@@ -48,12 +188,12 @@ impl Container for MemoryContainerBloom {
 
     /// Returns container fill percentage.
     fn get_usage(&self) -> f32 {
-        100.0f32 / self.filter.bit_vec().len() as f32 * self.num_writes as f32
+        100.0f32 / self.num_bits as f32 * self.get_num_writes() as f32
     }
 
     /// Returns number of writes into the container.
     fn get_num_writes(&self) -> u64 {
-        self.num_writes as u64
+        shared_num_writes(self).map_or(self.num_writes, |num_writes| num_writes.load(Ordering::Relaxed))
     }
 
     /// Sets number of writes into the container (initialized when container file is opened).
@@ -63,7 +203,7 @@ impl Container for MemoryContainerBloom {
 
     /// Returns maximum number of allowed writes into the container.
     fn get_num_max_writes(&self) -> u64 {
-        self.max_writes as u64
+        self.max_writes
     }
 
     /// Sets maximum number of allowed writes into the container (initialized when container file is opened).
@@ -73,64 +213,146 @@ impl Container for MemoryContainerBloom {
 
     /// Saves filter data content to the given, already opened for write file.
     fn save_content(&mut self, file: &mut File) {
-        let mut buf_writer = BufWriter::with_capacity(10000000, file);
+        let mut buf_writer = BufWriter::new(&mut *file);
 
         // Writing sip keys.
-        let sip_keys = self.filter.sip_keys();
-        let (sip_keys_0_0, sip_keys_0_1) = &sip_keys.get(0).unwrap();
-        let (sip_keys_1_0, sip_keys_1_1) = &sip_keys.get(1).unwrap();
-        buf_writer.write_u64::<LittleEndian>(*sip_keys_0_0).unwrap();
-        buf_writer.write_u64::<LittleEndian>(*sip_keys_0_1).unwrap();
-        buf_writer.write_u64::<LittleEndian>(*sip_keys_1_0).unwrap();
-        buf_writer.write_u64::<LittleEndian>(*sip_keys_1_1).unwrap();
+        for sip in self.sips.iter() {
+            let (key_0, key_1) = sip.keys();
+            buf_writer.write_u64::<LittleEndian>(key_0).unwrap();
+            buf_writer.write_u64::<LittleEndian>(key_1).unwrap();
+        }
+        buf_writer.flush().unwrap();
+        drop(buf_writer);
 
-        // Writing bit vec.
-        buf_writer.write_all(&self.filter.bit_vec().to_bytes()).unwrap();
+        // Writing bits.
+        self.bits.save(file);
     }
 
     /// Loads filter data content from the given, already opened file.
     fn load_content(&mut self, file: &mut File) {
-        let construction_details = self.get_container_details();
+        self.sips = read_sips(file);
+        self.bits.load(file);
+    }
+
+    /// Maps bits of the given file instead of loading them.
+    fn map_content(&mut self, file: &mut File, path: &str, offset: u64, mode: MapMode) -> bool {
+        // Bits follow the sip keys.
+        if !self.bits.map(file, path, offset, 32, mode) {
+            return false;
+        }
+        self.sips = read_sips(file);
+        true
+    }
+
+    /// Returns mapping of the file if bits are modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        self.bits.mapped_file()
+    }
+
+    /// Writes changes of the bits modified in place to the given, already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.bits.sync(file);
+    }
+
+    /// Adds bits of the other filter into this one. Filters must have the same size, number of hash functions and
+    /// keys, so the same value sets the same bits in both of them. Number of writes is summed, so values present in
+    /// both filters are counted twice.
+    fn merge(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerBloom>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+
+        for idx in 0 .. self.bits.len() {
+            let byte = other.bits.load_byte(idx);
+            // Bytes are not written when nothing changes, so pages stay clean.
+            if self.bits.load_byte(idx) | byte != self.bits.load_byte(idx) {
+                self.bits.fetch_or_byte(idx, byte);
+            }
+        }
+        self.num_writes += other.get_num_writes();
+
+        Ok(())
+    }
 
-        // Reading sip keys.
-        let sip_keys_0_0 = file.read_u64::<LittleEndian>().unwrap();
-        let sip_keys_0_1 = file.read_u64::<LittleEndian>().unwrap();
-        let sip_keys_1_0 = file.read_u64::<LittleEndian>().unwrap();
-        let sip_keys_1_1 = file.read_u64::<LittleEndian>().unwrap();
+    /// Keeps only bits set in both filters. Filters must be compatible as when merging. Bits set by different values
+    /// of the two filters are kept too, so the result may have more false positives than filter of the intersection
+    /// would. Number of writes is estimated from the bits left.
+    fn intersect(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerBloom>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
 
-        let sip_keys: [(u64, u64); 2] = [(sip_keys_0_0, sip_keys_0_1), (sip_keys_1_0,sip_keys_1_1)];
+        for idx in 0 .. self.bits.len() {
+            let byte = other.bits.load_byte(idx);
+            if self.bits.load_byte(idx) & byte != self.bits.load_byte(idx) {
+                self.bits.fetch_and_byte(idx, byte);
+            }
+        }
+        self.num_writes = self.estimate_fill().unwrap().num_values.round() as u64;
+
+        Ok(())
+    }
+
+    /// Bits are shared by many values, so clearing bits of the other filter would remove values which should stay.
+    fn subtract(&mut self, _other: &dyn Container) -> Result<(), String> {
+        Err("Bloom filters can't be subtracted, as bits of the subtracted lines are set by the remaining lines too (use xxHash filters or exact sets)".to_string())
+    }
 
-        // Reading bit vec.
-        let mut bytes = Vec::new();
-        bytes.reserve_exact(construction_details.construction_details.size as usize);
-        file.read_to_end(&mut bytes).unwrap();
+    /// Estimates number of values from the number of set bits.
+    fn estimate_fill(&self) -> Option<FillEstimate> {
+        let num_set_bits: u64 = (0 .. self.bits.len()).map(|idx| self.bits.load_byte(idx).count_ones() as u64).sum();
+        Some(FillEstimate {
+            num_values: bloom_num_items_from_fill(self.num_bits, num_set_bits, self.num_hashes as u64),
+            error_rate: bloom_error_rate_from_fill(self.num_bits, num_set_bits, self.num_hashes as u64),
+        })
+    }
+}
 
-        self.filter = Bloom::from_existing(
-            &bytes,
-            construction_details.construction_details.size as u64 * 8,
-            construction_details.construction_details.limit as u32,
-            sip_keys);
+/// Checks whether the same value sets the same bits in both filters. Returns reason why it doesn't.
+pub(crate) fn check_compatible(container: &MemoryContainerBloom, other: &MemoryContainerBloom) -> Result<(), String> {
+    if container.num_bits != other.num_bits {
+        return Err(format!("Bloom filters have different sizes ({} and {} bytes)", container.num_bits / 8, other.num_bits / 8));
+    }
+    if container.num_hashes != other.num_hashes {
+        return Err(format!("Bloom filters use different number of hash functions ({} and {}), as their limits differ ({} and {})",
+            container.num_hashes, other.num_hashes, container.max_writes, other.max_writes));
     }
+    if container.sips.iter().zip(other.sips.iter()).any(|(sip, other_sip)| sip.keys() != other_sip.keys()) {
+        return Err("Bloom filters use different hash keys, so the same line sets different bits in them (filters have to be copies of the same empty filter file)".to_string());
+    }
+    Ok(())
+}
+
+/// Reads sip keys from the given, already opened file and creates keyed hashers from them.
+fn read_sips(file: &mut File) -> [SipHasher13; 2] {
+    let mut read_sip = || {
+        let key_0 = file.read_u64::<LittleEndian>().unwrap();
+        let key_1 = file.read_u64::<LittleEndian>().unwrap();
+        SipHasher13::new_with_keys(key_0, key_1)
+    };
+    [read_sip(), read_sip()]
 }
 
 impl MemoryContainerBloom {
-    /// Creates instance of bloom filter from given container details.
-    pub(crate) fn new_limit_and_error_rate(container_details: ContainerDetails) -> Self {
-        Self {
-            num_writes: 0,
-            max_writes: container_details.construction_details.limit,
-            filter: Bloom::new_for_fp_rate(container_details.construction_details.limit as usize, container_details.construction_details.error_rate),
-            container_details,
+    /// Creates instance of bloom filter from given container details. Size of the filter is derived from the limit and
+    /// error rate and stored back in the container details.
+    pub(crate) fn new_limit_and_error_rate(mut container_details: ContainerDetails) -> Self {
+        container_details.construction_details.size = bloom_size_for_error_rate(
+            container_details.construction_details.limit,
+            container_details.construction_details.error_rate);
 
-        }
+        Self::new_limit_and_size(container_details)
     }
 
     /// Creates instance of bloom filter from given container details.
     pub(crate) fn new_limit_and_size(container_details: ContainerDetails) -> Self {
+        let size = container_details.construction_details.size;
+        let limit = container_details.construction_details.limit;
         Self {
             num_writes: 0,
-            max_writes: container_details.construction_details.limit,
-            filter: Bloom::new(container_details.construction_details.size as usize, container_details.construction_details.limit as usize),
+            max_writes: limit,
+            bits: LazyMemory::new(size as usize),
+            num_bits: size * 8,
+            num_hashes: bloom_num_hashes(size, limit) as u32,
+            sips: random_sips(),
             container_details,
         }
     }
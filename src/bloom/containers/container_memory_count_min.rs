@@ -0,0 +1,293 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use xxhash_rust::xxh3::xxh3_128;
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::lazy_memory::{FileMapping, LazyMemory, MapMode};
+use crate::ContainerDetails;
+
+pub(crate) struct MemoryContainerCountMin {
+    container_details: ContainerDetails,
+    num_writes: u64, // Number of distinct keys/values (those which had zero estimated count when inserted).
+    max_writes: u64, // Maximum number of distinct keys/values.
+    counters: LazyMemory<u32>, // Rows of counters, one after another. Counters saturate at u32::MAX.
+    width: u64, // Number of counters in each row.
+    top_size: usize, // Number of the most frequent keys/values to track (0 if not tracked).
+    top: HashMap<String, u64>, // Tracked most frequent keys/values with their estimated counts.
+    top_min_count: u64, // Lowest count among tracked keys/values once the tracker is full.
+}
+
+/// Number of rows (hash functions). Each row lowers probability of overestimation.
+pub(crate) const DEPTH: u64 = 4;
+
+/// Calculates indices of the counters (one in each row) for the given value.
+fn calc_indices(container: &MemoryContainerCountMin, value: &str) -> [usize; DEPTH as usize] {
+    // Double hashing: both halves of the 128-bit hash are combined to get independent enough indices.
+    let hash = xxh3_128(value.as_bytes());
+    let h1 = hash as u64;
+    let h2 = (hash >> 64) as u64 | 1;
+
+    let mut indices = [0; DEPTH as usize];
+    for (row, index) in indices.iter_mut().enumerate() {
+        *index = (row as u64 * container.width + h1.wrapping_add((row as u64).wrapping_mul(h2)) % container.width) as usize;
+    }
+    indices
+}
+
+/// Returns estimated count of the value stored in the given counters.
+fn estimate(container: &MemoryContainerCountMin, indices: &[usize]) -> u64 {
+    indices.iter().map(|index| container.counters[*index]).min().unwrap() as u64
+}
+
+/// Increments counters of the value using conservative update (only the smallest counters are incremented, which
+/// lowers overestimation). Returns estimated count before the increment.
+fn increment(container: &mut MemoryContainerCountMin, value: &str) -> u64 {
+    container.container_details.hll.insert(value);
+    let indices = calc_indices(container, value);
+    let count = estimate(container, &indices);
+
+    for index in indices {
+        if container.counters[index] as u64 == count {
+            container.counters[index] = container.counters[index].saturating_add(1);
+        }
+    }
+
+    if count == 0 {
+        container.num_writes += 1;
+    }
+
+    if container.top_size > 0 {
+        track(container, value, count + 1);
+    }
+
+    count
+}
+
+/// Updates tracked most frequent values with the new estimated count of the value.
+fn track(container: &mut MemoryContainerCountMin, value: &str, count: u64) {
+    if let Some(tracked_count) = container.top.get_mut(value) {
+        let was_least_frequent = *tracked_count == container.top_min_count;
+        *tracked_count = count;
+        if was_least_frequent && container.top.len() == container.top_size {
+            container.top_min_count = container.top.values().copied().min().unwrap();
+        }
+        return;
+    }
+
+    if container.top.len() < container.top_size {
+        container.top.insert(value.to_string(), count);
+        if container.top.len() == container.top_size {
+            container.top_min_count = container.top.values().copied().min().unwrap();
+        }
+        return;
+    }
+
+    if count <= container.top_min_count {
+        // Most of the values end here, so tracker costs almost nothing for infrequent values.
+        return;
+    }
+
+    // Replacing the least frequent tracked value.
+    let least_frequent = container.top.iter().min_by_key(|(_, tracked_count)| **tracked_count).map(|(tracked, _)| tracked.clone()).unwrap();
+    container.top.remove(&least_frequent);
+    container.top.insert(value.to_string(), count);
+    container.top_min_count = container.top.values().copied().min().unwrap();
+}
+
+/// Removes the least frequent tracked values, so at most top_size of them are left.
+fn trim_top(container: &mut MemoryContainerCountMin) {
+    if container.top.len() <= container.top_size {
+        return;
+    }
+
+    let mut top: Vec<(String, u64)> = container.top.drain().collect();
+    top.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    top.truncate(container.top_size);
+    container.top = top.into_iter().collect();
+    container.top_min_count = container.top.values().copied().min().unwrap_or(0);
+}
+
+impl Container for MemoryContainerCountMin {
+    /// Inserts value into the sketch.
+    fn set(&mut self, value: &str) {
+        increment(self, value);
+    }
+
+    /// Checks whether sketch could have given value.
+    fn check(&self, value: &str) -> bool {
+        self.count(value) > 0
+    }
+
+    /// Checks whether sketch could have given value and increments its count. Returns true if value could have
+    /// existed.
+    fn check_and_set(&mut self, value: &str) -> bool {
+        increment(self, value) > 0
+    }
+
+    /// Returns estimated number of occurrences of the value. Estimate is never lower than the real count.
+    fn count(&self, value: &str) -> u64 {
+        estimate(self, &calc_indices(self, value))
+    }
+
+    /// Increments number of occurrences of the value. Returns estimated count before the increment.
+    fn count_and_set(&mut self, value: &str) -> u64 {
+        increment(self, value)
+    }
+
+    /// Sets number of the most frequent values to track.
+    fn set_top_size(&mut self, size: usize) {
+        self.top_size = size;
+        trim_top(self);
+    }
+
+    /// Returns tracked most frequent values with their estimated counts.
+    fn get_top(&self) -> Vec<(String, u64)> {
+        self.top.iter().map(|(value, count)| (value.clone(), *count)).collect()
+    }
+
+    /// Checks whether container is full, and we should not insert new values.
+    fn is_full(&self) -> bool {
+        self.num_writes >= self.max_writes
+    }
+
+    /// Returns construction info used to create this container.
+    fn get_container_details(&mut self) -> &mut ContainerDetails {
+        &mut self.container_details
+    }
+
+    /// Returns container fill percentage (percentage of non-zero counters).
+    fn get_usage(&self) -> f32 {
+        let num_used = self.counters.iter().filter(|counter| **counter != 0).count();
+        100.0f32 / self.counters.len() as f32 * num_used as f32
+    }
+
+    /// Returns number of writes into the container.
+    fn get_num_writes(&self) -> u64 {
+        self.num_writes
+    }
+
+    /// Sets number of writes into the container (initialized when container file is opened).
+    fn set_num_writes(&mut self, value: u64) {
+        self.num_writes = value;
+    }
+
+    /// Returns maximum number of allowed writes into the container.
+    fn get_num_max_writes(&self) -> u64 {
+        self.max_writes
+    }
+
+    /// Sets maximum number of allowed writes into the container (initialized when container file is opened).
+    fn set_num_max_writes(&mut self, value: u64) {
+        self.max_writes = value;
+    }
+
+    /// Saves sketch counters followed by the tracked most frequent values to the given, already opened for write file.
+    fn save_content(&mut self, file: &mut File) {
+        self.counters.save(file);
+        save_top(self, file);
+    }
+
+    /// Loads sketch counters followed by the tracked most frequent values from the given, already opened file.
+    fn load_content(&mut self, file: &mut File) {
+        let mut buf_reader = BufReader::with_capacity(10000000, file);
+        self.counters.load(&mut buf_reader);
+        load_top(self, &mut buf_reader);
+    }
+
+    /// Maps sketch counters of the given file instead of loading them. Tracked most frequent values are still loaded.
+    fn map_content(&mut self, file: &mut File, path: &str, offset: u64, mode: MapMode) -> bool {
+        if !self.counters.map(file, path, offset, 0, mode) {
+            return false;
+        }
+        file.seek(SeekFrom::Start(offset + self.counters.len() as u64 * 4)).unwrap();
+        load_top(self, &mut BufReader::with_capacity(10000000, file));
+        true
+    }
+
+    /// Returns mapping of the file if counters are modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        self.counters.mapped_file()
+    }
+
+    /// Writes changes of the counters modified in place followed by the tracked most frequent values to the given,
+    /// already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.counters.sync(file);
+        save_top(self, file);
+    }
+
+    /// Adds counters of the other sketch into this one. Sketches must have the same width, so the same value maps to
+    /// the same counters. Tracked most frequent values of both sketches are re-estimated from the summed counters.
+    /// Number of distinct values is summed, so values present in both sketches are counted twice.
+    fn merge(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerCountMin>().ok_or("containers have different types")?;
+        if self.width != other.width {
+            return Err(format!("count-min sketches have different widths ({} and {} counters)", self.width, other.width));
+        }
+
+        for idx in 0 .. self.counters.len() {
+            if other.counters[idx] != 0 {
+                self.counters[idx] = self.counters[idx].saturating_add(other.counters[idx]);
+            }
+        }
+        self.num_writes += other.num_writes;
+
+        let values: Vec<String> = self.top.keys().chain(other.top.keys()).cloned().collect();
+        for value in values {
+            let count = estimate(self, &calc_indices(self, &value));
+            self.top.insert(value, count);
+        }
+        trim_top(self);
+        self.top_min_count = self.top.values().copied().min().unwrap_or(0);
+
+        Ok(())
+    }
+}
+
+/// Saves tracked most frequent values at the current position of the given, already opened for write file.
+fn save_top(container: &MemoryContainerCountMin, file: &mut File) {
+    let mut buf_writer = BufWriter::with_capacity(10000000, file);
+    buf_writer.write_u64::<LittleEndian>(container.top_size as u64).unwrap();
+    buf_writer.write_u64::<LittleEndian>(container.top.len() as u64).unwrap();
+    for (value, count) in container.top.iter() {
+        buf_writer.write_u64::<LittleEndian>(*count).unwrap();
+        buf_writer.write_u64::<LittleEndian>(value.len() as u64).unwrap();
+        buf_writer.write_all(value.as_bytes()).unwrap();
+    }
+}
+
+/// Loads tracked most frequent values saved by save_top().
+fn load_top(container: &mut MemoryContainerCountMin, reader: &mut impl Read) {
+    // Tracker may be missing in files saved without it, so it's just left empty.
+    container.top_size = reader.read_u64::<LittleEndian>().unwrap_or(0) as usize;
+    let num_top = reader.read_u64::<LittleEndian>().unwrap_or(0);
+    for _ in 0 .. num_top {
+        let count = reader.read_u64::<LittleEndian>().unwrap();
+        let mut value = vec![0; reader.read_u64::<LittleEndian>().unwrap() as usize];
+        reader.read_exact(&mut value).unwrap();
+        container.top.insert(String::from_utf8(value).unwrap(), count);
+    }
+    container.top_min_count = container.top.values().copied().min().unwrap_or(0);
+}
+
+impl MemoryContainerCountMin {
+    /// Creates instance of count-min sketch from given container details. Size (in bytes) is split between DEPTH rows
+    /// of 32-bit counters and the actually used size is stored back in the container details.
+    pub(crate) fn new_limit_and_size(mut container_details: ContainerDetails) -> Self {
+        let width = max(16, container_details.construction_details.size / 4 / DEPTH);
+        container_details.construction_details.size = width * DEPTH * 4;
+
+        Self {
+            num_writes: 0,
+            max_writes: container_details.construction_details.limit,
+            counters: LazyMemory::new((width * DEPTH) as usize),
+            width,
+            top_size: 0,
+            top: HashMap::new(),
+            top_min_count: 0,
+            container_details,
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::cmp::max;
+use std::fs::File;
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+use crate::bloom::containers::container::{Container, FillEstimate, BATCH_SIZE};
+use crate::bloom::containers::lazy_memory::{FileMapping, LazyMemory, MapMode};
+use crate::bloom::sizing::exact_error_rate;
+use crate::{ConstructionType, ContainerDetails};
+
+pub(crate) struct MemoryContainerExact {
+    container_details: ContainerDetails,
+    num_writes: u64, // Number of written keys/values.
+    max_writes: u64, // Maximum number of added keys/values.
+    slots: LazyMemory<u64>, // Open-addressing table of digests. All-zero slot means the slot is free.
+    words_per_slot: u64, // Number of u64 words used by each digest (1 for 64-bit and 2 for 128-bit digests).
+    num_slots: u64, // Total number of slots in the table (always a power of two).
+}
+
+/// Calculates number of table slots for the given limit, so the table is never more than 3/4 full. Exits if the table of
+/// slots with given number of 64-bit words would be too big to be allocated.
+fn calc_num_slots(limit: u64, words_per_slot: u64) -> u64 {
+    limit.checked_mul(4)
+        .and_then(|num_slots| (num_slots / 3 + 1).checked_next_power_of_two())
+        .filter(|num_slots| num_slots.checked_mul(words_per_slot * 8).is_some_and(|size| size <= isize::MAX as u64))
+        .map(|num_slots| max(16, num_slots))
+        .unwrap_or_else(|| {
+            eprintln!("Error: Exact container with limit {} is too big.", limit);
+            std::process::exit(1);
+        })
+}
+
+/// Calculates digest of the given value. Returns (low, high) words; high word is always zero for 64-bit digests.
+fn calc_digest(container: &MemoryContainerExact, value: &str) -> (u64, u64) {
+    let (mut low, high) = if container.words_per_slot == 1 {
+        (xxh3_64(value.as_bytes()), 0)
+    } else {
+        let hash = xxh3_128(value.as_bytes());
+        (hash as u64, (hash >> 64) as u64)
+    };
+
+    // All-zero slot marks a free slot, so we can't store all-zero digest as is.
+    if low == 0 && high == 0 {
+        low = 1;
+    }
+
+    (low, high)
+}
+
+/// Reads digest stored in the given slot.
+fn read_slot(container: &MemoryContainerExact, slot_idx: u64) -> (u64, u64) {
+    let word_idx = (slot_idx * container.words_per_slot) as usize;
+    if container.words_per_slot == 1 {
+        (container.slots[word_idx], 0)
+    } else {
+        (container.slots[word_idx], container.slots[word_idx + 1])
+    }
+}
+
+/// Writes digest into the given slot.
+fn write_slot(container: &mut MemoryContainerExact, slot_idx: u64, digest: (u64, u64)) {
+    let word_idx = (slot_idx * container.words_per_slot) as usize;
+    container.slots[word_idx] = digest.0;
+    if container.words_per_slot == 2 {
+        container.slots[word_idx + 1] = digest.1;
+    }
+}
+
+/// Linearly probes the table for the given digest. Returns whether digest was found and index of the slot where it was
+/// found or where it could be inserted.
+fn probe(container: &MemoryContainerExact, digest: (u64, u64)) -> (bool, u64) {
+    let mask = container.num_slots - 1;
+    // Low word is a uniformly distributed hash, so it's used as the home slot.
+    let mut slot_idx = digest.0 & mask;
+
+    for _ in 0 .. container.num_slots {
+        let slot = read_slot(container, slot_idx);
+        if slot == (0, 0) {
+            // Free slot, so digest is not in the table.
+            return (false, slot_idx);
+        }
+        if slot == digest {
+            return (true, slot_idx);
+        }
+        slot_idx = (slot_idx + 1) & mask;
+    }
+
+    // Table is completely full. Can't happen as long as limit is respected, but we won't loop forever.
+    (false, container.num_slots)
+}
+
+/// Inserts digest into the table. Returns true if digest was already there.
+fn insert_digest(container: &mut MemoryContainerExact, digest: (u64, u64)) -> bool {
+    let (found, slot_idx) = probe(container, digest);
+
+    if found {
+        return true;
+    }
+
+    if slot_idx < container.num_slots {
+        write_slot(container, slot_idx, digest);
+        container.num_writes += 1;
+    }
+
+    false
+}
+
+/// Calculates digests of the values and prefetches their home slots.
+fn prefetch_values(container: &MemoryContainerExact, values: &[&str], digests: &mut [(u64, u64); BATCH_SIZE]) {
+    for (value, digest) in values.iter().zip(digests.iter_mut()) {
+        *digest = calc_digest(container, value);
+        let slot_idx = digest.0 & (container.num_slots - 1);
+        container.slots.prefetch((slot_idx * container.words_per_slot) as usize);
+    }
+}
+
+impl Container for MemoryContainerExact {
+    /// Inserts value into the filter.
+    fn set(&mut self, value: &str) {
+        self.container_details.hll.insert(value);
+        let digest = calc_digest(self, value);
+        insert_digest(self, digest);
+    }
+
+    /// Checks whether filter has given value.
+    fn check(&self, value: &str) -> bool {
+        let digest = calc_digest(self, value);
+        probe(self, digest).0
+    }
+
+    /// Checks whether filter has given value and if no, inserts the value. Returns true if value existed.
+    fn check_and_set(&mut self, value: &str) -> bool {
+        self.container_details.hll.insert(value);
+        let digest = calc_digest(self, value);
+        insert_digest(self, digest)
+    }
+
+    /// Checks whether filter has given values. Home slots of the values are prefetched first.
+    fn check_batch(&self, values: &[&str], found: &mut [bool]) {
+        let mut digests = [(0, 0); BATCH_SIZE];
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut digests);
+            for (found, digest) in found.iter_mut().zip(digests) {
+                *found = probe(self, digest).0;
+            }
+        }
+    }
+
+    /// Checks and inserts the values in order. Home slots of the values are prefetched first.
+    fn check_and_set_batch(&mut self, values: &[&str], found: &mut [bool]) {
+        let mut digests = [(0, 0); BATCH_SIZE];
+        for value in values {
+            self.container_details.hll.insert(value);
+        }
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut digests);
+            for (found, digest) in found.iter_mut().zip(digests) {
+                *found = insert_digest(self, digest);
+            }
+        }
+    }
+
+    /// Checks whether container is full, and we should not insert new values.
+    fn is_full(&self) -> bool {
+        self.num_writes >= self.max_writes
+    }
+
+    /// Returns construction info used to create this container.
+    fn get_container_details(&mut self) -> &mut ContainerDetails {
+        &mut self.container_details
+    }
+
+    /// Returns container fill percentage.
+    fn get_usage(&self) -> f32 {
+        100.0f32 / self.num_slots as f32 * self.num_writes as f32
+    }
+
+    /// Returns number of writes into the container.
+    fn get_num_writes(&self) -> u64 {
+        self.num_writes
+    }
+
+    /// Sets number of writes into the container (initialized when container file is opened).
+    fn set_num_writes(&mut self, value: u64) {
+        self.num_writes = value;
+    }
+
+    /// Returns maximum number of allowed writes into the container.
+    fn get_num_max_writes(&self) -> u64 {
+        self.max_writes
+    }
+
+    /// Sets maximum number of allowed writes into the container (initialized when container file is opened).
+    fn set_num_max_writes(&mut self, value: u64) {
+        self.max_writes = value;
+    }
+
+    /// Saves filter data content to the given, already opened for write file.
+    fn save_content(&mut self, file: &mut File) {
+        self.slots.save(file);
+    }
+
+    /// Loads filter data content from the given, already opened file.
+    fn load_content(&mut self, file: &mut File) {
+        self.slots.load(file);
+    }
+
+    /// Maps filter data content of the given file instead of loading it.
+    fn map_content(&mut self, file: &mut File, path: &str, offset: u64, mode: MapMode) -> bool {
+        self.slots.map(file, path, offset, 0, mode)
+    }
+
+    /// Returns mapping of the file if content is modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        self.slots.mapped_file()
+    }
+
+    /// Writes changes of the filter data content modified in place to the given, already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.slots.sync(file);
+    }
+
+    /// Adds digests of the other set into this one. Digests already present aren't added again, so number of writes
+    /// stays exact. Sets may have different sizes, but digests must have the same width.
+    fn merge(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerExact>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+
+        for digest in other.digests() {
+            let (found, slot_idx) = probe(self, digest);
+            if !found && slot_idx == self.num_slots {
+                return Err(format!("exact set is too small to hold values of both sets (it has only {} slots)", self.num_slots));
+            }
+            insert_digest(self, digest);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps only digests which the other set has too.
+    fn intersect(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerExact>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+        retain_digests(self, other, true);
+        Ok(())
+    }
+
+    /// Keeps only digests which the other set doesn't have.
+    fn subtract(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerExact>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+        retain_digests(self, other, false);
+        Ok(())
+    }
+
+    /// Number of values is known exactly, false positive rate follows from the number of stored digests.
+    fn estimate_fill(&self) -> Option<FillEstimate> {
+        Some(FillEstimate {
+            num_values: self.num_writes as f64,
+            error_rate: exact_error_rate(self.num_writes, (self.words_per_slot * 64) as i32),
+        })
+    }
+}
+
+/// Checks whether the same value has the same digest in both sets. Returns reason why it doesn't.
+fn check_compatible(container: &MemoryContainerExact, other: &MemoryContainerExact) -> Result<(), String> {
+    if container.words_per_slot != other.words_per_slot {
+        return Err(format!("exact sets store digests of different width ({} and {} bits)",
+            container.words_per_slot * 64, other.words_per_slot * 64));
+    }
+    Ok(())
+}
+
+/// Keeps only digests which are (or aren't) found in the other set. Table is rebuilt, as digests can't be removed from
+/// the linearly probed table in place.
+fn retain_digests(container: &mut MemoryContainerExact, other: &MemoryContainerExact, found_in_other: bool) {
+    let digests: Vec<(u64, u64)> = container.digests().filter(|digest| probe(other, *digest).0 == found_in_other).collect();
+
+    container.slots.clear();
+    container.num_writes = 0;
+    for digest in digests {
+        insert_digest(container, digest);
+    }
+}
+
+impl MemoryContainerExact {
+    /// Creates instance of exact digest set from given container details. Size of the table is derived from the limit
+    /// and stored back in the container details.
+    pub(crate) fn new_limit(mut container_details: ContainerDetails) -> Self {
+        let words_per_slot: u64 = match container_details.construction_details.construction_type {
+            ConstructionType::ExactLimit128 => 2,
+            _ => 1,
+        };
+        let num_slots = calc_num_slots(container_details.construction_details.limit, words_per_slot);
+        container_details.construction_details.size = num_slots * words_per_slot * 8;
+
+        Self {
+            num_writes: 0,
+            max_writes: container_details.construction_details.limit,
+            slots: LazyMemory::new((num_slots * words_per_slot) as usize),
+            words_per_slot,
+            num_slots,
+            container_details,
+        }
+    }
+
+    /// Calculates size in bytes of the table holding given number of digests of given number of 64-bit words. Exits if
+    /// the table would be too big.
+    pub(crate) fn calc_size_for_limit(limit: u64, words_per_slot: u64) -> u64 {
+        calc_num_slots(limit, words_per_slot) * words_per_slot * 8
+    }
+
+    /// Calculates the largest limit for which table of 64-bit digests fits in the given number of bytes.
+    pub(crate) fn calc_limit_for_size(size: u64) -> u64 {
+        let num_slots = max(16, size / 8);
+        // Rounding down to power of two, then leaving room so calc_num_slots() won't round it up again.
+        let num_slots = 1u64 << (63 - num_slots.leading_zeros());
+        num_slots * 3 / 4 - 1
+    }
+
+    /// Returns iterator over all stored digests as (low, high) words.
+    pub(crate) fn digests(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        (0 .. self.num_slots)
+            .map(|slot_idx| read_slot(self, slot_idx))
+            .filter(|digest| *digest != (0, 0))
+    }
+}
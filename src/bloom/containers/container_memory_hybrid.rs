@@ -0,0 +1,311 @@
+use std::cmp::{max, min};
+use std::fs::File;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use xxhash_rust::xxh3::xxh3_64;
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::container_memory_exact::MemoryContainerExact;
+use crate::bloom::containers::container_memory_xxh::MemoryContainerXXH;
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::bloom::scalable::GROWTH;
+use crate::bloom::sizing::{xxh_error_rate, xxh_size_for_error_rate, DEFAULT_ERROR_RATE};
+use crate::{ConstructionDetails, ConstructionType, ContainerDetails};
+
+/// Phase in which hybrid container currently is. Stored as the first byte of the container content.
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u8)]
+enum HybridPhase {
+    // Values are stored in the exact digest set.
+    Exact = 0,
+    // Values were spilled into the xxHash filter.
+    Filter = 1,
+}
+
+pub(crate) struct MemoryContainerHybrid {
+    container_details: ContainerDetails,
+    num_writes: u64, // Number of written keys/values.
+    max_writes: u64, // Maximum number of added keys/values.
+    phase: HybridPhase, // Whether we're still exact or already spilled into the filter.
+    exact: Option<MemoryContainerExact>, // Exact digest set used until it reaches the memory threshold.
+    layers: Vec<MemoryContainerXXH>, // xxHash filters used after spilling, from the oldest. Only the last one is written.
+}
+
+/// Returns limits of the filter layers. The first layer holds spilled digests with room for as many new ones, each
+/// next one is created when the previous one is full and holds GROWTH times more, until all of them together can hold
+/// the whole limit.
+fn layer_limits(first_limit: u64, max_writes: u64) -> Vec<u64> {
+    let mut limits = vec![max(1, first_limit)];
+    let mut total = limits[0];
+    while total < max_writes {
+        let limit = min(limits.last().unwrap().saturating_mul(GROWTH), max_writes - total);
+        limits.push(limit);
+        total += limit;
+    }
+    limits
+}
+
+/// Calculates sizes of the filter layers. Lookups go through all the layers, so each one gets an equal share of the
+/// DEFAULT_ERROR_RATE, which bounds the compound false positive rate.
+fn layer_sizes(first_limit: u64, max_writes: u64) -> Vec<(u64, u64)> {
+    let limits = layer_limits(first_limit, max_writes);
+    let error_rate = DEFAULT_ERROR_RATE / limits.len() as f64;
+    limits.into_iter().map(|limit| {
+        let size = xxh_size_for_error_rate(limit, error_rate).unwrap_or_else(|| {
+            eprintln!("Error: Hybrid container with limit {} is too big.", max_writes);
+            std::process::exit(1);
+        });
+        (limit, size)
+    }).collect()
+}
+
+/// Creates details of the inner container sharing path and data source with the hybrid container.
+fn inner_details(container: &MemoryContainerHybrid, construction_type: ConstructionType, limit: u64, size: u64) -> ContainerDetails {
+    ContainerDetails {
+        path: container.container_details.path.clone(),
+        data_source: container.container_details.data_source,
+        construction_details: ConstructionDetails {
+            construction_type,
+            limit,
+            size,
+            error_rate: 0.0,
+            ttl: 0,
+            shards: 1,
+        },
+        created_at: container.container_details.created_at,
+        expires_at: container.container_details.expires_at,
+        read_only: container.container_details.read_only,
+        hll: HyperLogLog::new(),
+        partition: container.container_details.partition,
+        num_promotions: 0,
+    }
+}
+
+/// Creates xxHash filter layer with given limit and size.
+fn create_layer(container: &MemoryContainerHybrid, limit: u64, size: u64) -> MemoryContainerXXH {
+    MemoryContainerXXH::new_limit_and_size(inner_details(container, ConstructionType::XXHLimitAndSize, limit, size))
+}
+
+/// Appends the next filter layer once the last one is full. The last layer keeps taking values when there's no next
+/// one, i.e. when the hybrid container is full too.
+fn grow_if_needed(container: &mut MemoryContainerHybrid) {
+    if !container.layers.last().unwrap().is_full() {
+        return;
+    }
+
+    let first_limit = container.layers[0].get_num_max_writes();
+    if let Some((limit, size)) = layer_sizes(first_limit, container.max_writes).get(container.layers.len()).copied() {
+        let layer = create_layer(container, limit, size);
+        container.layers.push(layer);
+    }
+}
+
+/// Converts exact digest set into the xxHash filter sized for twice the number of digests. Exact digests are xxh3_64
+/// hashes, so they can be inserted into the filter without the original values.
+fn spill(container: &mut MemoryContainerHybrid) {
+    let exact = container.exact.take().unwrap();
+    let (limit, size) = layer_sizes(exact.get_num_writes().saturating_mul(GROWTH), container.max_writes)[0];
+    let mut layer = create_layer(container, limit, size);
+
+    for (hash, _) in exact.digests() {
+        layer.insert_hash(hash);
+    }
+
+    container.layers = vec![layer];
+    container.phase = HybridPhase::Filter;
+}
+
+/// Spills into the filter if exact digest set can't take more values, or adds filter layer if the last one is full.
+fn spill_if_needed(container: &mut MemoryContainerHybrid) {
+    match container.phase {
+        HybridPhase::Exact if container.exact.as_ref().unwrap().is_full() => spill(container),
+        HybridPhase::Exact => {}
+        HybridPhase::Filter => grow_if_needed(container),
+    }
+}
+
+/// Checks whether any of the filter layers could have value of given hash.
+fn check_layers(layers: &[MemoryContainerXXH], hash: u64) -> bool {
+    layers.iter().any(|layer| layer.check_hash(hash))
+}
+
+/// Checks whether filter layers could have given value and if no, inserts it into the last layer. Returns true if value
+/// could have existed.
+fn check_and_set_layers(container: &mut MemoryContainerHybrid, value: &str) -> bool {
+    let hash = xxh3_64(value.as_bytes());
+    let (last, older) = container.layers.split_last_mut().unwrap();
+    check_layers(older, hash) || last.insert_hash(hash)
+}
+
+/// Returns container which currently takes new values.
+fn current(container: &MemoryContainerHybrid) -> &dyn Container {
+    match container.phase {
+        HybridPhase::Exact => container.exact.as_ref().unwrap(),
+        HybridPhase::Filter => container.layers.last().unwrap(),
+    }
+}
+
+impl Container for MemoryContainerHybrid {
+    /// Inserts value into the filter. Values which are already present are not counted again.
+    fn set(&mut self, value: &str) {
+        self.check_and_set(value);
+    }
+
+    /// Checks whether filter could have given value.
+    fn check(&self, value: &str) -> bool {
+        match self.phase {
+            HybridPhase::Exact => self.exact.as_ref().unwrap().check(value),
+            HybridPhase::Filter => check_layers(&self.layers, xxh3_64(value.as_bytes())),
+        }
+    }
+
+    /// Checks whether filter could have given value and if no, inserts the value. Returns true if value could have
+    /// existed.
+    fn check_and_set(&mut self, value: &str) -> bool {
+        self.container_details.hll.insert(value);
+        spill_if_needed(self);
+        let had_value = match self.phase {
+            HybridPhase::Exact => self.exact.as_mut().unwrap().check_and_set(value),
+            HybridPhase::Filter => check_and_set_layers(self, value),
+        };
+
+        if !had_value {
+            self.num_writes += 1;
+        }
+
+        had_value
+    }
+
+    /// Checks whether container is full, and we should not insert new values.
+    fn is_full(&self) -> bool {
+        self.num_writes >= self.max_writes
+    }
+
+    /// Returns construction info used to create this container.
+    fn get_container_details(&mut self) -> &mut ContainerDetails {
+        &mut self.container_details
+    }
+
+    /// Returns fill percentage of the exact set or the last filter layer.
+    fn get_usage(&self) -> f32 {
+        current(self).get_usage()
+    }
+
+    /// Returns phase in which container currently is.
+    fn get_status(&self) -> Option<String> {
+        match self.phase {
+            HybridPhase::Exact => Some("phase: exact".to_string()),
+            HybridPhase::Filter => Some(format!("phase: filter, layers: {}", self.layers.len())),
+        }
+    }
+
+    /// Returns number of writes into the container.
+    fn get_num_writes(&self) -> u64 {
+        self.num_writes
+    }
+
+    /// Sets number of writes into the container (initialized when container file is opened).
+    fn set_num_writes(&mut self, value: u64) {
+        self.num_writes = value;
+    }
+
+    /// Returns maximum number of allowed writes into the container.
+    fn get_num_max_writes(&self) -> u64 {
+        self.max_writes
+    }
+
+    /// Sets maximum number of allowed writes into the container (initialized when container file is opened).
+    fn set_num_max_writes(&mut self, value: u64) {
+        self.max_writes = value;
+    }
+
+    /// Saves filter data content to the given, already opened for write file.
+    fn save_content(&mut self, file: &mut File) {
+        // Writing phase, so we know which content follows.
+        file.write_u8(self.phase as u8).unwrap();
+
+        if self.phase == HybridPhase::Exact {
+            self.exact.as_mut().unwrap().save_content(file);
+            return;
+        }
+
+        // Writing number of filter layers, then limit, size and number of writes of each layer followed by its content.
+        file.write_u64::<LittleEndian>(self.layers.len() as u64).unwrap();
+        for layer in self.layers.iter_mut() {
+            file.write_u64::<LittleEndian>(layer.get_num_max_writes()).unwrap();
+            file.write_u64::<LittleEndian>(layer.get_container_details().construction_details.size).unwrap();
+            file.write_u64::<LittleEndian>(layer.get_num_writes()).unwrap();
+            layer.save_content(file);
+        }
+    }
+
+    /// Loads filter data content from the given, already opened file.
+    fn load_content(&mut self, file: &mut File) {
+        // Reading phase.
+        if file.read_u8().unwrap() == HybridPhase::Exact as u8 {
+            let num_writes = self.num_writes;
+            let exact = self.exact.as_mut().unwrap();
+            exact.load_content(file);
+            // Inner containers don't keep their counters in the content, but exact one needs it to know when to spill.
+            exact.set_num_writes(num_writes);
+            return;
+        }
+
+        self.exact = None;
+        self.phase = HybridPhase::Filter;
+
+        // Reading filter layers.
+        let num_layers = file.read_u64::<LittleEndian>().unwrap();
+        for _ in 0 .. num_layers {
+            let limit = file.read_u64::<LittleEndian>().unwrap();
+            let size = file.read_u64::<LittleEndian>().unwrap();
+            let num_writes = file.read_u64::<LittleEndian>().unwrap();
+            let mut layer = create_layer(self, limit, size);
+            layer.load_content(file);
+            layer.set_num_writes(num_writes);
+            self.layers.push(layer);
+        }
+    }
+}
+
+impl MemoryContainerHybrid {
+    /// Creates instance of hybrid container from given container details. Size is the memory threshold of the exact
+    /// phase.
+    pub(crate) fn new_limit_and_size(container_details: ContainerDetails) -> Self {
+        let mut container = Self {
+            num_writes: 0,
+            max_writes: container_details.construction_details.limit,
+            phase: HybridPhase::Exact,
+            exact: None,
+            layers: vec![],
+            container_details,
+        };
+
+        let exact_limit = MemoryContainerExact::calc_limit_for_size(container.container_details.construction_details.size);
+        container.exact = Some(MemoryContainerExact::new_limit(inner_details(&container, ConstructionType::ExactLimit64, exact_limit, 0)));
+        container
+    }
+
+    /// Calculates total size in bytes of the filter layers of the container with given limit and memory threshold of
+    /// the exact phase, together with their compound false positive rate.
+    pub(crate) fn calc_filter_size_and_error_rate(limit: u64, size: u64) -> (u64, f64) {
+        let first_limit = MemoryContainerExact::calc_limit_for_size(size).saturating_mul(GROWTH);
+        layer_sizes(first_limit, limit).into_iter().fold((0, 0.0), |(total_size, error_rate), (limit, size)| {
+            (total_size + size, error_rate + xxh_error_rate(size, limit))
+        })
+    }
+
+    /// Calculates the largest memory threshold of the exact phase for which the container with given limit fits into
+    /// given number of bytes, as both phases are allocated while spilling. Returns None if it doesn't fit at all.
+    pub(crate) fn calc_size_for_budget(limit: u64, budget: u64) -> Option<u64> {
+        let fits = |size: u64| size + Self::calc_filter_size_and_error_rate(limit, size).0 <= budget;
+        // Bigger exact phase spills later, so the filter needs fewer layers, but their size changes only a little.
+        let (mut low, mut high) = (1, budget);
+        if !fits(low) {
+            return None;
+        }
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if fits(mid) { low = mid } else { high = mid - 1 }
+        }
+        Some(low)
+    }
+}
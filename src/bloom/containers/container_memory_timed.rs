@@ -0,0 +1,241 @@
+use std::cmp::max;
+use std::fs::File;
+use xxhash_rust::xxh3::xxh3_64;
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::lazy_memory::{FileMapping, LazyMemory, MapMode};
+use crate::ContainerDetails;
+
+/// Time-aware container remembering when each value was last written. Values are stored only as 32-bit fingerprints, so
+/// a new value is taken for the live one with the same fingerprint. Each lookup is thus a false positive with probability
+/// of about n / 2^32 for n live values, e.g. 2.3e-3 for the default limit of 10M.
+pub(crate) struct MemoryContainerTimed {
+    container_details: ContainerDetails,
+    num_writes: u64, // Number of occupied slots.
+    max_writes: u64, // Maximum number of live (not expired) keys/values.
+    slots: LazyMemory<u64>, // Open-addressing table. Each slot holds 32-bit fingerprint and 32-bit timestamp. Zero is free slot.
+    num_slots: u64, // Total number of slots in the table (always a power of two).
+    ttl: u64, // Number of seconds after which values expire.
+    now: u64, // Current time used to decide whether values are expired.
+}
+
+/// Calculates number of table slots for the given limit, so the table is never more than 3/4 full.
+fn calc_num_slots(limit: u64) -> u64 {
+    max(16, (limit.saturating_mul(4) / 3 + 1).next_power_of_two())
+}
+
+/// Calculates non-zero 32-bit fingerprint of the given value.
+fn calc_fingerprint(value: &str) -> u32 {
+    max(1, (xxh3_64(value.as_bytes()) >> 32) as u32)
+}
+
+/// Calculates home slot of the fingerprint. Home slot is derived only from the fingerprint, so the table can be rebuilt
+/// without the original values.
+fn calc_home_slot(container: &MemoryContainerTimed, fingerprint: u32) -> u64 {
+    (fingerprint as u64).wrapping_mul(0x9E3779B97F4A7C15) >> 32 & (container.num_slots - 1)
+}
+
+/// Packs fingerprint and timestamp into a slot.
+fn pack(fingerprint: u32, timestamp: u64) -> u64 {
+    ((fingerprint as u64) << 32) | timestamp.min(u32::MAX as u64)
+}
+
+/// Checks whether value written at given timestamp is expired.
+fn is_expired(container: &MemoryContainerTimed, timestamp: u64) -> bool {
+    container.now >= timestamp + container.ttl
+}
+
+/// Searches for the fingerprint. Returns index of the slot holding the fingerprint (if any) and index of the first
+/// slot which could be used to insert it (free or expired one).
+fn probe(container: &MemoryContainerTimed, fingerprint: u32) -> (Option<u64>, Option<u64>) {
+    let mask = container.num_slots - 1;
+    let mut slot_idx = calc_home_slot(container, fingerprint);
+    let mut insert_idx = None;
+
+    for _ in 0 .. container.num_slots {
+        let slot = container.slots[slot_idx as usize];
+        if slot == 0 {
+            return (None, insert_idx.or(Some(slot_idx)));
+        }
+        if (slot >> 32) as u32 == fingerprint {
+            return (Some(slot_idx), insert_idx);
+        }
+        if insert_idx.is_none() && is_expired(container, slot & u32::MAX as u64) {
+            // Expired slots are reused, but we can't stop there as the fingerprint may be further in the chain.
+            insert_idx = Some(slot_idx);
+        }
+        slot_idx = (slot_idx + 1) & mask;
+    }
+
+    (None, insert_idx)
+}
+
+/// Rebuilds the table with only live values, so expired slots are released.
+fn sweep(container: &mut MemoryContainerTimed) {
+    let live: Vec<u64> = container.slots.iter()
+        .copied()
+        .filter(|slot| *slot != 0 && !is_expired(container, slot & u32::MAX as u64))
+        .collect();
+
+    container.slots.clear();
+    container.num_writes = 0;
+
+    let mask = container.num_slots - 1;
+    for slot in live {
+        let mut slot_idx = calc_home_slot(container, (slot >> 32) as u32);
+        while container.slots[slot_idx as usize] != 0 {
+            slot_idx = (slot_idx + 1) & mask;
+        }
+        container.slots[slot_idx as usize] = slot;
+        container.num_writes += 1;
+    }
+}
+
+/// Inserts value with the current timestamp. Returns true if value was found and is not expired.
+fn insert(container: &mut MemoryContainerTimed, value: &str) -> bool {
+    container.container_details.hll.insert(value);
+    let fingerprint = calc_fingerprint(value);
+    let (found_idx, insert_idx) = probe(container, fingerprint);
+
+    if let Some(slot_idx) = found_idx {
+        if !is_expired(container, container.slots[slot_idx as usize] & u32::MAX as u64) {
+            // Value is still live. We're not refreshing its timestamp, so it expires counting from the first time.
+            return true;
+        }
+        // Value expired, so it's treated as a new one.
+        container.slots[slot_idx as usize] = pack(fingerprint, container.now);
+        return false;
+    }
+
+    if let Some(slot_idx) = insert_idx {
+        if container.slots[slot_idx as usize] == 0 {
+            container.num_writes += 1;
+        }
+        container.slots[slot_idx as usize] = pack(fingerprint, container.now);
+    }
+
+    if container.num_writes >= container.max_writes {
+        // Table reached its limit, but some values may already be expired.
+        sweep(container);
+    }
+
+    false
+}
+
+impl Container for MemoryContainerTimed {
+    /// Inserts value into the filter.
+    fn set(&mut self, value: &str) {
+        insert(self, value);
+    }
+
+    /// Checks whether filter could have given value which is not expired.
+    fn check(&self, value: &str) -> bool {
+        let (found_idx, _) = probe(self, calc_fingerprint(value));
+        found_idx.is_some_and(|slot_idx| !is_expired(self, self.slots[slot_idx as usize] & u32::MAX as u64))
+    }
+
+    /// Checks whether filter could have given value which is not expired and if no, inserts the value. Returns true if
+    /// value could have existed.
+    fn check_and_set(&mut self, value: &str) -> bool {
+        insert(self, value)
+    }
+
+    /// Checks whether container is full, and we should not insert new values.
+    fn is_full(&self) -> bool {
+        self.num_writes >= self.max_writes
+    }
+
+    /// Returns construction info used to create this container.
+    fn get_container_details(&mut self) -> &mut ContainerDetails {
+        &mut self.container_details
+    }
+
+    /// Returns container fill percentage.
+    fn get_usage(&self) -> f32 {
+        100.0f32 / self.num_slots as f32 * self.num_writes as f32
+    }
+
+    /// Returns time to live of the values.
+    fn get_status(&self) -> Option<String> {
+        Some(format!("ttl: {} s", self.ttl))
+    }
+
+    /// Sets current time used to decide whether values are expired.
+    fn set_time(&mut self, timestamp: u64) {
+        let time_changed = timestamp != self.now;
+        self.now = timestamp;
+
+        if time_changed && self.is_full() {
+            // Full container can't insert, so we give expired values a chance to be released at most once per second.
+            sweep(self);
+        }
+    }
+
+    /// Returns number of writes into the container.
+    fn get_num_writes(&self) -> u64 {
+        self.num_writes
+    }
+
+    /// Sets number of writes into the container (initialized when container file is opened).
+    fn set_num_writes(&mut self, value: u64) {
+        self.num_writes = value;
+    }
+
+    /// Returns maximum number of allowed writes into the container.
+    fn get_num_max_writes(&self) -> u64 {
+        self.max_writes
+    }
+
+    /// Sets maximum number of allowed writes into the container (initialized when container file is opened).
+    fn set_num_max_writes(&mut self, value: u64) {
+        self.max_writes = value;
+    }
+
+    /// Saves filter data content to the given, already opened for write file.
+    fn save_content(&mut self, file: &mut File) {
+        self.slots.save(file);
+    }
+
+    /// Loads filter data content from the given, already opened file.
+    fn load_content(&mut self, file: &mut File) {
+        self.slots.load(file);
+    }
+
+    /// Maps filter data content of the given file instead of loading it.
+    fn map_content(&mut self, file: &mut File, path: &str, offset: u64, mode: MapMode) -> bool {
+        self.slots.map(file, path, offset, 0, mode)
+    }
+
+    /// Returns mapping of the file if content is modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        self.slots.mapped_file()
+    }
+
+    /// Writes changes of the filter data content modified in place to the given, already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.slots.sync(file);
+    }
+}
+
+impl MemoryContainerTimed {
+    /// Creates instance of time-aware container from given container details. Size of the table is derived from the
+    /// limit and stored back in the container details.
+    pub(crate) fn new_limit_and_ttl(mut container_details: ContainerDetails) -> Self {
+        let num_slots = calc_num_slots(container_details.construction_details.limit);
+        container_details.construction_details.size = num_slots * 8;
+
+        Self {
+            num_writes: 0,
+            max_writes: container_details.construction_details.limit,
+            slots: LazyMemory::new(num_slots as usize),
+            num_slots,
+            ttl: container_details.construction_details.ttl,
+            now: 0,
+            container_details,
+        }
+    }
+
+    /// Calculates size in bytes of the table holding given number of live values.
+    pub(crate) fn calc_size_for_limit(limit: u64) -> u64 {
+        calc_num_slots(limit) * 8
+    }
+}
@@ -1,25 +1,57 @@
+use std::cmp::max;
 use std::fs::File;
-use bit_vec::BitVec;
-use std::io::{Write, Read, BufWriter};
-use crate::bloom::containers::container::Container;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::bloom::containers::container::{Container, FillEstimate, BATCH_SIZE, SHARED_NUM_WRITES_OFFSET};
+use crate::bloom::containers::lazy_memory::{FileMapping, LazyMemory, MapMode};
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::bloom::sizing::{xxh_error_rate, xxh_size_for_error_rate, DEFAULT_ERROR_RATE};
 use xxhash_rust::xxh3::xxh3_64;
 
-use crate::ContainerDetails;
+use crate::{ContainerDetails, DataSource};
 
 pub(crate) struct MemoryContainerXXH {
     container_details: ContainerDetails,
     num_writes: u64, // Number of written keys/values.
     max_writes: u64, // Maximum number of added keys/values.
-    bit_vec: BitVec, // Vector of bits used to store keys/values.
+    bits: LazyMemory<u8>, // Bits used to store keys/values, most significant bit of each byte first.
     key_bits: u8, // Number of bits used for each key in the slot.
     slot_bits: u8, // Total number of bits used for each slot.
     num_slots: u64, // Total number of slots in the vector of bits.
-    num_tries: u64 // Maximum number of lookups when adding/retrieving keys/values.
+    num_tries: u64, // Maximum number of lookups when adding/retrieving keys/values.
+    layout: SlotLayout, // Arrangement of the slots in the bits.
+}
+
+/// Arrangement of the slots in the bits. Stored in the header as the content version.
+#[derive(Copy, Clone, PartialEq)]
+enum SlotLayout {
+    // Slots follow each other bit by bit, so slot may span two words. Used by files created before slots were aligned
+    // to words (content version 0).
+    Packed = 0,
+    // SLOTS_PER_WORD slots are stored in each little endian 64-bit word, so slot is written together with its occupied
+    // bit at once (content version 1). Highest bit of the word is unused.
+    Words = 1,
+}
+
+/// Number of bits used for each key in the slot.
+pub(crate) const KEY_BITS: u8 = 20;
+
+/// Number of internal bits in each slot. We will only store boolean indicating whether slot is occupied.
+pub(crate) const SLOT_INTERNAL_BITS: u8 = 1;
+
+/// Maximum number of consecutive slots looked up when adding/retrieving keys.
+pub(crate) const NUM_TRIES: u64 = 4;
+
+/// Number of slots stored in each 64-bit word.
+pub(crate) const SLOTS_PER_WORD: u64 = 64 / (SLOT_INTERNAL_BITS + KEY_BITS) as u64;
+
+/// Calculates number of slots of the filter with given size in bytes. Bytes after the last whole word are unused.
+pub(crate) fn calc_num_slots(size: u64) -> u64 {
+    size / 8 * SLOTS_PER_WORD
 }
 
 /// Performs input value scaling.
 fn remap(value: f64, in_min: f64, in_max: f64, out_min: f64, out_max: f64) -> f64 {
-    return out_min + (value - in_min) * (out_max - out_min) / (in_max - in_min);
+    out_min + (value - in_min) * (out_max - out_min) / (in_max - in_min)
 }
 
 /// Calculates index of the slot where we can insert key which is a part of given hash.
@@ -27,11 +59,47 @@ fn calc_slot_index(container: &MemoryContainerXXH, hash: u64) -> u64 {
     remap(hash as f64, 0f64, u64::MAX as f64, 0f64, (container.num_slots - 1) as f64) as u64 % container.num_slots
 }
 
-/// Returns u32 made from bit_vec bits of given index range. Note that both indices are inclusive.
-fn get_bit_vec_slice(container: &MemoryContainerXXH, slot_bit_from: u64, slot_bit_to: u64) -> u32 {
+/// Calculates number of slots of the filter with given size in bytes, slot size in bits and layout.
+fn num_slots_for_layout(size: u64, slot_bits: u8, layout: SlotLayout) -> u64 {
+    match layout {
+        SlotLayout::Packed => size * 8 / slot_bits as u64,
+        SlotLayout::Words => calc_num_slots(size),
+    }
+}
+
+/// Returns bit of given index.
+fn get_bit(container: &MemoryContainerXXH, bit_idx: u64) -> bool {
+    container.bits.load_byte((bit_idx / 8) as usize) & (0x80 >> (bit_idx % 8)) != 0
+}
+
+/// Sets bit of given index. Bytes are only written when the bit changes, so pages of the lazy memory stay untouched
+/// for zero bits.
+fn set_bit(container: &mut MemoryContainerXXH, bit_idx: u64, value: bool) {
+    if get_bit(container, bit_idx) != value {
+        container.bits[(bit_idx / 8) as usize] ^= 0x80 >> (bit_idx % 8);
+    }
+}
+
+/// Returns number of writes shared with other processes writing into the same file at the same time.
+fn shared_num_writes(container: &MemoryContainerXXH) -> Option<&AtomicU64> {
+    container.bits.mapped_file()
+        .filter(|mapping| mapping.is_concurrent())
+        .map(|mapping| mapping.header_u64(SHARED_NUM_WRITES_OFFSET))
+}
+
+/// Counts a write into the container.
+fn add_write(container: &mut MemoryContainerXXH) {
+    match shared_num_writes(container) {
+        Some(num_writes) => { num_writes.fetch_add(1, Ordering::Relaxed); }
+        None => container.num_writes += 1,
+    }
+}
+
+/// Returns u32 made from bits of given index range. Note that both indices are inclusive.
+fn get_bits_slice(container: &MemoryContainerXXH, slot_bit_from: u64, slot_bit_to: u64) -> u32 {
     let mut result: u32 = 0;
     for i in 0 .. slot_bit_to - slot_bit_from + 1 {
-        let bit_value = container.bit_vec.get((slot_bit_from + i) as usize).unwrap();
+        let bit_value = get_bit(container, slot_bit_from + i);
         if bit_value {
             result |= 1 << i;
         }
@@ -40,10 +108,10 @@ fn get_bit_vec_slice(container: &MemoryContainerXXH, slot_bit_from: u64, slot_bi
 }
 
 /// Writes key bits into container. Note that both indices are inclusive.
-fn set_bit_vec_slice(container: &mut MemoryContainerXXH, slot_bit_from: u64, slot_bit_to: u64, key: u32) {
+fn set_bits_slice(container: &mut MemoryContainerXXH, slot_bit_from: u64, slot_bit_to: u64, key: u32) {
     for i in 0 .. slot_bit_to - slot_bit_from + 1 {
-        let bit_value = if key & (1 << i) != 0 { true } else { false };
-        container.bit_vec.set((slot_bit_from + i) as usize, bit_value);
+        let bit_value = key & (1 << i) != 0;
+        set_bit(container, slot_bit_from + i, bit_value);
     }
 }
 
@@ -52,33 +120,75 @@ fn get_hash_key_value(container: &MemoryContainerXXH, hash: u64) -> u32 {
     (hash & ((1 << container.key_bits) - 1)) as u32
 }
 
-/// Writes key in the given slot index. Marks slot as occupied.
-fn write_key(container: &mut MemoryContainerXXH, mut slot_idx: u64, key: u32) {
-    slot_idx = slot_idx % container.num_slots;
-    // Marking slot as occupied.
-    let slot_occupied_bit = slot_idx * container.slot_bits as u64;
-    container.bit_vec.set(slot_occupied_bit as usize, true);
-    // Writing key into slot.
-    let slot_key_bit_from = (slot_idx * container.slot_bits as u64) + 1;
-    let slot_key_bit_to = slot_key_bit_from + container.key_bits as u64 - 1; // Inclusive end index.
-    set_bit_vec_slice(container, slot_key_bit_from, slot_key_bit_to, key);
-    container.num_writes += 1
+/// Returns index of the first byte of the word holding given slot and position of the slot's lowest bit in the word.
+fn word_position(container: &MemoryContainerXXH, slot_idx: u64) -> (usize, u64) {
+    ((slot_idx / SLOTS_PER_WORD * 8) as usize, slot_idx % SLOTS_PER_WORD * container.slot_bits as u64)
+}
+
+/// Returns bits of the slot, i.e. whether it's occupied in the lowest bit followed by the key.
+fn load_slot(container: &MemoryContainerXXH, slot_idx: u64) -> u64 {
+    match container.layout {
+        SlotLayout::Packed => {
+            let slot_bit_from = slot_idx * container.slot_bits as u64;
+            get_bits_slice(container, slot_bit_from, slot_bit_from + container.slot_bits as u64 - 1) as u64
+        }
+        SlotLayout::Words => {
+            let (byte_idx, shift) = word_position(container, slot_idx);
+            container.bits.load_u64(byte_idx) >> shift & ((1 << container.slot_bits) - 1)
+        }
+    }
+}
+
+/// Writes key in the given free slot index and marks slot as occupied. In concurrent mode, other processes may write
+/// into the same word, so slot is published together with its key by a single compare-and-swap, only if it's still
+/// free. Returns false if slot was claimed by other process in the meantime.
+fn write_key(container: &mut MemoryContainerXXH, mut slot_idx: u64, key: u32) -> bool {
+    slot_idx %= container.num_slots;
+    let slot = (key as u64) << 1 | 1;
+
+    match container.layout {
+        SlotLayout::Packed => {
+            let slot_bit_from = slot_idx * container.slot_bits as u64;
+            set_bits_slice(container, slot_bit_from, slot_bit_from + container.slot_bits as u64 - 1, slot as u32);
+        }
+        SlotLayout::Words if shared_num_writes(container).is_some() => {
+            let (byte_idx, shift) = word_position(container, slot_idx);
+            let mut word = container.bits.load_u64(byte_idx);
+            loop {
+                if word >> shift & 1 != 0 {
+                    return false;
+                }
+                // Other slots of the word may change in the meantime too, then we're just trying again.
+                match container.bits.compare_exchange_u64(byte_idx, word, word | slot << shift) {
+                    Ok(_) => break,
+                    Err(current) => word = current,
+                }
+            }
+        }
+        SlotLayout::Words => {
+            let (byte_idx, shift) = word_position(container, slot_idx);
+            let word = container.bits.load_u64(byte_idx);
+            container.bits.store_u64(byte_idx, word | slot << shift);
+        }
+    }
+
+    add_write(container);
+    true
 }
 
 /// Reads key in the given slot index.
-fn read_key(container: &MemoryContainerXXH, mut slot_idx: u64) -> u32 {
-    slot_idx = slot_idx % container.num_slots;
-    // Writing key from slot.
-    let slot_key_bit_from = (slot_idx * container.slot_bits as u64) + 1;
-    let slot_key_bit_to = slot_key_bit_from + container.key_bits as u64 - 1; // Inclusive end index.
-    get_bit_vec_slice(container, slot_key_bit_from, slot_key_bit_to)
+fn read_key(container: &MemoryContainerXXH, slot_idx: u64) -> u32 {
+    (load_slot(container, slot_idx % container.num_slots) >> 1) as u32
 }
 
 /// Checks whether slot is in use.
 fn get_slot_in_use(container: &MemoryContainerXXH, mut slot_idx: u64) -> bool {
-    slot_idx = slot_idx % container.num_slots;
-    // Reading first bit of the slot which indicates whether slot is in use.
-    container.bit_vec.get((slot_idx * container.slot_bits as u64) as usize).unwrap()
+    slot_idx %= container.num_slots;
+    match container.layout {
+        // Reading first bit of the slot which indicates whether slot is in use.
+        SlotLayout::Packed => get_bit(container, slot_idx * container.slot_bits as u64),
+        SlotLayout::Words => load_slot(container, slot_idx) & 1 != 0,
+    }
 }
 
 /// Tries to insert part of the hash in the first free slot starting from the specified slot index.
@@ -100,7 +210,13 @@ fn insert_key(container: &mut MemoryContainerXXH, slot_idx: u64, hash: u64, num_
             continue;
         }
         // Free slot found, writing key and marking as occupied.
-        write_key(container, slot_idx + i, hash_key_value);
+        if !write_key(container, slot_idx + i, hash_key_value) {
+            // Other process claimed the slot in the meantime. Its key was written at once with it, so it may be ours.
+            if read_key(container, slot_idx + i) == hash_key_value {
+                return true;
+            }
+            continue;
+        }
         // Key wasn't found so returning false.
         return false;
     }
@@ -133,36 +249,86 @@ fn find_key(container: &MemoryContainerXXH, slot_idx: u64, hash: u64, num_tries:
     true
 }
 
+/// Prefetches bytes of the slots looked up for the key starting from the given slot index.
+fn prefetch_slots(container: &MemoryContainerXXH, slot_idx: u64) {
+    let (byte_from, byte_to) = match container.layout {
+        SlotLayout::Packed => {
+            let bit_from = slot_idx * container.slot_bits as u64;
+            let bit_to = bit_from + container.num_tries * container.slot_bits as u64 - 1;
+            ((bit_from / 8) as usize, (bit_to / 8) as usize)
+        }
+        SlotLayout::Words => (word_position(container, slot_idx).0, word_position(container, slot_idx + container.num_tries - 1).0 + 7),
+    };
+    container.bits.prefetch(byte_from);
+    container.bits.prefetch(byte_to);
+}
+
+/// Calculates hashes and slot indices of the values and prefetches their slots.
+fn prefetch_values(container: &MemoryContainerXXH, values: &[&str], hashes: &mut [(u64, u64); BATCH_SIZE]) {
+    for (value, (hash, slot_idx)) in values.iter().zip(hashes.iter_mut()) {
+        *hash = xxh3_64(value.as_bytes());
+        *slot_idx = calc_slot_index(container, *hash);
+        prefetch_slots(container, *slot_idx);
+    }
+}
+
 impl Container for MemoryContainerXXH {
     /// Inserts value into the filter.
-    fn set(&mut self, value: &String) {
+    fn set(&mut self, value: &str) {
+        self.container_details.hll.insert(value);
         let hash = xxh3_64(value.as_bytes());
         let slot_idx = calc_slot_index(self, hash);
         insert_key(self, slot_idx, hash, self.num_tries);
-        self.num_writes += 1;
+        add_write(self);
     }
 
     /// Checks whether filter could have given value.
-    fn check(&self, value: &String) -> bool {
+    fn check(&self, value: &str) -> bool {
         // Very naive version of check. Just for testing purposes.
         let hash = xxh3_64(value.as_bytes());
         let slot_idx = calc_slot_index(self, hash);
         // We won't use the free_index in read mode.
-        return find_key(self, slot_idx, hash, self.num_tries);
+        find_key(self, slot_idx, hash, self.num_tries)
     }
 
     /// Checks whether filter could have given value and if no, inserts the value. Returns true if value could have
     /// existed.
-    fn check_and_set(&mut self, value: &String) -> bool {
+    fn check_and_set(&mut self, value: &str) -> bool {
+        self.container_details.hll.insert(value);
         let hash = xxh3_64(value.as_bytes());
         let slot_idx = calc_slot_index(self, hash);
         // insert_key() will return whether key was found while trying to insert it.
-        return insert_key(self, slot_idx, hash, self.num_tries);
+        insert_key(self, slot_idx, hash, self.num_tries)
+    }
+
+    /// Checks whether filter could have given values. Slots of the values are prefetched first.
+    fn check_batch(&self, values: &[&str], found: &mut [bool]) {
+        let mut hashes = [(0, 0); BATCH_SIZE];
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut hashes);
+            for (found, (hash, slot_idx)) in found.iter_mut().zip(hashes) {
+                *found = find_key(self, slot_idx, hash, self.num_tries);
+            }
+        }
+    }
+
+    /// Checks and inserts the values in order. Slots of the values are prefetched first.
+    fn check_and_set_batch(&mut self, values: &[&str], found: &mut [bool]) {
+        let mut hashes = [(0, 0); BATCH_SIZE];
+        for value in values {
+            self.container_details.hll.insert(value);
+        }
+        for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+            prefetch_values(self, values, &mut hashes);
+            for (found, (hash, slot_idx)) in found.iter_mut().zip(hashes) {
+                *found = insert_key(self, slot_idx, hash, self.num_tries);
+            }
+        }
     }
 
     /// Checks whether container is full, and we should not insert new values.
     fn is_full(&self) -> bool {
-        return self.num_writes >= self.max_writes;
+        self.get_num_writes() >= self.max_writes
     }
 
     /// Returns construction info used to create this container.
@@ -172,12 +338,12 @@ impl Container for MemoryContainerXXH {
 
     /// Returns container fill percentage.
     fn get_usage(&self) -> f32 {
-        100.0f32 / self.bit_vec.len() as f32 * self.num_writes as f32
+        100.0f32 / (self.bits.len() * 8) as f32 * self.get_num_writes() as f32
     }
 
     // Returns number of writes into the container.
     fn get_num_writes(&self) -> u64 {
-        self.num_writes as u64
+        shared_num_writes(self).map_or(self.num_writes, |num_writes| num_writes.load(Ordering::Relaxed))
     }
 
     // Sets number of writes into the container (initialized when container file is opened).
@@ -187,7 +353,7 @@ impl Container for MemoryContainerXXH {
 
     // Returns maximum number of allowed writes into the container.
     fn get_num_max_writes(&self) -> u64 {
-        self.max_writes as u64
+        self.max_writes
     }
 
     // Sets maximum number of allowed writes into the container (initialized when container file is opened).
@@ -197,34 +363,199 @@ impl Container for MemoryContainerXXH {
 
     /// Saves filter data content to the given, already opened for write file.
     fn save_content(&mut self, file: &mut File) {
-        let mut buf_writer = BufWriter::with_capacity(10000000, file);
-        buf_writer.write_all(&self.bit_vec.to_bytes()).unwrap();
+        self.bits.save(file);
     }
 
     /// Loads filter data content from the given, already opened file.
     fn load_content(&mut self, file: &mut File) {
-        let construction_details = &self.get_container_details();
-        let mut bytes = Vec::new();
-        bytes.reserve_exact(construction_details.construction_details.size as usize);
-        file.read_to_end(&mut bytes).unwrap();
-        self.bit_vec = BitVec::from_bytes(&bytes);
+        self.bits.load(file);
+    }
+
+    /// Returns version of the slot layout.
+    fn get_content_version(&self) -> u8 {
+        self.layout as u8
+    }
+
+    /// Sets version of the slot layout (initialized when container file is opened). Files of unknown versions are
+    /// refused, as their slots would be misread.
+    fn set_content_version(&mut self, version: u8) {
+        self.layout = match version {
+            0 => SlotLayout::Packed,
+            1 => SlotLayout::Words,
+            _ => {
+                eprintln!("Error: File \"{}\" was created by newer version of bloom!", self.container_details.path);
+                std::process::exit(1);
+            }
+        };
+        self.num_slots = num_slots_for_layout(self.bits.len() as u64, self.slot_bits, self.layout);
+    }
+
+    /// Maps filter data content of the given file instead of loading it.
+    fn map_content(&mut self, file: &mut File, path: &str, offset: u64, mode: MapMode) -> bool {
+        self.bits.map(file, path, offset, 0, mode)
+    }
+
+    /// Returns mapping of the file if content is modified in place.
+    fn mapped_file(&self) -> Option<&FileMapping> {
+        self.bits.mapped_file()
+    }
+
+    /// Writes changes of the filter data content modified in place to the given, already opened for write file.
+    fn sync_content(&mut self, file: &mut File) {
+        self.bits.sync(file);
+    }
+
+    /// Adds keys of the other filter into this one. Filters must have the same number of slots. Each key is inserted
+    /// starting from the slot it occupies in the other filter. Slots between its home slot and that one are occupied
+    /// in the other filter, so they end up occupied here too, and lookups starting at the home slot still reach it (or
+    /// find all the tried slots occupied). Number of writes is summed, so values present in both filters are counted
+    /// twice.
+    fn merge(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerXXH>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+
+        let num_writes = self.num_writes;
+        for slot_idx in 0 .. other.num_slots {
+            if get_slot_in_use(other, slot_idx) {
+                insert_key(self, slot_idx, read_key(other, slot_idx) as u64, self.num_tries);
+            }
+        }
+        self.num_writes = num_writes + other.get_num_writes();
+
+        Ok(())
+    }
+
+    /// Keeps only keys which the other filter could have too. Filters must have the same number of slots.
+    fn intersect(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerXXH>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+        retain_keys(self, other, true);
+        Ok(())
+    }
+
+    /// Keeps only keys which the other filter doesn't have. Filters must have the same number of slots. Values whose
+    /// lookup in the other filter is a false positive are removed too.
+    fn subtract(&mut self, other: &dyn Container) -> Result<(), String> {
+        let other = other.downcast_ref::<MemoryContainerXXH>().ok_or("containers have different types")?;
+        check_compatible(self, other)?;
+        retain_keys(self, other, false);
+        Ok(())
+    }
+
+    /// Estimates number of values from the number of occupied slots.
+    fn estimate_fill(&self) -> Option<FillEstimate> {
+        let num_used_slots = (0 .. self.num_slots).filter(|slot_idx| get_slot_in_use(self, *slot_idx)).count() as u64;
+        Some(FillEstimate {
+            num_values: num_used_slots as f64,
+            error_rate: xxh_error_rate(self.bits.len() as u64, num_used_slots),
+        })
     }
 }
 
+/// Keeps only keys which are (or aren't) found in the other filter. Home slot of the key isn't stored, so the key is
+/// looked up from each slot its lookup may start at (the slot itself and occupied slots right before it). Key is kept
+/// in the intersection if any of its lookups finds it, and in the difference if none of them does. Kept keys are
+/// inserted into the empty table from the slots where their lookups may start, so no kept value is lost. Values which
+/// were found only because all slots of their lookup were occupied have no key, so they are lost.
+fn retain_keys(container: &mut MemoryContainerXXH, other: &MemoryContainerXXH, found_in_other: bool) {
+    let mut retained = MemoryContainerXXH::new_limit_and_size(ContainerDetails {
+        path: String::new(),
+        data_source: DataSource::Memory,
+        construction_details: container.container_details.construction_details,
+        created_at: 0,
+        expires_at: 0,
+        read_only: false,
+        hll: HyperLogLog::new(),
+        partition: None,
+        num_promotions: 0,
+    });
+    retained.set_content_version(container.layout as u8);
+
+    let mut homes: Vec<(u64, bool)> = Vec::with_capacity(NUM_TRIES as usize);
+    for slot_idx in 0 .. container.num_slots {
+        if !get_slot_in_use(container, slot_idx) {
+            continue;
+        }
+        let key = read_key(container, slot_idx) as u64;
+
+        homes.clear();
+        for distance in 0 .. container.num_tries {
+            let home_idx = (slot_idx + container.num_slots - distance) % container.num_slots;
+            if distance > 0 && !get_slot_in_use(container, home_idx) {
+                // Lookups starting before the free slot never reach this one.
+                break;
+            }
+            homes.push((home_idx, find_key(other, home_idx, key, other.num_tries)));
+        }
+
+        // Keys found only from slots other than their home one are kept in the intersection, as their home slot is
+        // unknown. Removing them from the difference instead keeps keys of removed values from staying near their home.
+        let keep_all = !found_in_other && homes.iter().all(|(_, found)| !found);
+        for (home_idx, found) in homes.iter() {
+            if keep_all || (found_in_other && *found) {
+                insert_key(&mut retained, *home_idx, key, NUM_TRIES);
+            }
+        }
+    }
+
+    container.bits = retained.bits;
+    container.num_writes = retained.num_writes;
+}
+
+/// Checks whether keys of both filters are stored in the same slots. Returns reason why they aren't.
+pub(crate) fn check_compatible(container: &MemoryContainerXXH, other: &MemoryContainerXXH) -> Result<(), String> {
+    if container.bits.len() != other.bits.len() {
+        return Err(format!("xxHash filters have different sizes ({} and {} bytes)", container.bits.len(), other.bits.len()));
+    }
+    if container.layout != other.layout {
+        return Err("xxHash filters have different slot layouts, as one of them was created by older version of bloom".to_string());
+    }
+    Ok(())
+}
+
 impl MemoryContainerXXH {
-    /// Creates instance of bloom filter from given container details.
-    pub(crate) fn new_limit_and_size(container_details: ContainerDetails) -> Self {
-        let key_bits: u8 = 20;
-        let slot_internal_bits: u8 = 1; // We will only store boolean indicating whether slot is occupied.
+    /// Creates instance of bloom filter from given container details. Slots are aligned to words, so filter takes at
+    /// least one word and the actually used size is stored back in the container details.
+    pub(crate) fn new_limit_and_size(mut container_details: ContainerDetails) -> Self {
+        let key_bits: u8 = KEY_BITS;
+        let slot_internal_bits: u8 = SLOT_INTERNAL_BITS;
+        container_details.construction_details.size = max(8, container_details.construction_details.size);
         Self {
             num_writes: 0,
             max_writes: container_details.construction_details.limit,
-            bit_vec: BitVec::from_elem(container_details.construction_details.size as usize * 8, false),
+            bits: LazyMemory::new(container_details.construction_details.size as usize),
             key_bits,
             slot_bits: slot_internal_bits + key_bits,
-            num_slots: (container_details.construction_details.size * 8) / (slot_internal_bits as u64 + key_bits as u64),
-            num_tries: 4,
+            num_slots: calc_num_slots(container_details.construction_details.size),
+            num_tries: NUM_TRIES,
+            layout: SlotLayout::Words,
             container_details,
         }
     }
+
+    /// Calculates filter size in bytes needed to store given number of keys with DEFAULT_ERROR_RATE.
+    pub(crate) fn calc_size_for_limit(limit: u64) -> u64 {
+        Self::calc_size_for_limit_and_error_rate(limit, DEFAULT_ERROR_RATE)
+    }
+
+    /// Calculates filter size in bytes needed to store given number of keys with given false positive rate. Exits if
+    /// the filter would be too big.
+    pub(crate) fn calc_size_for_limit_and_error_rate(limit: u64, error_rate: f64) -> u64 {
+        let size = xxh_size_for_error_rate(limit, error_rate).unwrap_or_else(|| {
+            eprintln!("Error: xxHash filter with limit {} and false positive rate {} is too big.", limit, error_rate);
+            std::process::exit(1);
+        });
+        max(64, size)
+    }
+
+    /// Checks whether filter could have value of already calculated xxh3_64 hash.
+    pub(crate) fn check_hash(&self, hash: u64) -> bool {
+        find_key(self, calc_slot_index(self, hash), hash, self.num_tries)
+    }
+
+    /// Inserts already calculated xxh3_64 hash of the value. Returns true if key was found.
+    pub(crate) fn insert_hash(&mut self, hash: u64) -> bool {
+        let slot_idx = calc_slot_index(self, hash);
+        insert_key(self, slot_idx, hash, self.num_tries)
+    }
 }
@@ -0,0 +1,457 @@
+use std::fs::File;
+use std::path::Path;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+use crate::bloom::containers::container::{Container, ContainerHeader, FillEstimate, BATCH_SIZE};
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::{ConstructionDetails, ContainerDetails};
+
+/// Seed of the hash selecting the shard, so shards don't correlate with slots of the containers which use xxh3 without
+/// a seed (or with the HyperLogLog seed).
+const SEED: u64 = 0x53_48_52_44;
+
+/// Maximum number of shards, so shard index always fits into four digits of the file name.
+pub const MAX_SHARDS: usize = 4096;
+
+/// Container split into shards, each of them being a container with its own file. Shard is selected by the top bits of
+/// the line hash, so each line is always stored in the same shard. Only shards which were modified are saved.
+pub(crate) struct ShardedContainer {
+    container_details: ContainerDetails,
+    shards: Vec<Box<dyn Container>>,
+    shard_bits: u32, // Number of top bits of the hash selecting the shard.
+    dirty: Vec<bool>, // Whether shard was modified since it was loaded or saved.
+    top_size: usize, // Number of the most frequent values to track.
+}
+
+/// Returns part of the shard file name added before the extension, e.g. ".shard-0003".
+pub fn shard_suffix(idx: usize) -> String {
+    format!(".shard-{idx:04}")
+}
+
+/// Returns path of the shard file with given index, e.g. "day.blf" -> "day.shard-0003.blf".
+pub fn shard_path(path: &str, idx: usize) -> String {
+    let file_path = Path::new(path);
+    match (file_path.file_stem(), file_path.extension()) {
+        (Some(stem), Some(extension)) => file_path
+            .with_file_name(format!("{}{}.{}", stem.to_string_lossy(), shard_suffix(idx), extension.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{path}{}", shard_suffix(idx)),
+    }
+}
+
+/// Returns number of existing shard files of the given file (zero if file is not sharded).
+pub fn discover_shards(path: &str) -> usize {
+    (0 .. MAX_SHARDS).find(|idx| !Path::new(&shard_path(path, *idx)).exists()).unwrap_or(MAX_SHARDS)
+}
+
+/// Checks whether given number of shards is supported.
+pub fn is_valid_num_shards(num_shards: usize) -> bool {
+    num_shards.is_power_of_two() && num_shards <= MAX_SHARDS
+}
+
+/// Returns number of shards of the existing sharded file. Fails if some of the shard files are missing.
+fn existing_num_shards(path: &str) -> usize {
+    let num_shards = discover_shards(path);
+    if !is_valid_num_shards(num_shards) {
+        eprintln!("Error: Sharded file \"{}\" is incomplete, found {} shard file(s), but number of shards should be a power of two.", path, num_shards);
+        std::process::exit(1);
+    }
+    num_shards
+}
+
+/// Combines header fields of the shards into header of the whole container. Limit, size and number of writes are
+/// summed, timestamps are taken from the first shard.
+fn combine_construction_details(shards: &[ConstructionDetails]) -> ConstructionDetails {
+    ConstructionDetails {
+        limit: shards.iter().map(|shard| shard.limit).sum(),
+        size: shards.iter().map(|shard| shard.size).sum(),
+        shards: shards.len(),
+        ..shards[0]
+    }
+}
+
+/// Reads only the headers of the shard files of the existing sharded file.
+pub fn header_from_files(path: &str) -> ContainerHeader {
+    let headers: Vec<ContainerHeader> = (0 .. existing_num_shards(path))
+        .map(|idx| <dyn Container>::header_from_file(&shard_path(path, idx)))
+        .collect();
+
+    let mut hll = HyperLogLog::new();
+    for header in headers.iter() {
+        hll.merge(&header.hll);
+    }
+
+    let construction_details: Vec<ConstructionDetails> = headers.iter().map(|header| header.construction_details).collect();
+    ContainerHeader {
+        construction_details: combine_construction_details(&construction_details),
+        num_writes: headers.iter().map(|header| header.num_writes).sum(),
+        num_max_writes: headers.iter().map(|header| header.num_max_writes).sum(),
+        created_at: headers[0].created_at,
+        expires_at: headers[0].expires_at,
+        hll,
+        partition: headers[0].partition,
+        content_version: headers[0].content_version,
+    }
+}
+
+/// Returns details of the shard with given index. Limit and size of the whole container are split evenly between the
+/// shards.
+fn shard_details(container_details: &ContainerDetails, num_shards: usize, idx: usize) -> ContainerDetails {
+    let construction_details = container_details.construction_details;
+    ContainerDetails {
+        path: shard_path(&container_details.path, idx),
+        construction_details: ConstructionDetails {
+            limit: construction_details.limit.div_ceil(num_shards as u64),
+            size: construction_details.size.div_ceil(num_shards as u64),
+            shards: 1,
+            ..construction_details
+        },
+        data_source: container_details.data_source,
+        created_at: container_details.created_at,
+        expires_at: container_details.expires_at,
+        read_only: container_details.read_only,
+        hll: HyperLogLog::new(),
+        partition: container_details.partition,
+        num_promotions: 0,
+    }
+}
+
+/// Returns index of the shard storing given value.
+fn shard_idx(shard_bits: u32, value: &str) -> usize {
+    if shard_bits == 0 {
+        return 0;
+    }
+    (xxh3_64_with_seed(value.as_bytes(), SEED) >> (64 - shard_bits)) as usize
+}
+
+/// Calls given function with values of each shard and their results. Values are grouped per batch, keeping their order
+/// within the shard, so each shard gets all its values of the batch at once.
+fn for_each_shard_batch<F>(shard_bits: u32, values: &[&str], found: &mut [bool], mut call: F)
+where
+    F: FnMut(usize, &[&str], &mut [bool]),
+{
+    for (values, found) in values.chunks(BATCH_SIZE).zip(found.chunks_mut(BATCH_SIZE)) {
+        let mut shard_idxs = [0; BATCH_SIZE];
+        let mut order = [0; BATCH_SIZE];
+        for (idx, value) in values.iter().enumerate() {
+            shard_idxs[idx] = shard_idx(shard_bits, value);
+            order[idx] = idx;
+        }
+        order[.. values.len()].sort_unstable_by_key(|idx| (shard_idxs[*idx], *idx));
+
+        let mut start = 0;
+        while start < values.len() {
+            let shard = shard_idxs[order[start]];
+            let end = (start + 1 .. values.len()).find(|idx| shard_idxs[order[*idx]] != shard).unwrap_or(values.len());
+
+            let mut shard_values = [""; BATCH_SIZE];
+            let mut shard_found = [false; BATCH_SIZE];
+            for (shard_value, idx) in shard_values.iter_mut().zip(order[start .. end].iter()) {
+                *shard_value = values[*idx];
+            }
+            call(shard, &shard_values[.. end - start], &mut shard_found[.. end - start]);
+            for (shard_found, idx) in shard_found.iter().zip(order[start .. end].iter()) {
+                found[*idx] = *shard_found;
+            }
+
+            start = end;
+        }
+    }
+}
+
+impl ShardedContainer {
+    /// Creates empty sharded container whose shards have no files yet.
+    pub(crate) fn new(mut container_details: ContainerDetails) -> Self {
+        let num_shards = container_details.construction_details.shards;
+        let mut shards: Vec<Box<dyn Container>> = (0 .. num_shards)
+            .map(|idx| <dyn Container>::from_details(shard_details(&container_details, num_shards, idx)))
+            .collect();
+
+        // Shards may derive their size from the limit, so size of the whole container is known only now.
+        container_details.construction_details.size = shards.iter_mut().map(|shard| shard.get_container_details().construction_details.size).sum();
+
+        Self::from_shards(container_details, shards, true)
+    }
+
+    /// Creates sharded container from the existing shard files of the given path.
+    pub(crate) fn from_files(path: &str, writable: bool) -> Self {
+        let shards = (0 .. existing_num_shards(path))
+            .map(|idx| <dyn Container>::from_file(&shard_path(path, idx), writable))
+            .collect();
+
+        Self::from_loaded_shards(path, shards)
+    }
+
+    /// Creates sharded container whose shard files other processes write into at the same time. Shard files are
+    /// created if they don't exist yet.
+    pub(crate) fn from_shared_files(container_details: ContainerDetails) -> Self {
+        let path = container_details.path.clone();
+        // Other processes may be creating the shard files right now, so the passed number of shards is trusted over
+        // the number of existing files.
+        let num_shards = match container_details.construction_details.shards {
+            1 => existing_num_shards(&path),
+            num_shards => num_shards,
+        };
+        let shards = (0 .. num_shards)
+            .map(|idx| <dyn Container>::from_shared_file(shard_details(&container_details, num_shards, idx)))
+            .collect();
+
+        Self::from_loaded_shards(&path, shards)
+    }
+
+    /// Creates sharded container from the shards loaded from the files. Details of the whole container are combined
+    /// from details of the shards.
+    fn from_loaded_shards(path: &str, mut shards: Vec<Box<dyn Container>>) -> Self {
+        let construction_details: Vec<ConstructionDetails> = shards.iter_mut().map(|shard| shard.get_container_details().construction_details).collect();
+
+        // Distinct lines of the whole container are stored in the first shard, but merging all of them costs little.
+        let mut hll = HyperLogLog::new();
+        for shard in shards.iter_mut() {
+            hll.merge(&shard.get_container_details().hll);
+        }
+
+        let first = shards[0].get_container_details();
+        let container_details = ContainerDetails {
+            path: path.to_string(),
+            construction_details: combine_construction_details(&construction_details),
+            data_source: first.data_source,
+            created_at: first.created_at,
+            expires_at: first.expires_at,
+            read_only: false,
+            hll,
+            partition: first.partition,
+            num_promotions: 0,
+        };
+
+        Self::from_shards(container_details, shards, false)
+    }
+
+    /// Creates sharded container from its details and shards. Shards are marked as modified if they have no files yet.
+    fn from_shards(container_details: ContainerDetails, shards: Vec<Box<dyn Container>>, dirty: bool) -> Self {
+        Self {
+            container_details,
+            shard_bits: shards.len().trailing_zeros(),
+            dirty: vec![dirty; shards.len()],
+            shards,
+            top_size: 0,
+        }
+    }
+
+    /// Combines each shard with the shard of the same index of the other container. Containers must have the same
+    /// number of shards, so each value belongs to the shards of the same index in both of them.
+    fn combine_shards<F>(&mut self, other: &dyn Container, mut combine: F) -> Result<(), String>
+        where F: FnMut(&mut Box<dyn Container>, &dyn Container) -> Result<(), String>
+    {
+        let other = other.downcast_ref::<ShardedContainer>().ok_or("only one of the containers is sharded")?;
+        if self.shards.len() != other.shards.len() {
+            return Err(format!("containers have different numbers of shards ({} and {})", self.shards.len(), other.shards.len()));
+        }
+
+        for (idx, (shard, other_shard)) in self.shards.iter_mut().zip(other.shards.iter()).enumerate() {
+            combine(shard, &**other_shard).map_err(|e| format!("shard {}: {}", idx, e))?;
+            self.dirty[idx] = true;
+        }
+
+        Ok(())
+    }
+
+    /// Copies details of the whole container which may have been changed (e.g. path of the moved window generation)
+    /// into the shards. Shards whose details changed are marked as modified.
+    fn sync_shard_details(&mut self) {
+        for (idx, (shard, dirty)) in self.shards.iter_mut().zip(self.dirty.iter_mut()).enumerate() {
+            let path = shard_path(&self.container_details.path, idx);
+            let details = shard.get_container_details();
+            if details.path != path || details.created_at != self.container_details.created_at || details.expires_at != self.container_details.expires_at {
+                details.path = path;
+                details.created_at = self.container_details.created_at;
+                details.expires_at = self.container_details.expires_at;
+                *dirty = true;
+            }
+        }
+
+        // Distinct lines of the whole container are stored in the first shard.
+        let first = self.shards[0].get_container_details();
+        let mut hll = HyperLogLog::from_registers(first.hll.registers().to_vec());
+        hll.merge(&self.container_details.hll);
+        if hll.registers() != first.hll.registers() {
+            first.hll = hll;
+            self.dirty[0] = true;
+        }
+    }
+}
+
+impl Container for ShardedContainer {
+    /// Inserts value into its shard.
+    fn set(&mut self, value: &str) {
+        self.container_details.hll.insert(value);
+        let idx = shard_idx(self.shard_bits, value);
+        self.shards[idx].set(value);
+        self.dirty[idx] = true;
+    }
+
+    /// Checks whether shard of the value could have it.
+    fn check(&self, value: &str) -> bool {
+        self.shards[shard_idx(self.shard_bits, value)].check(value)
+    }
+
+    /// Checks whether shard of the value could have it and if no, inserts the value. Returns true if value could have
+    /// existed.
+    fn check_and_set(&mut self, value: &str) -> bool {
+        self.container_details.hll.insert(value);
+        let idx = shard_idx(self.shard_bits, value);
+        let found = self.shards[idx].check_and_set(value);
+        // Time-aware shards refresh values even when they're found.
+        self.dirty[idx] |= !found || self.container_details.construction_details.ttl > 0;
+        found
+    }
+
+    /// Checks whether shards could have given values.
+    fn check_batch(&self, values: &[&str], found: &mut [bool]) {
+        for_each_shard_batch(self.shard_bits, values, found, |idx, values, found| self.shards[idx].check_batch(values, found));
+    }
+
+    /// Same as check_and_set() called for each of the values in order.
+    fn check_and_set_batch(&mut self, values: &[&str], found: &mut [bool]) {
+        let timed = self.container_details.construction_details.ttl > 0;
+        for value in values {
+            self.container_details.hll.insert(value);
+        }
+        for_each_shard_batch(self.shard_bits, values, found, |idx, values, found| {
+            self.shards[idx].check_and_set_batch(values, found);
+            self.dirty[idx] |= timed || found.iter().any(|found| !found);
+        });
+    }
+
+    /// Checks whether any of the shards is full, so no shard ever takes more values than it was sized for.
+    fn is_full(&self) -> bool {
+        self.shards.iter().any(|shard| shard.is_full())
+    }
+
+    /// Checks whether each shard has room for its values. Shard getting full makes the whole container full for the
+    /// values after it, so no shard may get full even with its last value.
+    fn has_room_for(&self, values: &[&str]) -> bool {
+        let mut shard_idxs: Vec<usize> = values.iter().map(|value| shard_idx(self.shard_bits, value)).collect();
+        shard_idxs.sort_unstable();
+
+        !self.is_full() && shard_idxs.chunk_by(|a, b| a == b).all(|values| {
+            let shard = &self.shards[values[0]];
+            shard.get_num_writes() + (values.len() as u64) < shard.get_num_max_writes()
+        })
+    }
+
+    /// Sets number of the most frequent values to track in each shard.
+    fn set_top_size(&mut self, size: usize) {
+        self.top_size = size;
+        for shard in self.shards.iter_mut() {
+            shard.set_top_size(size);
+        }
+    }
+
+    /// Returns the most frequent values of all the shards. Each value is tracked only by its own shard.
+    fn get_top(&self) -> Vec<(String, u64)> {
+        let mut top: Vec<(String, u64)> = self.shards.iter().flat_map(|shard| shard.get_top()).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(self.top_size);
+        top
+    }
+
+    /// Returns construction info used to create this container.
+    fn get_container_details(&mut self) -> &mut ContainerDetails {
+        &mut self.container_details
+    }
+
+    /// Returns fill percentage averaged over the shards.
+    fn get_usage(&self) -> f32 {
+        self.shards.iter().map(|shard| shard.get_usage()).sum::<f32>() / self.shards.len() as f32
+    }
+
+    /// Returns number of shards and how many of them were modified.
+    fn get_status(&self) -> Option<String> {
+        let num_dirty = self.dirty.iter().filter(|dirty| **dirty).count();
+        let status = format!("shards: {} ({} modified)", self.shards.len(), num_dirty);
+        // Status of the shards (e.g. phase of hybrid ones) is shown only if they have any.
+        match self.shards[0].get_status() {
+            Some(shard_status) => Some(format!("{}, first shard: {}", status, shard_status)),
+            None => Some(status),
+        }
+    }
+
+    /// Sets current time of all the shards.
+    fn set_time(&mut self, timestamp: u64) {
+        for shard in self.shards.iter_mut() {
+            shard.set_time(timestamp);
+        }
+    }
+
+    /// Returns estimated number of occurrences of the value in its shard.
+    fn count(&self, value: &str) -> u64 {
+        self.shards[shard_idx(self.shard_bits, value)].count(value)
+    }
+
+    /// Increments number of occurrences of the value in its shard. Returns estimated count before the increment.
+    fn count_and_set(&mut self, value: &str) -> u64 {
+        self.container_details.hll.insert(value);
+        let idx = shard_idx(self.shard_bits, value);
+        self.dirty[idx] = true;
+        self.shards[idx].count_and_set(value)
+    }
+
+    // Returns number of writes into all the shards.
+    fn get_num_writes(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.get_num_writes()).sum()
+    }
+
+    // Number of writes is kept by the shards, which read it from their own files.
+    fn set_num_writes(&mut self, _value: u64) {}
+
+    // Returns maximum number of allowed writes into all the shards.
+    fn get_num_max_writes(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.get_num_max_writes()).sum()
+    }
+
+    // Maximum number of writes is kept by the shards, which read it from their own files.
+    fn set_num_max_writes(&mut self, _value: u64) {}
+
+    /// Saves modified shards into their files. Shards which weren't modified are left as they are.
+    fn save(&mut self) {
+        self.sync_shard_details();
+
+        for (shard, dirty) in self.shards.iter_mut().zip(self.dirty.iter_mut()) {
+            if *dirty {
+                shard.save();
+                *dirty = false;
+            }
+        }
+    }
+
+    /// Content is stored only in the shard files.
+    fn save_content(&mut self, _file: &mut File) {}
+
+    /// Content is stored only in the shard files.
+    fn load_content(&mut self, _file: &mut File) {}
+
+    /// Merges each shard of the other container into the shard of the same index.
+    fn merge(&mut self, other: &dyn Container) -> Result<(), String> {
+        self.combine_shards(other, |shard, other_shard| shard.merge(other_shard))
+    }
+
+    /// Intersects each shard with the shard of the same index of the other container.
+    fn intersect(&mut self, other: &dyn Container) -> Result<(), String> {
+        self.combine_shards(other, |shard, other_shard| shard.intersect(other_shard))
+    }
+
+    /// Subtracts the shard of the same index of the other container from each shard.
+    fn subtract(&mut self, other: &dyn Container) -> Result<(), String> {
+        self.combine_shards(other, |shard, other_shard| shard.subtract(other_shard))
+    }
+
+    /// Sums numbers of values of the shards. Each lookup goes to one of the equally loaded shards, so false positive
+    /// rate is the average of the shards.
+    fn estimate_fill(&self) -> Option<FillEstimate> {
+        let estimates = self.shards.iter().map(|shard| shard.estimate_fill()).collect::<Option<Vec<FillEstimate>>>()?;
+        Some(FillEstimate {
+            num_values: estimates.iter().map(|estimate| estimate.num_values).sum(),
+            error_rate: estimates.iter().map(|estimate| estimate.error_rate).sum::<f64>() / estimates.len() as f64,
+        })
+    }
+}
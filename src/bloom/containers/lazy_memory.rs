@@ -0,0 +1,463 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+/// Number of bytes checked at once when skipping never-touched memory while saving and loading. Matches the usual page
+/// size, so untouched pages are neither committed nor written.
+const PAGE_SIZE: usize = 4096;
+
+/// Buffers smaller than this are allocated on the heap, as mapping them would waste most of the page.
+const MAP_THRESHOLD: usize = 1 << 20;
+
+/// Number of bytes written to the file at once.
+const WRITE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Word stored in the lazy memory. Words are saved in little endian, as all the other container data.
+pub(crate) trait Word: Copy + Default + PartialEq {
+    const SIZE: usize;
+
+    fn write_le(self, bytes: &mut Vec<u8>);
+
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_word {
+    ($($type:ty),*) => {$(
+        impl Word for $type {
+            const SIZE: usize = std::mem::size_of::<$type>();
+
+            fn write_le(self, bytes: &mut Vec<u8>) {
+                bytes.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read_le(bytes: &[u8]) -> Self {
+                Self::from_le_bytes(bytes.try_into().unwrap())
+            }
+        }
+    )*};
+}
+
+impl_word!(u8, u32, u64);
+
+/// How the container file is mapped.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum MapMode {
+    // Changes are kept in memory, so the file is never modified.
+    Private,
+    // Changes are written straight to the file.
+    Shared,
+    // Changes are written straight to the file, which other processes write into at the same time.
+    Concurrent,
+}
+
+/// Mapping of the container file. Whole file is mapped from its beginning, as mapping has to start at the page boundary,
+/// so the header is accessible too.
+pub(crate) struct FileMapping {
+    base: *mut u8,
+    map_len: usize,
+    path: String,
+    content_offset: u64, // Offset of the container content, i.e., size of the header.
+    words_offset: u64, // Offset of the words from the beginning of the content.
+    mode: MapMode,
+}
+
+impl FileMapping {
+    /// Returns path of the mapped file.
+    pub(crate) fn path(&self) -> &String {
+        &self.path
+    }
+
+    /// Returns offset of the container content in the file.
+    pub(crate) fn content_offset(&self) -> u64 {
+        self.content_offset
+    }
+
+    /// Checks whether other processes may write into the file at the same time.
+    pub(crate) fn is_concurrent(&self) -> bool {
+        self.mode == MapMode::Concurrent
+    }
+
+    /// Returns header word at given (8-byte aligned) offset, so it can be updated atomically.
+    pub(crate) fn header_u64(&self, offset: u64) -> &AtomicU64 {
+        assert!(offset.is_multiple_of(8) && offset + 8 <= self.content_offset);
+        unsafe { AtomicU64::from_ptr(self.base.add(offset as usize) as *mut u64) }
+    }
+
+    /// Returns header bytes at given offset, so they can be updated atomically.
+    pub(crate) fn header_bytes(&self, offset: u64, len: usize) -> &[AtomicU8] {
+        assert!(offset + len as u64 <= self.content_offset);
+        unsafe { std::slice::from_raw_parts(self.base.add(offset as usize) as *const AtomicU8, len) }
+    }
+
+    /// Flushes changes to the file. Only dirty pages are written.
+    pub(crate) fn flush(&self) {
+        #[cfg(unix)]
+        if unsafe { libc::msync(self.base as *mut libc::c_void, self.map_len, libc::MS_SYNC) } != 0 {
+            eprintln!("Error: Could not write changes to the file \"{}\"!", self.path);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Memory backing the words.
+enum Backing {
+    Heap,
+    Anonymous,
+    File(FileMapping),
+}
+
+/// Zero-initialized memory which is committed page by page on the first write, so physical footprint of the container
+/// grows with the actual use instead of its size. Large buffers are backed by anonymous memory mapping, smaller ones by
+/// the heap. Memory may be also replaced by the mapping of the container file, so only the accessed pages are read.
+pub(crate) struct LazyMemory<T: Word> {
+    ptr: *mut T,
+    len: usize,
+    backing: Backing,
+}
+
+// Memory is exclusively owned, like in Vec.
+unsafe impl<T: Word + Send> Send for LazyMemory<T> {}
+unsafe impl<T: Word + Sync> Sync for LazyMemory<T> {}
+
+impl<T: Word> LazyMemory<T> {
+    /// Reserves zeroed memory for given number of words. Nothing is committed until written.
+    pub(crate) fn new(len: usize) -> Self {
+        let num_bytes = len * T::SIZE;
+
+        #[cfg(unix)]
+        if num_bytes >= MAP_THRESHOLD {
+            // Pages of anonymous mapping are zero-filled by the kernel on first access. We don't reserve swap for them,
+            // as most of the pages of sparse containers are never touched.
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    num_bytes,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                eprintln!("Error: Could not reserve {} bytes of memory.", num_bytes);
+                std::process::exit(1);
+            }
+            return Self { ptr: ptr as *mut T, len, backing: Backing::Anonymous };
+        }
+
+        // Zeroed heap allocation of the vector is lazy on most platforms too, we just can't rely on it.
+        let mut vec = std::mem::ManuallyDrop::new(vec![T::default(); len].into_boxed_slice());
+        Self { ptr: vec.as_mut_ptr(), len, backing: Backing::Heap }
+    }
+
+    /// Replaces the memory with the mapping of the file whose words start at given offset from the beginning of the
+    /// content. Returns false if file can't be mapped, and so words have to be loaded.
+    #[cfg(unix)]
+    pub(crate) fn map(&mut self, file: &File, path: &str, content_offset: u64, words_offset: u64, mode: MapMode) -> bool {
+        let offset = (content_offset + words_offset) as usize;
+        let map_len = offset + self.len * T::SIZE;
+
+        // Words are stored in little endian, so they can be used in place only on little endian machines.
+        if self.len == 0 || (T::SIZE > 1 && cfg!(target_endian = "big")) {
+            return false;
+        }
+        if file.metadata().map(|metadata| metadata.len() < map_len as u64).unwrap_or(true) {
+            // Truncated file, loading will zero the missing words.
+            return false;
+        }
+
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                if mode == MapMode::Private { libc::MAP_PRIVATE } else { libc::MAP_SHARED },
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return false;
+        }
+
+        let ptr = unsafe { (base as *mut u8).add(offset) } as *mut T;
+        if !ptr.is_aligned() {
+            unsafe { libc::munmap(base, map_len) };
+            return false;
+        }
+
+        self.release();
+        self.ptr = ptr;
+        self.backing = Backing::File(FileMapping {
+            base: base as *mut u8,
+            map_len,
+            path: path.to_string(),
+            content_offset,
+            words_offset,
+            mode,
+        });
+        true
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn map(&mut self, _file: &File, _path: &str, _content_offset: u64, _words_offset: u64, _mode: MapMode) -> bool {
+        false
+    }
+
+    /// Returns number of words.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks whether other processes may write into the memory at the same time.
+    fn is_concurrent(&self) -> bool {
+        matches!(&self.backing, Backing::File(mapping) if mapping.is_concurrent())
+    }
+
+    /// Returns mapping of the file if writes go straight to the file.
+    pub(crate) fn mapped_file(&self) -> Option<&FileMapping> {
+        match &self.backing {
+            Backing::File(mapping) if mapping.mode != MapMode::Private => Some(mapping),
+            _ => None,
+        }
+    }
+
+    /// Flushes words written through the mapping to the file. File position is left after the words, as if they were
+    /// saved.
+    pub(crate) fn sync(&self, file: &mut File) {
+        let mapping = self.mapped_file().expect("Internal Error: Memory is not shared with the file.");
+        mapping.flush();
+        file.seek(SeekFrom::Start(mapping.content_offset + mapping.words_offset + (self.len * T::SIZE) as u64)).unwrap();
+    }
+
+    /// Zeroes all the words. Anonymously mapped pages are released back to the system.
+    pub(crate) fn clear(&mut self) {
+        #[cfg(target_os = "linux")]
+        if matches!(self.backing, Backing::Anonymous) {
+            // Private anonymous pages read as zeros after being dropped.
+            unsafe { libc::madvise(self.ptr as *mut libc::c_void, self.len * T::SIZE, libc::MADV_DONTNEED) };
+            return;
+        }
+
+        self.fill(T::default());
+    }
+
+    /// Writes words to the file, skipping pages which were never written (or are zeroed). Skipped pages are left as holes,
+    /// so file takes disk space only for the used part of the container.
+    pub(crate) fn save(&self, file: &mut File) {
+        let mut buffer = Vec::with_capacity(WRITE_BUFFER_SIZE + PAGE_SIZE);
+        let mut num_skipped: i64 = 0;
+
+        for page in self.chunks(PAGE_SIZE / T::SIZE) {
+            if page.iter().all(|word| *word == T::default()) {
+                file.write_all(&buffer).unwrap();
+                buffer.clear();
+                num_skipped += (page.len() * T::SIZE) as i64;
+                continue;
+            }
+
+            if num_skipped > 0 {
+                file.seek(SeekFrom::Current(num_skipped)).unwrap();
+                num_skipped = 0;
+            }
+
+            for word in page {
+                word.write_le(&mut buffer);
+            }
+
+            if buffer.len() >= WRITE_BUFFER_SIZE {
+                file.write_all(&buffer).unwrap();
+                buffer.clear();
+            }
+        }
+
+        file.write_all(&buffer).unwrap();
+
+        if num_skipped > 0 {
+            // Trailing hole has to be made by extending the file, as seeking alone doesn't change its length.
+            let end = file.seek(SeekFrom::Current(num_skipped)).unwrap();
+            file.set_len(end).unwrap();
+        }
+    }
+
+    /// Reads words saved by save(). Zeroed pages are not copied, so they stay uncommitted. Words missing at the end of
+    /// the file are left zeroed.
+    pub(crate) fn load(&mut self, reader: &mut impl Read) {
+        let mut bytes = vec![0; PAGE_SIZE];
+
+        for page in self.chunks_mut(PAGE_SIZE / T::SIZE) {
+            let num_bytes = page.len() * T::SIZE;
+            if !read_up_to(reader, &mut bytes[.. num_bytes]) {
+                break;
+            }
+
+            if bytes[.. num_bytes].iter().all(|byte| *byte == 0) {
+                continue;
+            }
+
+            for (word, word_bytes) in page.iter_mut().zip(bytes.chunks(T::SIZE)) {
+                *word = T::read_le(word_bytes);
+            }
+        }
+    }
+}
+
+/// Fills buffer from the reader. Returns false if the end of the reader was reached before anything was read. Partially
+/// read buffer is zero-padded.
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> bool {
+    let mut num_read = 0;
+    while num_read < buffer.len() {
+        match reader.read(&mut buffer[num_read ..]) {
+            Ok(0) => break,
+            Ok(num) => num_read += num,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => panic!("{}", error),
+        }
+    }
+    buffer[num_read ..].fill(0);
+    num_read > 0
+}
+
+// Words of the memory shared with other processes are accessed only through the atomic accessors, as the plain slice
+// would be a data race. Accessors writing the words borrow the memory mutably, so they never alias a slice either.
+impl<T: Word> Deref for LazyMemory<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        debug_assert!(!self.is_concurrent(), "Internal Error: Memory shared with other processes is accessed as a slice.");
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Word> DerefMut for LazyMemory<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        debug_assert!(!self.is_concurrent(), "Internal Error: Memory shared with other processes is accessed as a slice.");
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl LazyMemory<u8> {
+    /// Returns the bytes as atomics, so they can be accessed while other processes write them at the same time.
+    fn atomic_bytes(&self) -> &[AtomicU8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const AtomicU8, self.len) }
+    }
+
+    /// Returns byte of given index. Byte is read atomically, as other processes may write it at the same time.
+    pub(crate) fn load_byte(&self, idx: usize) -> u8 {
+        self.atomic_bytes()[idx].load(Ordering::Relaxed)
+    }
+
+    /// Sets bits of the byte of given index atomically. Returns the previous value of the byte.
+    pub(crate) fn fetch_or_byte(&mut self, idx: usize, mask: u8) -> u8 {
+        self.atomic_bytes()[idx].fetch_or(mask, Ordering::Relaxed)
+    }
+
+    /// Clears bits of the byte of given index which are not in the mask atomically. Returns the previous value of the
+    /// byte.
+    pub(crate) fn fetch_and_byte(&mut self, idx: usize, mask: u8) -> u8 {
+        self.atomic_bytes()[idx].fetch_and(mask, Ordering::Relaxed)
+    }
+
+    /// Returns little endian word starting at the byte of given index (multiple of 8). Word is read atomically, as other
+    /// processes may write it at the same time. Word which is not aligned in memory is read byte by byte.
+    pub(crate) fn load_u64(&self, idx: usize) -> u64 {
+        assert!(idx.is_multiple_of(8) && idx + 8 <= self.len);
+        let ptr = unsafe { self.ptr.add(idx) } as *mut u64;
+        if !ptr.is_aligned() {
+            let bytes = &self.atomic_bytes()[idx .. idx + 8];
+            return u64::from_le_bytes(std::array::from_fn(|byte_idx| bytes[byte_idx].load(Ordering::Relaxed)));
+        }
+        u64::from_le(unsafe { AtomicU64::from_ptr(ptr) }.load(Ordering::Relaxed))
+    }
+
+    /// Writes little endian word starting at the byte of given index (multiple of 8). Word is written atomically, so
+    /// processes reading it at the same time never see it torn. Word which is not aligned in memory is written byte by
+    /// byte.
+    pub(crate) fn store_u64(&mut self, idx: usize, value: u64) {
+        assert!(idx.is_multiple_of(8) && idx + 8 <= self.len);
+        let ptr = unsafe { self.ptr.add(idx) } as *mut u64;
+        if !ptr.is_aligned() {
+            for (byte, value) in self.atomic_bytes()[idx .. idx + 8].iter().zip(value.to_le_bytes()) {
+                byte.store(value, Ordering::Relaxed);
+            }
+            return;
+        }
+        unsafe { AtomicU64::from_ptr(ptr) }.store(value.to_le(), Ordering::Relaxed);
+    }
+
+    /// Replaces little endian word starting at the byte of given index (multiple of 8) atomically, if it still has the
+    /// current value. Returns the previous value of the word, as AtomicU64::compare_exchange() does. Word has to be
+    /// aligned in memory, which holds for the file mappings, as content starts at 8-byte aligned offset.
+    pub(crate) fn compare_exchange_u64(&mut self, idx: usize, current: u64, new: u64) -> Result<u64, u64> {
+        assert!(idx.is_multiple_of(8) && idx + 8 <= self.len);
+        let ptr = unsafe { self.ptr.add(idx) } as *mut u64;
+        assert!(ptr.is_aligned(), "Internal Error: Word is not aligned in memory.");
+        unsafe { AtomicU64::from_ptr(ptr) }
+            .compare_exchange(current.to_le(), new.to_le(), Ordering::Relaxed, Ordering::Relaxed)
+            .map(u64::from_le)
+            .map_err(u64::from_le)
+    }
+}
+
+impl<T: Word> LazyMemory<T> {
+    /// Hints the processor to load the word of given index into the cache, so it's read without waiting for the memory
+    /// later. Does nothing on other architectures than x86-64.
+    pub(crate) fn prefetch(&self, idx: usize) {
+        if idx < self.len {
+            #[cfg(target_arch = "x86_64")]
+            unsafe { std::arch::x86_64::_mm_prefetch(self.ptr.add(idx) as *const i8, std::arch::x86_64::_MM_HINT_T0) };
+        }
+    }
+
+    /// Frees the current memory.
+    fn release(&mut self) {
+        match self.backing {
+            Backing::Heap => drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.ptr, self.len)) }),
+            #[cfg(unix)]
+            Backing::Anonymous => unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len * T::SIZE); },
+            #[cfg(unix)]
+            Backing::File(ref mapping) => unsafe { libc::munmap(mapping.base as *mut libc::c_void, mapping.map_len); },
+            #[cfg(not(unix))]
+            _ => {}
+        }
+    }
+}
+
+impl<T: Word> Drop for LazyMemory<T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_skip_zeroed_pages() {
+        let path = std::env::temp_dir().join(format!("bloom_lazy_memory_{}.bin", std::process::id()));
+
+        // Large enough to be mapped, with written words at the start, in the middle and at the very end.
+        let mut memory: LazyMemory<u32> = LazyMemory::new(1 << 20);
+        memory[1] = 1;
+        memory[300000] = u32::MAX;
+        memory[(1 << 20) - 1] = 7;
+
+        let mut file = File::create(&path).unwrap();
+        memory.save(&mut file);
+        drop(file);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4 << 20);
+
+        let mut loaded: LazyMemory<u32> = LazyMemory::new(1 << 20);
+        loaded.load(&mut File::open(&path).unwrap());
+        assert!(memory.iter().eq(loaded.iter()));
+
+        loaded.clear();
+        assert!(loaded.iter().all(|word| *word == 0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,9 @@
 pub mod container;
 pub mod container_memory_bloom;
+pub mod container_memory_count_min;
+pub mod container_memory_exact;
+pub mod container_memory_hybrid;
+pub mod container_memory_timed;
 pub mod container_memory_xxh;
+pub mod container_sharded;
+pub mod lazy_memory;
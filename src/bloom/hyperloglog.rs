@@ -0,0 +1,123 @@
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// Number of bits of the hash used to select the register. Gives 4096 registers and standard error of about 1.6%.
+pub const PRECISION: u8 = 12;
+
+/// Number of registers.
+const NUM_REGISTERS: usize = 1 << PRECISION;
+
+/// Seed of the hash, so registers don't correlate with slots of the containers which use xxh3 without a seed.
+const SEED: u64 = 0x48_4C_4C;
+
+/// HyperLogLog estimating number of distinct values. Registers are allocated on the first insert, so unused instances
+/// (e.g. inner containers of the hybrid one) cost nothing.
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Creates empty estimator.
+    pub fn new() -> Self {
+        Self { registers: vec![] }
+    }
+
+    /// Creates empty estimator with already allocated registers, so they're stored in the file even before the first
+    /// insert.
+    pub fn new_allocated() -> Self {
+        Self { registers: vec![0; NUM_REGISTERS] }
+    }
+
+    /// Creates estimator from registers stored in the file. Registers of other precision are ignored.
+    pub fn from_registers(registers: Vec<u8>) -> Self {
+        if registers.len() != NUM_REGISTERS {
+            return Self::new();
+        }
+        Self { registers }
+    }
+
+    /// Returns registers to be stored in the file (empty if nothing was inserted).
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Adds value to the estimator.
+    pub fn insert(&mut self, value: &str) {
+        if self.registers.is_empty() {
+            self.registers = vec![0; NUM_REGISTERS];
+        }
+
+        let hash = xxh3_64_with_seed(value.as_bytes(), SEED);
+        let register_idx = (hash >> (64 - PRECISION)) as usize;
+        // Position of the first set bit in the remaining bits. Sentinel bit limits it when all of them are zero.
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+
+        if rank > self.registers[register_idx] {
+            self.registers[register_idx] = rank;
+        }
+    }
+
+    /// Adds values of another estimator, so the result estimates number of distinct values in both of them.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        if other.registers.is_empty() {
+            return;
+        }
+        if self.registers.is_empty() {
+            self.registers = vec![0; NUM_REGISTERS];
+        }
+        for (register, other_register) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*other_register);
+        }
+    }
+
+    /// Returns estimated number of distinct values.
+    pub fn estimate(&self) -> u64 {
+        if self.registers.is_empty() {
+            return 0;
+        }
+
+        let m = NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|register| 2f64.powi(-(*register as i32))).sum();
+        let estimate = alpha * m * m / sum;
+
+        let num_zeros = self.registers.iter().filter(|register| **register == 0).count();
+        if estimate <= 2.5 * m && num_zeros > 0 {
+            // Linear counting is more accurate for small cardinalities.
+            return (m * (m / num_zeros as f64).ln()).round() as u64;
+        }
+
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_is_close_to_real_count() {
+        for num_values in [0u64, 10, 1000, 100000] {
+            let mut hll = HyperLogLog::new();
+            for i in 0 .. num_values {
+                // Repeated values don't change the estimate.
+                hll.insert(&i.to_string());
+                hll.insert(&i.to_string());
+            }
+            let error = (hll.estimate() as f64 - num_values as f64).abs();
+            assert!(error <= num_values as f64 * 0.05, "Estimate {} is too far from {}", hll.estimate(), num_values);
+        }
+    }
+
+    #[test]
+    fn test_merge_estimates_union() {
+        let mut first = HyperLogLog::new();
+        let mut second = HyperLogLog::new();
+        for i in 0 .. 20000 {
+            first.insert(&i.to_string());
+            second.insert(&(i + 10000).to_string());
+        }
+        first.merge(&second);
+        let error = (first.estimate() as f64 - 30000.0).abs();
+        assert!(error <= 30000.0 * 0.05, "Estimate {} is too far from 30000", first.estimate());
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+/// Seed of the hash selecting the partition, so partitions don't correlate with slots of the containers or shards.
+const SEED: u64 = 0x50_52_54_4E;
+
+/// Part of the hash range processed by this instance, when the same input is split between several of them. Index is
+/// 1-based, so partitions of the input split in 4 are 1/4, 2/4, 3/4 and 4/4.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Partition {
+    pub idx: u32,
+    pub count: u32,
+}
+
+impl Partition {
+    /// Parses partition given as "I/N", where I is between 1 and N.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (idx, count) = value.split_once('/')?;
+        let idx: u32 = idx.parse().ok()?;
+        let count: u32 = count.parse().ok()?;
+
+        (idx >= 1 && idx <= count).then_some(Self { idx, count })
+    }
+
+    /// Checks whether hash of the value falls into this partition. Hash range is split into count equal parts.
+    pub fn contains(&self, value: &[u8]) -> bool {
+        let hash = xxh3_64_with_seed(value, SEED);
+        ((hash as u128 * self.count as u128) >> 64) as u32 + 1 == self.idx
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.idx, self.count)
+    }
+}
+
+/// Returns partition as shown in messages, "none" for containers of the whole input.
+pub fn partition_name(partition: Option<Partition>) -> String {
+    partition.map_or_else(|| "none".to_string(), |partition| partition.to_string())
+}
+
+/// What to do with lines of other partitions.
+pub enum ForeignLines {
+    // Lines are skipped (default).
+    Drop,
+    // Lines are printed without being looked up.
+    Pass,
+    // Lines are written into the file without being looked up.
+    File(BufWriter<File>),
+}
+
+impl ForeignLines {
+    /// Handles line of other partition. Lines which are passed through are written into stdout.
+    pub fn write_line<W: Write>(&mut self, line: &[u8], stdout: &mut W) {
+        match self {
+            ForeignLines::Drop => {}
+            ForeignLines::Pass => {
+                stdout.write_all(line).unwrap();
+                stdout.write_all(b"\n").unwrap();
+            }
+            ForeignLines::File(file) => {
+                file.write_all(line).unwrap();
+                file.write_all(b"\n").unwrap();
+            }
+        }
+    }
+
+    /// Flushes the file lines of other partitions are written into.
+    pub fn flush(&mut self) {
+        if let ForeignLines::File(file) = self {
+            file.flush().unwrap_or_else(|e| {
+                eprintln!("Error: Could not write lines of other partitions: {}", e);
+                std::process::exit(1);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partition() {
+        assert!(Partition::parse("1/4") == Some(Partition { idx: 1, count: 4 }));
+        assert!(Partition::parse("4/4") == Some(Partition { idx: 4, count: 4 }));
+        assert!(Partition::parse("0/4").is_none());
+        assert!(Partition::parse("5/4").is_none());
+        assert!(Partition::parse("1").is_none());
+    }
+
+    #[test]
+    fn test_each_value_belongs_to_one_partition() {
+        let partitions: Vec<Partition> = (1 ..= 3).map(|idx| Partition { idx, count: 3 }).collect();
+        let mut sizes = [0; 3];
+
+        for i in 0 .. 3000 {
+            let value = i.to_string();
+            let owners: Vec<usize> = (0 .. 3).filter(|idx| partitions[*idx].contains(value.as_bytes())).collect();
+            assert_eq!(owners.len(), 1);
+            sizes[owners[0]] += 1;
+        }
+
+        // Partitions are roughly equal.
+        assert!(sizes.iter().all(|size| (900 .. 1100).contains(size)), "Unbalanced partitions: {:?}", sizes);
+    }
+}
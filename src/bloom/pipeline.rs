@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::io::{self, stdin, BufWriter, ErrorKind, Read, StdoutLock, Write};
+use std::ops::Range;
+use std::sync::mpsc::{sync_channel, Receiver, TryRecvError};
+use std::thread;
+use crate::{OnFullPolicy, Params};
+use crate::bloom::containers::container::Container;
+use crate::bloom::partition::Partition;
+use crate::bloom::process::{check_block, process_block, write_foreign_line, LookedUp, BLOCK_SIZE};
+use crate::bloom::rotation::rotate_if_needed;
+
+/// Number of bytes read at once. Chunks end on a newline, so they may be a bit longer.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Line of the chunk with the result of its lookup.
+struct Line {
+    range: Range<usize>, // Position of the line in the chunk, without the newline.
+    text: bool, // Whether line is valid UTF-8. Other lines are only printed.
+    foreign: bool, // Whether line belongs to other partition. Such lines are never looked up.
+    found_in: Option<usize>, // Index of the first container which could have the line.
+}
+
+/// Newline-aligned part of the input.
+struct Chunk {
+    file_range: Option<Range<u64>>, // Part of the file still to be read by the worker.
+    data: Vec<u8>,
+    lines: Vec<Line>,
+}
+
+/// Source of the chunks.
+enum Input {
+    // Regular file read in parallel by the workers, with offset of the next chunk and size of the file.
+    File(File, u64, u64),
+    // Pipe or terminal read sequentially by the reader thread.
+    Stream(Receiver<io::Result<Vec<u8>>>),
+}
+
+/// Checks whether lines may be looked up in parallel. Containers mustn't forget lines or get replaced while processing
+/// and lines mustn't depend on the previous ones, so lookups done ahead stay valid.
+pub fn supports_parallel(params: &Params) -> bool {
+    !params.time_aware && params.ttl_field.is_none() && !params.estimate_only && !params.needs_counts() && params.max_occurrences.is_none()
+        && !matches!(params.on_full, OnFullPolicy::Evict | OnFullPolicy::Slide)
+}
+
+/// Processes the input in params.threads threads. Chunks of the input are split into lines and looked up in the
+/// containers by the workers, then written into containers and printed in input order, so the output is the same as
+/// when processed line by line.
+pub fn process_parallel(params: &mut Params, curr_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) {
+    let mut input = open_input(params.threads);
+
+    while let Some(mut chunks) = next_chunks(&mut input, params) {
+        // Containers are replaced only between batches, so they stay the same while looked up and written.
+        rotate_if_needed(params, curr_container_idx);
+
+        // Containers are only read by the workers, so lookups of all the chunks run at the same time.
+        let num_containers = params.containers.len();
+        let containers = &params.containers;
+        let partition = params.partition;
+        let file = match &input {
+            Input::File(file, _, _) => Some(file),
+            Input::Stream(_) => None,
+        };
+        thread::scope(|scope| {
+            for chunk in chunks.iter_mut() {
+                scope.spawn(move || look_up(chunk, containers, partition, file));
+            }
+        });
+
+        // Containers before the writable one aren't written, so lines looked up in them stay valid.
+        let mut num_unchanged = if params.write_mode { *curr_container_idx } else { num_containers };
+        for chunk in chunks.iter() {
+            if !commit(chunk, num_containers, &mut num_unchanged, params, curr_container_idx, stdout_lock) {
+                return;
+            }
+        }
+    }
+}
+
+/// Uses standard input as a file if it's a regular one, so it may be read in parallel. Otherwise, starts the reader
+/// thread.
+fn open_input(threads: usize) -> Input {
+    #[cfg(unix)]
+    {
+        use std::io::Seek;
+        use std::os::fd::AsFd;
+
+        if let Ok(fd) = stdin().as_fd().try_clone_to_owned() {
+            let mut file = File::from(fd);
+            // Part of the input may have been already read by another process.
+            if let (Ok(metadata), Ok(offset)) = (file.metadata(), file.stream_position()) {
+                if metadata.is_file() {
+                    return Input::File(file, offset, metadata.len());
+                }
+            }
+        }
+    }
+
+    Input::Stream(spawn_reader(threads * 2))
+}
+
+/// Returns next chunks of the input, one for each thread, or None at the end of the input.
+fn next_chunks(input: &mut Input, params: &Params) -> Option<Vec<Chunk>> {
+    let mut chunks = vec![];
+
+    match input {
+        Input::File(file, offset, size) => {
+            while chunks.len() < params.threads {
+                match next_file_range(file, offset, *size) {
+                    Some(file_range) => chunks.push(Chunk { file_range: Some(file_range), data: vec![], lines: vec![] }),
+                    None => break,
+                }
+            }
+        }
+        Input::Stream(receiver) => {
+            while chunks.len() < params.threads {
+                // Waiting only for the first chunk, so lines are not held back when the input is slow.
+                let received = if chunks.is_empty() {
+                    receiver.recv().map_err(|_| TryRecvError::Disconnected)
+                } else {
+                    receiver.try_recv()
+                };
+                match received {
+                    Ok(Ok(data)) => chunks.push(Chunk { file_range: None, data, lines: vec![] }),
+                    Ok(Err(e)) => {
+                        if !params.silent_warnings {
+                            eprintln!("Error reading input: {}", e);
+                        }
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    (!chunks.is_empty()).then_some(chunks)
+}
+
+/// Returns range of the next chunk of the file. Chunk is extended up to the end of the line it would cut.
+fn next_file_range(file: &File, offset: &mut u64, size: u64) -> Option<Range<u64>> {
+    if *offset >= size {
+        return None;
+    }
+
+    let start = *offset;
+    let mut end = size;
+    let mut pos = start + CHUNK_SIZE as u64 - 1;
+    let mut buf = [0u8; 4096];
+    while pos < size {
+        let num_read = read_at(file, &mut buf, pos);
+        if num_read == 0 {
+            break;
+        }
+        if let Some(newline_idx) = buf[.. num_read].iter().position(|byte| *byte == b'\n') {
+            end = pos + newline_idx as u64 + 1;
+            break;
+        }
+        pos += num_read as u64;
+    }
+
+    *offset = end;
+    Some(start .. end)
+}
+
+/// Reads bytes of the file at given offset. Returns number of bytes read.
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> usize {
+    #[cfg(unix)]
+    let result = std::os::unix::fs::FileExt::read_at(file, buf, offset);
+    #[cfg(not(unix))]
+    let result: io::Result<usize> = unreachable!("Standard input is only read as a file on Unix.");
+
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: Could not read the input: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Starts the thread reading standard input into chunks ending on a newline. At most capacity chunks wait for the
+/// workers.
+fn spawn_reader(capacity: usize) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = sync_channel(capacity);
+
+    // Thread isn't joined, as it may wait for the input after we stop reading it.
+    thread::spawn(move || {
+        let mut stdin = stdin().lock();
+        let mut rest = vec![];
+
+        loop {
+            let mut data: Vec<u8> = std::mem::take(&mut rest);
+            // Whatever is available is taken, unless it doesn't complete any line.
+            let num_read = loop {
+                let len = data.len();
+                data.resize(len + CHUNK_SIZE, 0);
+                match stdin.read(&mut data[len ..]) {
+                    Ok(num_read) => {
+                        data.truncate(len + num_read);
+                        if num_read == 0 || data[len ..].contains(&b'\n') {
+                            break num_read;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => data.truncate(len),
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                }
+            };
+
+            if num_read == 0 {
+                // The last line may not end with a newline.
+                if !data.is_empty() {
+                    let _ = sender.send(Ok(data));
+                }
+                return;
+            }
+
+            // Part of the line after the last newline is completed by the next read.
+            let end = data.iter().rposition(|byte| *byte == b'\n').unwrap() + 1;
+            rest = data.split_off(end);
+            if sender.send(Ok(data)).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Reads the chunk (if it's a part of the file), splits it into lines and looks lines of the partition up in the
+/// containers.
+fn look_up(chunk: &mut Chunk, containers: &[Box<dyn Container>], partition: Option<Partition>, file: Option<&File>) {
+    if let (Some(file_range), Some(file)) = (chunk.file_range.take(), file) {
+        chunk.data = vec![0; (file_range.end - file_range.start) as usize];
+        let mut len = 0;
+        while len < chunk.data.len() {
+            let num_read = read_at(file, &mut chunk.data[len ..], file_range.start + len as u64);
+            if num_read == 0 {
+                // File was truncated in the meantime.
+                chunk.data.truncate(len);
+                break;
+            }
+            len += num_read;
+        }
+    }
+
+    let mut start = 0;
+    while start < chunk.data.len() {
+        let end = chunk.data[start ..].iter().position(|byte| *byte == b'\n').map_or(chunk.data.len(), |idx| start + idx);
+        let text = std::str::from_utf8(&chunk.data[start .. end]).is_ok();
+        let foreign = partition.is_some_and(|partition| !partition.contains(&chunk.data[start .. end]));
+        chunk.lines.push(Line { range: start .. end, text, foreign, found_in: None });
+        start = end + 1;
+    }
+
+    // Lines are looked up in blocks, so their positions in the containers are prefetched.
+    for lines in chunk.lines.chunks_mut(BLOCK_SIZE) {
+        let mut values = [""; BLOCK_SIZE];
+        for (value, line) in values.iter_mut().zip(lines.iter()) {
+            if line.text && !line.foreign {
+                // SAFETY: Line was validated above.
+                *value = unsafe { std::str::from_utf8_unchecked(&chunk.data[line.range.clone()]) };
+            }
+        }
+
+        let mut found_in = [None; BLOCK_SIZE];
+        check_block(&values[.. lines.len()], containers, 0, &mut found_in);
+        for (line, found_in) in lines.iter_mut().zip(found_in) {
+            // Lines which aren't valid UTF-8 or belong to other partitions were looked up as empty ones, so their results
+            // are ignored.
+            line.found_in = found_in.filter(|_| line.text && !line.foreign);
+        }
+    }
+}
+
+/// Writes lines of the chunk into the containers and prints them in input order. Lines are processed in blocks using
+/// the lookups of the workers, so they're looked up again only in the containers written since (tracked by
+/// num_unchanged). Returns false if we should stop reading the input.
+fn commit(chunk: &Chunk, num_containers: usize, num_unchanged: &mut usize, params: &mut Params, curr_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) -> bool {
+    let mut lines = [""; BLOCK_SIZE];
+    let mut looked_up = LookedUp { found_in: [None; BLOCK_SIZE], num_containers, num_unchanged: *num_unchanged };
+    let mut num_lines = 0;
+
+    for (idx, line) in chunk.lines.iter().enumerate() {
+        let bytes = &chunk.data[line.range.clone()];
+
+        // Valid lines are processed together, lines which aren't valid UTF-8 or belong to other partitions split them.
+        if line.text && !line.foreign {
+            // SAFETY: Line was validated by the worker.
+            lines[num_lines] = unsafe { std::str::from_utf8_unchecked(bytes) };
+            looked_up.found_in[num_lines] = line.found_in;
+            num_lines += 1;
+            if num_lines < BLOCK_SIZE && idx + 1 < chunk.lines.len() {
+                continue;
+            }
+        }
+
+        if num_lines > 0 {
+            let curr_idx = *curr_container_idx;
+            looked_up.num_unchanged = *num_unchanged;
+            if !process_block(&lines[.. num_lines], &looked_up, params, curr_container_idx, stdout_lock) {
+                return false;
+            }
+            if params.write_mode {
+                // Lines may have been written into the writable container and the ones which replaced it.
+                *num_unchanged = (*num_unchanged).min(curr_idx);
+            }
+            num_lines = 0;
+        }
+
+        if line.foreign {
+            write_foreign_line(bytes, params, stdout_lock);
+        }
+        else if !line.text {
+            // Invalid UTF-8 is printed as it is.
+            stdout_lock.write_all(bytes).unwrap();
+            stdout_lock.write_all(b"\n").unwrap();
+            if params.line_buffered {
+                stdout_lock.flush().unwrap();
+            }
+        }
+    }
+
+    true
+}
@@ -0,0 +1,221 @@
+use parse_size::parse_size;
+use crate::bloom::containers::container_memory_exact::MemoryContainerExact;
+use crate::bloom::containers::container_memory_hybrid::MemoryContainerHybrid;
+use crate::bloom::containers::container_memory_xxh::{MemoryContainerXXH, KEY_BITS};
+use crate::bloom::sizing::{bloom_error_rate, bloom_num_hashes, bloom_size_for_error_rate, exact_error_rate, format_size, xxh_error_rate, xxh_size_for_error_rate};
+
+/// Target of the plan: either false positive rate or memory size.
+enum PlanTarget {
+    ErrorRate(f64),
+    Memory(u64),
+}
+
+/// Single row of the plan.
+struct PlanRow {
+    kind: &'static str,
+    // Size in bytes, None if container can't satisfy the target.
+    size: Option<u64>,
+    parameters: String,
+    error_rate: Option<f64>,
+    lookup_cost: String,
+    arguments: String,
+}
+
+fn print_plan_help() {
+    println!("USAGE:");
+    println!("  bloom plan --items NUM --fpr RATE");
+    println!("  bloom plan --items NUM --memory SIZE");
+    println!();
+    println!("Prints parameters of each container type holding NUM lines with given false positive rate or memory size,");
+    println!("together with ready to paste command line arguments.");
+}
+
+/// Runs "bloom plan" subcommand with given arguments (following the "plan" one).
+pub fn plan(args: &[String]) {
+    let mut items: Option<u64> = None;
+    let mut target: Option<PlanTarget> = None;
+
+    let mut idx = 0;
+    while idx < args.len() {
+        let value = args.get(idx + 1);
+        match args[idx].as_str() {
+            "--items" => {
+                items = Some(value.and_then(|value| parse_size(value).ok()).filter(|items| *items > 0).unwrap_or_else(|| {
+                    eprintln!("Error: --items expects number of lines greater than 0.");
+                    std::process::exit(1);
+                }));
+            }
+            "--fpr" => {
+                let error_rate = value.and_then(|value| value.parse().ok()).filter(|error_rate| *error_rate > 0.0 && *error_rate < 1.0).unwrap_or_else(|| {
+                    eprintln!("Error: --fpr expects false positive rate (> 0 and < 1).");
+                    std::process::exit(1);
+                });
+                target = Some(PlanTarget::ErrorRate(error_rate));
+            }
+            "--memory" => {
+                let size = value.and_then(|value| parse_size(value).ok()).filter(|size| *size > 0).unwrap_or_else(|| {
+                    eprintln!("Error: --memory expects size greater than 0 in bytes or given unit.");
+                    std::process::exit(1);
+                });
+                target = Some(PlanTarget::Memory(size));
+            }
+            "-h" | "--help" => {
+                print_plan_help();
+                std::process::exit(0);
+            }
+            arg => {
+                eprintln!("Error: Invalid parameter passed to plan: \"{}\".", arg);
+                std::process::exit(1);
+            }
+        }
+        idx += 2;
+    }
+
+    let (items, target) = match (items, target) {
+        (Some(items), Some(target)) => (items, target),
+        _ => {
+            eprintln!("Error: plan expects --items and either --fpr or --memory parameters.");
+            std::process::exit(1);
+        }
+    };
+
+    // Exits if even the exact container of 128-bit digests can't hold that many lines, as all the rows would be useless.
+    MemoryContainerExact::calc_size_for_limit(items, 2);
+
+    let rows = match target {
+        PlanTarget::ErrorRate(error_rate) => {
+            println!("Plan for {} lines with false positive rate {}:", items, error_rate);
+            plan_for_error_rate(items, error_rate)
+        }
+        PlanTarget::Memory(size) => {
+            println!("Plan for {} lines in {}:", items, format_size(size));
+            plan_for_memory(items, size)
+        }
+    };
+
+    println!();
+    println!("{:<10} {:>12} {:<28} {:>10} {:<24} ARGUMENTS", "TYPE", "SIZE", "PARAMETERS", "FP RATE", "LOOKUP COST");
+    for row in rows {
+        println!(
+            "{:<10} {:>12} {:<28} {:>10} {:<24} {}",
+            row.kind,
+            row.size.map(format_size).unwrap_or("-".to_string()),
+            row.parameters,
+            row.error_rate.map(|error_rate| format!("{:.1e}", error_rate)).unwrap_or("-".to_string()),
+            row.lookup_cost,
+            row.arguments
+        );
+    }
+}
+
+/// Returns rows of the exact container for both digest widths. Exact containers size is derived only from the limit.
+fn exact_rows(items: u64, memory: Option<u64>) -> Vec<PlanRow> {
+    [(64, 1, ""), (128, 2, ",128")].into_iter().map(|(digest_bits, words_per_slot, argument)| {
+        let size = MemoryContainerExact::calc_size_for_limit(items, words_per_slot);
+        let fits = memory.is_none_or(|memory| size <= memory);
+        PlanRow {
+            kind: "exact",
+            size: fits.then_some(size),
+            parameters: if fits { format!("{digest_bits}-bit digests") } else { format!("doesn't fit, needs {}", format_size(size)) },
+            error_rate: fits.then(|| exact_error_rate(items, digest_bits)),
+            lookup_cost: "~2 slots (3/4 load)".to_string(),
+            arguments: if fits { format!("-el {items}{argument}") } else { "-".to_string() },
+        }
+    }).collect()
+}
+
+/// Returns row of the hybrid container with given memory threshold of the exact phase, whose filter grows in layers up to
+/// the limit.
+fn hybrid_row(items: u64, threshold: Option<u64>) -> PlanRow {
+    match threshold {
+        Some(threshold) => {
+            let (filter_size, error_rate) = MemoryContainerHybrid::calc_filter_size_and_error_rate(items, threshold);
+            PlanRow {
+                kind: "hybrid",
+                // Both phases are allocated while spilling.
+                size: Some(threshold + filter_size),
+                parameters: format!("exact up to {}", format_size(threshold)),
+                error_rate: Some(error_rate),
+                lookup_cost: "exact, then xxh layers".to_string(),
+                arguments: format!("-hls {items},{threshold}"),
+            }
+        }
+        None => PlanRow {
+            kind: "hybrid",
+            size: None,
+            parameters: "doesn't fit".to_string(),
+            error_rate: None,
+            lookup_cost: "exact, then xxh layers".to_string(),
+            arguments: "-".to_string(),
+        },
+    }
+}
+
+fn plan_for_error_rate(items: u64, error_rate: f64) -> Vec<PlanRow> {
+    let mut rows = vec![];
+
+    let size = bloom_size_for_error_rate(items, error_rate);
+    let num_hashes = bloom_num_hashes(size, items);
+    rows.push(PlanRow {
+        kind: "bloom",
+        size: Some(size),
+        parameters: format!("k = {num_hashes}"),
+        error_rate: Some(bloom_error_rate(size, items)),
+        lookup_cost: format!("{num_hashes} random bit reads"),
+        arguments: format!("-ble {items},{error_rate}"),
+    });
+
+    let size = xxh_size_for_error_rate(items, error_rate);
+    rows.push(PlanRow {
+        kind: "xxhash",
+        size,
+        parameters: format!("{KEY_BITS}-bit fingerprints"),
+        error_rate: size.map(|size| xxh_error_rate(size, items)),
+        lookup_cost: "1-4 adjacent slots".to_string(),
+        arguments: size.map(|size| format!("-xls {items},{size}")).unwrap_or("-".to_string()),
+    });
+
+    rows.extend(exact_rows(items, None));
+
+    // Exact phase may take as much as the filter it spills into.
+    rows.push(hybrid_row(items, Some(MemoryContainerXXH::calc_size_for_limit(items))));
+
+    // Filter hybrid container spills into and digests of the exact container have fixed rates, which may be too high.
+    for row in rows.iter_mut().filter(|row| row.error_rate.is_some_and(|row_error_rate| row_error_rate > error_rate * (1.0 + 1e-9))) {
+        row.size = None;
+        row.parameters = "doesn't reach requested rate".to_string();
+        row.arguments = "-".to_string();
+    }
+
+    rows
+}
+
+fn plan_for_memory(items: u64, memory: u64) -> Vec<PlanRow> {
+    let mut rows = vec![];
+
+    let num_hashes = bloom_num_hashes(memory, items);
+    rows.push(PlanRow {
+        kind: "bloom",
+        size: Some(memory),
+        parameters: format!("k = {num_hashes}"),
+        error_rate: Some(bloom_error_rate(memory, items)),
+        lookup_cost: format!("{num_hashes} random bit reads"),
+        arguments: format!("-bls {items},{memory}"),
+    });
+
+    rows.push(PlanRow {
+        kind: "xxhash",
+        size: Some(memory),
+        parameters: format!("{KEY_BITS}-bit fingerprints"),
+        error_rate: Some(xxh_error_rate(memory, items)),
+        lookup_cost: "1-4 adjacent slots".to_string(),
+        arguments: format!("-xls {items},{memory}"),
+    });
+
+    rows.extend(exact_rows(items, Some(memory)));
+
+    // Exact phase gets what the filter leaves.
+    rows.push(hybrid_row(items, MemoryContainerHybrid::calc_size_for_budget(items, memory)));
+
+    rows
+}
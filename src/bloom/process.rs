@@ -1,8 +1,21 @@
-use std::io::{self, BufRead, BufReader, BufWriter, stdin, StdoutLock, Write};
+use std::io::{self, BufWriter, ErrorKind, Read, stdin, StdoutLock, Write};
+use std::cmp::max;
+use std::ops::Range;
 use memory_stats::memory_stats;
+use xxhash_rust::xxh3::xxh3_128;
 use crate::{Params};
 use crate::{DataSource};
 use crate::ConstructionType;
+use crate::{on_full_policy_name, promote_policy_name, OnFullPolicy, OutputMode, PromotePolicy};
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::container_memory_xxh::MemoryContainerXXH;
+use crate::bloom::rotation::{now, rotate_if_needed};
+use crate::bloom::budget::containers_footprint;
+use crate::bloom::sizing::{bloom_size_for_error_rate, format_size};
+use crate::bloom::scalable::{evict_oldest, spawn_successor};
+use crate::bloom::ttl::{extract_field, parse_timestamp};
+use crate::bloom::pipeline::{process_parallel, supports_parallel};
+use crate::bloom::window::slide_window;
 
 /// Performs Bloom filter tasks.
 pub fn process(params: &mut Params) {
@@ -24,7 +37,6 @@ pub fn process(params: &mut Params) {
     const BUFFER_CAPACITY: usize = 64 * 1024;
     let stdout = io::stdout();
     let handle = stdout.lock();
-    let mut line_idx: i64 = 0;
 
     {
         let mut stdout_lock = if params.line_buffered {
@@ -35,48 +47,32 @@ pub fn process(params: &mut Params) {
             BufWriter::with_capacity(BUFFER_CAPACITY, handle)
         };
 
-        let stdin = stdin().lock();
-        let mut reader = BufReader::new(stdin);
-        let mut buf = Vec::new();
-
-        loop {
-            buf.clear();
-            let _bytes_read = match reader.read_until(b'\n', &mut buf) {
-                Ok(0) => break, // EOF
-                Ok(n) => n,
-                Err(e) => {
-                    if !params.silent_warnings {
-                        eprintln!("Error reading line {}: {}", line_idx, e);
-                    }
-                    continue;
-                }
-            };
+        if params.threads > 1 && supports_parallel(params) {
+            process_parallel(params, &mut curr_container_idx, &mut stdout_lock);
+        } else {
+            if params.threads > 1 && !params.silent_warnings {
+                eprintln!("Warning: --threads is ignored with --ttl, --ttl-field, --window, --estimate, --max-occurrences, --repeated, --unique, --count, --top and --on-full evict.");
+            }
+            if supports_parallel(params) && !params.debug_internal {
+                process_blocks(params, &mut curr_container_idx, &mut stdout_lock);
+            } else {
+                process_lines(params, &mut curr_container_idx, &mut stdout_lock);
+            }
+        }
 
-            line_idx += 1;
+        if matches!(params.output_mode, OutputMode::Unique | OutputMode::Count) {
+            print_distinct_lines(params, &mut stdout_lock);
+        }
 
-            // Remove trailing newline if present
-            if buf.last() == Some(&b'\n') {
-                buf.pop();
-            }
+        if params.output_mode == OutputMode::Top {
+            print_top(params, &mut stdout_lock);
+        }
 
-            // Create a String if valid UTF-8, otherwise use raw bytes
-            match String::from_utf8(buf.clone()) {
-                Ok(line) => {
-                    process_line(&line, params, &mut curr_container_idx, &mut stdout_lock);
-                    if params.line_buffered {
-                        stdout_lock.flush().unwrap();
-                    }
-                },
-                Err(_) => {
-                    // Handle invalid UTF-8 by using raw bytes
-                    stdout_lock.write_all(&buf).unwrap();
-                    stdout_lock.write_all(b"\n").unwrap();
-                    if params.line_buffered {
-                        stdout_lock.flush().unwrap();
-                    }
-                }
-            }
+        if params.estimate_only {
+            print_estimate(params, &mut stdout_lock);
         }
+
+        params.foreign_lines.flush();
     }
 
     if params.debug_memory {
@@ -85,28 +81,449 @@ pub fn process(params: &mut Params) {
         if let Some(usage) = memory_stats() {
             eprintln!("Physical memory used: {:.2} MiB.", (usage.physical_mem - initial_physical_mem) as f64 / 1024.0 / 1024.0);
             eprintln!("Virtual memory used: {:.2} MiB.", (usage.virtual_mem - initial_virtual_mem) as f64 / 1024.0 / 1024.0);
+            // Containers are allocated before processing, but commit their pages only when written.
+            eprintln!("Physical memory of the process: {:.2} MiB.", usage.physical_mem as f64 / 1024.0 / 1024.0);
+            eprintln!("Memory reserved by containers: {:.2} MiB.", containers_footprint(params) as f64 / 1024.0 / 1024.0);
         } else {
             eprintln!("Couldn't get the current memory usage :(");
         }
     }
+
+    if let Some(budget) = params.memory_budget {
+        // Containers are sized by formulas, so we confirm that the whole process really fits.
+        if let Some(usage) = memory_stats() {
+            if params.debug_memory {
+                eprintln!("Memory budget: {}, physical memory of the process: {}.", format_size(budget), format_size(usage.physical_mem as u64));
+            }
+            if usage.physical_mem as u64 > budget && !params.silent_warnings {
+                eprintln!("Warning: Process uses {} of physical memory, which exceeds memory budget of {}.", format_size(usage.physical_mem as u64), format_size(budget));
+            }
+        }
+    }
 }
 
-/// Processes a single line.
-fn process_line(line: &String, params: &mut Params, curr_writable_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) {
+/// Processes the input line by line.
+fn process_lines(params: &mut Params, curr_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) {
+    let mut line_idx: i64 = 0;
+    // Timestamp of the last line with the parsable --ttl-field, used for lines without one.
+    let mut last_timestamp: Option<u64> = None;
+
+    let mut reader = LineReader::new(stdin().lock());
+
+    while let Some(result) = reader.next_line() {
+        // Period is checked for each line, so lines are written into the file of the period they arrived in.
+        rotate_if_needed(params, curr_container_idx);
+
+        let buf = match result {
+            Ok(buf) => buf,
+            Err(e) => {
+                if !params.silent_warnings {
+                    eprintln!("Error reading line {}: {}", line_idx, e);
+                }
+                continue;
+            }
+        };
+
+        line_idx += 1;
+
+        // Borrow a str if valid UTF-8, otherwise use raw bytes
+        match std::str::from_utf8(buf) {
+            Ok(line) => {
+                // With --ttl-field the timestamp field is excluded from the key used for lookups.
+                let mut field_key: Option<String> = None;
+
+                if params.time_aware || params.ttl_field.is_some() {
+                    let (timestamp, key) = line_time_and_key(line, params, line_idx, &mut last_timestamp);
+                    field_key = key;
+                    for container in params.containers.iter_mut() {
+                        container.set_time(timestamp);
+                    }
+                }
+
+                let key = field_key.as_deref().unwrap_or(line);
+
+                if params.partition.is_some_and(|partition| !partition.contains(key.as_bytes())) {
+                    write_foreign_line(buf, params, stdout_lock);
+                    continue;
+                }
+
+                if params.estimate_only {
+                    // Lines are only counted, nothing is printed until the end of input.
+                    params.hll.insert(key);
+                    continue;
+                }
+
+                let keep_reading = process_line(line, key, params, curr_container_idx, stdout_lock);
+                if params.top_every.is_some_and(|every| (line_idx as u64).is_multiple_of(every)) {
+                    print_top(params, stdout_lock);
+                }
+                if params.line_buffered {
+                    stdout_lock.flush().unwrap();
+                }
+                if !keep_reading {
+                    break;
+                }
+            },
+            Err(_) if params.partition.is_some_and(|partition| !partition.contains(buf)) => write_foreign_line(buf, params, stdout_lock),
+            Err(_) => {
+                // Handle invalid UTF-8 by using raw bytes
+                stdout_lock.write_all(buf).unwrap();
+                stdout_lock.write_all(b"\n").unwrap();
+                if params.line_buffered {
+                    stdout_lock.flush().unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Number of lines looked up in the containers in batches at once.
+pub(crate) const BLOCK_SIZE: usize = 64;
+
+/// Processes the input in blocks of lines looked up in batches (see process_block()).
+fn process_blocks(params: &mut Params, curr_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) {
+    let mut line_idx: i64 = 0;
+    let mut reader = LineReader::new(stdin().lock());
+    let mut ranges = Vec::with_capacity(BLOCK_SIZE);
+
+    while let Some(result) = reader.next_lines(BLOCK_SIZE, &mut ranges) {
+        rotate_if_needed(params, curr_container_idx);
+
+        if let Err(e) = result {
+            if !params.silent_warnings {
+                eprintln!("Error reading line {}: {}", line_idx, e);
+            }
+            continue;
+        }
+
+        // Valid lines are processed together, lines which aren't valid UTF-8 or belong to other partitions split them
+        // and are printed (or handled as lines of other partitions) as they are.
+        let mut lines = [""; BLOCK_SIZE];
+        let mut num_lines = 0;
+        for (idx, range) in ranges.iter().enumerate() {
+            let buf = reader.line(range.clone());
+            let valid = std::str::from_utf8(buf).ok();
+            let foreign = params.partition.is_some_and(|partition| !partition.contains(buf));
+            line_idx += 1;
+
+            if let Some(line) = valid.filter(|_| !foreign) {
+                lines[num_lines] = line;
+                num_lines += 1;
+                if idx + 1 < ranges.len() {
+                    continue;
+                }
+            }
+
+            if !process_block(&lines[.. num_lines], &LookedUp::NONE, params, curr_container_idx, stdout_lock) {
+                return;
+            }
+            num_lines = 0;
+
+            if foreign {
+                write_foreign_line(buf, params, stdout_lock);
+            }
+            else if valid.is_none() {
+                stdout_lock.write_all(buf).unwrap();
+                stdout_lock.write_all(b"\n").unwrap();
+                if params.line_buffered {
+                    stdout_lock.flush().unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Handles line of other partition (see --foreign-lines).
+pub(crate) fn write_foreign_line(line: &[u8], params: &mut Params, stdout_lock: &mut BufWriter<StdoutLock>) {
+    params.foreign_lines.write_line(line, stdout_lock);
+    if params.line_buffered {
+        stdout_lock.flush().unwrap();
+        params.foreign_lines.flush();
+    }
+}
+
+/// Results of looking lines of the block up ahead, e.g. by the workers of the parallel pipeline. Containers are never
+/// removed while lines are looked up ahead and lines found in them stay there, so only the lines which weren't found
+/// may be missing the lines written since the lookup.
+pub(crate) struct LookedUp {
+    pub found_in: [Option<usize>; BLOCK_SIZE], // Index of the first container which could have the line.
+    pub num_containers: usize, // Number of containers the lines were looked up in.
+    pub num_unchanged: usize, // Number of the first containers which weren't written since the lookup.
+}
+
+impl LookedUp {
+    /// Lines which weren't looked up yet.
+    pub(crate) const NONE: LookedUp = LookedUp { found_in: [None; BLOCK_SIZE], num_containers: 0, num_unchanged: 0 };
+}
+
+/// Processes lines of the block, looking them up in batches. Lines are looked up only in the containers which weren't
+/// looked up ahead or were written since. Gives the same results as processing them one by one, which it falls back to
+/// when the writable container could change within the block or lines found in older containers could be promoted.
+/// Returns false if we should stop reading the input.
+pub(crate) fn process_block(lines: &[&str], looked_up: &LookedUp, params: &mut Params, curr_writable_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) -> bool {
+    if lines.is_empty() {
+        return true;
+    }
+
+    let mut found_in = [None; BLOCK_SIZE];
+    let curr_idx = *curr_writable_container_idx;
+    // Writable container must stay the same for the whole block, so it needs room for all the lines.
+    let could_write = params.write_mode && params.containers.get_mut(curr_idx).is_some_and(|container| {
+        !container.get_container_details().read_only && container.has_room_for(lines)
+    });
+
+    if (params.write_mode && !could_write) || params.debug_internal {
+        return lines.iter().all(|line| process_line(line, line, params, curr_writable_container_idx, stdout_lock));
+    }
+
+    // 1. Looking lines up in the containers older than the writable one (or in all of them in read mode). Lines found
+    //    ahead are still there, others are looked up in the containers written since.
+    let num_read_only = if could_write { curr_idx } else { params.containers.len() };
+    for (found_in, looked_up_in) in found_in.iter_mut().zip(looked_up.found_in).take(lines.len()) {
+        *found_in = looked_up_in.filter(|idx| *idx < num_read_only);
+    }
+    let num_unchanged = looked_up.num_unchanged.min(num_read_only);
+    check_block(lines, &params.containers[num_unchanged .. num_read_only], num_unchanged, &mut found_in);
+
+    if could_write {
+        if params.promote != PromotePolicy::Never && found_in[.. lines.len()].iter().any(|idx| idx.is_some()) {
+            return lines.iter().all(|line| process_line(line, line, params, curr_writable_container_idx, stdout_lock));
+        }
+
+        // 2. Writing the rest into the writable container, in order, so lines repeated in the block are found. Lines
+        //    found there ahead are written too, as writing may find out that the line can't be stored after all.
+        let mut pending = [""; BLOCK_SIZE];
+        let mut pending_idx = [0; BLOCK_SIZE];
+        let mut num_pending = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            if found_in[idx].is_none() {
+                pending[num_pending] = line;
+                pending_idx[num_pending] = idx;
+                num_pending += 1;
+            }
+        }
+
+        let mut found = [false; BLOCK_SIZE];
+        let container = &mut params.containers[curr_idx];
+        container.check_and_set_batch(&pending[.. num_pending], &mut found[.. num_pending]);
+        for (idx, found) in pending_idx.iter().zip(found).take(num_pending) {
+            if found {
+                found_in[*idx] = Some(curr_idx);
+            }
+        }
+
+        // 3. Lines which were just written may be found in the newer containers as well. Newer containers aren't
+        //    written, so lines looked up ahead in all of them are known. Others are looked up.
+        num_pending = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            if found_in[idx].is_some() {
+                continue;
+            }
+            match looked_up.found_in[idx] {
+                Some(looked_up_in) if looked_up_in > curr_idx => found_in[idx] = Some(looked_up_in),
+                None if params.containers.len() <= looked_up.num_containers => {}
+                _ => {
+                    pending[num_pending] = line;
+                    pending_idx[num_pending] = idx;
+                    num_pending += 1;
+                }
+            }
+        }
+
+        let mut pending_found_in = [None; BLOCK_SIZE];
+        check_block(&pending[.. num_pending], &params.containers[curr_idx + 1 ..], curr_idx + 1, &mut pending_found_in);
+        for (idx, pending_found_in) in pending_idx.iter().zip(pending_found_in).take(num_pending) {
+            found_in[*idx] = pending_found_in;
+        }
+    }
+
+    // 4. Printing the lines in order.
+    for (idx, line) in lines.iter().enumerate() {
+        print_line(line, found_in[idx].is_some(), params, stdout_lock);
+        if params.line_buffered {
+            stdout_lock.flush().unwrap();
+        }
+    }
+
+    true
+}
+
+/// Looks lines which weren't found yet up in the containers, storing index of the container (offset by first_idx)
+/// where each line was found.
+pub(crate) fn check_block(lines: &[&str], containers: &[Box<dyn Container>], first_idx: usize, found_in: &mut [Option<usize>; BLOCK_SIZE]) {
+    let mut pending = [""; BLOCK_SIZE];
+    let mut pending_idx = [0; BLOCK_SIZE];
+    let mut found = [false; BLOCK_SIZE];
+
+    for (container_idx, container) in containers.iter().enumerate() {
+        let mut num_pending = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            if found_in[idx].is_none() {
+                pending[num_pending] = line;
+                pending_idx[num_pending] = idx;
+                num_pending += 1;
+            }
+        }
+        if num_pending == 0 {
+            return;
+        }
+
+        container.check_batch(&pending[.. num_pending], &mut found[.. num_pending]);
+        for (idx, found) in pending_idx.iter().zip(found).take(num_pending) {
+            if found {
+                found_in[*idx] = Some(first_idx + container_idx);
+            }
+        }
+    }
+}
+
+/// Reads lines into a reusable buffer, so no memory is allocated for each line. Buffer only grows for lines longer
+/// than it.
+struct LineReader<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    start: usize, // Beginning of the next line in the buffer.
+    end: usize, // End of the data read into the buffer.
+    eof: bool,
+}
+
+impl<R: Read> LineReader<R> {
+    /// Number of bytes read at once.
+    const CAPACITY: usize = 256 * 1024;
+
+    fn new(reader: R) -> Self {
+        Self { reader, buf: vec![0; Self::CAPACITY], start: 0, end: 0, eof: false }
+    }
+
+    /// Returns the next line without the newline, or None at the end of the input.
+    fn next_line(&mut self) -> Option<io::Result<&[u8]>> {
+        match self.next_range(true)? {
+            Ok(range) => Some(Ok(&self.buf[range])),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Reads up to max next lines into ranges (see line()). Waits only for the first line, others are taken only if
+    /// they're already in the buffer. Returns None at the end of the input.
+    fn next_lines(&mut self, max: usize, ranges: &mut Vec<Range<usize>>) -> Option<io::Result<()>> {
+        ranges.clear();
+        match self.next_range(true)? {
+            Ok(range) => ranges.push(range),
+            Err(e) => return Some(Err(e)),
+        }
+        while ranges.len() < max {
+            match self.next_range(false) {
+                Some(Ok(range)) => ranges.push(range),
+                _ => break,
+            }
+        }
+        Some(Ok(()))
+    }
+
+    /// Returns line of the range returned by next_lines().
+    fn line(&self, range: Range<usize>) -> &[u8] {
+        &self.buf[range]
+    }
+
+    /// Returns position of the next line in the buffer, without the newline. Buffer is refilled only if refill is set,
+    /// as it moves lines returned before. Otherwise, None is returned when there's no complete line in the buffer.
+    fn next_range(&mut self, refill: bool) -> Option<io::Result<Range<usize>>> {
+        loop {
+            if let Some(idx) = self.buf[self.start .. self.end].iter().position(|byte| *byte == b'\n') {
+                let line_start = self.start;
+                self.start += idx + 1;
+                return Some(Ok(line_start .. line_start + idx));
+            }
+
+            if self.eof {
+                // The last line may not end with a newline.
+                if self.start == self.end {
+                    return None;
+                }
+                let line_start = self.start;
+                self.start = self.end;
+                return Some(Ok(line_start .. self.end));
+            }
+
+            if !refill {
+                return None;
+            }
+
+            // Moving the incomplete line to the beginning, so the rest of it is read after it.
+            self.buf.copy_within(self.start .. self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+            if self.end == self.buf.len() {
+                self.buf.resize(self.buf.len() * 2, 0);
+            }
+
+            match self.reader.read(&mut self.buf[self.end ..]) {
+                Ok(0) => self.eof = true,
+                Ok(num_read) => self.end += num_read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Returns timestamp of the line (from --ttl-field or wall clock) and the key to use for lookups if it differs from the
+/// line.
+fn line_time_and_key(line: &str, params: &Params, line_idx: i64, last_timestamp: &mut Option<u64>) -> (u64, Option<String>) {
+    let field = match params.ttl_field {
+        Some(field) => field,
+        None => return (now(), None),
+    };
+
+    match extract_field(line, field) {
+        Some((value, key)) => match parse_timestamp(value) {
+            Some(timestamp) => {
+                *last_timestamp = Some(timestamp);
+                (timestamp, Some(key))
+            }
+            None => {
+                if !params.silent_warnings {
+                    eprintln!("Warning: Could not parse timestamp \"{}\" in line {}.", value, line_idx);
+                }
+                (last_timestamp.unwrap_or_else(now), Some(key))
+            }
+        },
+        None => {
+            if !params.silent_warnings {
+                eprintln!("Warning: Line {} has no field {} with timestamp.", line_idx, field);
+            }
+            (last_timestamp.unwrap_or_else(now), None)
+        }
+    }
+}
+
+/// Processes a single line. Value of the key is used for lookups, while the line itself is printed. Returns false if we
+/// should stop reading the input.
+fn process_line(line: &str, key: &str, params: &mut Params, curr_writable_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) -> bool {
     // Step 1: Iterating over containers and checking if value exists in each of them.
-    //         If value exists in container, we store (in write mode) the value in the first possible writable
-    //         container. In order to find possible container we just skip current container if it's full in a loop.
+    //         If value exists in container, we store (in write mode and depending on --promote policy) the value in
+    //         the first possible writable container. In order to find possible container we just skip current container if it's full in a loop.
     //         Special case is for container that is also the current writable container (after finding possible
     //         writable container in a loop). In such case we do check_and_set() to speed up.
 
+    // 1. Switching to next writable container.
+    let could_write = match select_writable_container(params, curr_writable_container_idx, stdout_lock) {
+        Some(could_write) => could_write,
+        None => return false,
+    };
+
+    store_and_print_line(line, key, params, curr_writable_container_idx, could_write, stdout_lock);
+    true
+}
+
+/// Switches to the next writable container if the current one is full or read only. Returns whether the line could be
+/// written into the current writable container, or None if we should stop reading the input.
+pub(crate) fn select_writable_container(params: &mut Params, curr_writable_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) -> Option<bool> {
     let mut could_write = params.write_mode;
-    let mut value_written = false;
-    let mut value_found = false;
 
-    // 1. Switching to next writable container.
     if params.write_mode {
-        while *curr_writable_container_idx < params.containers.len() && params.containers[*curr_writable_container_idx].is_full() {
-            // If current container is full then we advance current writable container index.
+        while *curr_writable_container_idx < params.containers.len() && (params.containers[*curr_writable_container_idx].is_full() || params.containers[*curr_writable_container_idx].get_container_details().read_only) {
+            // If current container is full (or read only) then we advance current writable container index.
             // Note that we could end up with an index out of range. That will mean that we couldn't write items at all.
             if params.debug_internal {
                 eprintln!("> #{}: Container is full, we will check another.", *curr_writable_container_idx);
@@ -114,6 +531,10 @@ fn process_line(line: &String, params: &mut Params, curr_writable_container_idx:
             *curr_writable_container_idx += 1;
         }
 
+        if *curr_writable_container_idx >= params.containers.len() && !handle_full(params, curr_writable_container_idx, stdout_lock) {
+            return None;
+        }
+
         if *curr_writable_container_idx >= params.containers.len() {
             // Current writable container index is out of range. That mean that there is no container that we can write
             // to.
@@ -126,55 +547,88 @@ fn process_line(line: &String, params: &mut Params, curr_writable_container_idx:
             if params.debug_internal {
                 eprintln!("> #{}: Container is not full and is ready to be written to.", *curr_writable_container_idx);
             }
+            params.containers_full = false;
         }
     }
 
+    Some(could_write)
+}
+
+/// Looks the line up in the containers, writes it into the current writable container (if it could be written) and
+/// prints it.
+pub(crate) fn store_and_print_line(line: &str, key: &str, params: &mut Params, curr_writable_container_idx: &mut usize, could_write: bool, stdout_lock: &mut BufWriter<StdoutLock>) {
+    let mut value_written = false;
+    let mut value_found = false;
+
     // 2. Iterating over containers in order to read and maybe write to the same container. If we end up with a matching
     //    value in the container which isn't the writable one then we will write the value in step 3 (outside the loop).
-    for (idx, ref mut container) in params.containers.iter_mut().enumerate()
-    {
-        if could_write && idx == *curr_writable_container_idx {
-            // In write mode we could use check_and_set() if current writable container is the one we iterate over.
-            value_found = container.check_and_set(line);
-            // If value was found then it also was written.
-            value_written = value_found;
-
-            if value_found {
-                // We found the value and also wrote it into the container. We're advancing to the step 3 in which we
-                // will print the value. In step 3 we will not write the value as value_written is now true.
-                if params.debug_internal {
-                    eprintln!("> #{}: We can write and it's writable container. Value \"{}\" found and written. Advancing to step 3.", idx, line);
+    if params.needs_counts() || params.max_occurrences.is_some() {
+        // Occurrence is always counted in the writable container, so there's nothing to write in step 3.
+        let count = count_occurrences(key, params, could_write, *curr_writable_container_idx);
+        value_written = true;
+
+        value_found = match params.output_mode {
+            // Value is treated as found once its occurrences in all the containers reach the limit.
+            OutputMode::Lines => count >= params.max_occurrences.unwrap(),
+            // Only the first repetition is treated as new, so it's the only one printed.
+            OutputMode::Repeated => count != 1,
+            // Nothing is printed until the report.
+            OutputMode::Top => true,
+            // Nothing is printed until the end of input. Lines are collected exactly, as the sketch may overestimate
+            // count of the new line or hold it since the previous runs.
+            OutputMode::Unique | OutputMode::Count => {
+                if params.distinct_digests.insert(xxh3_128(key.as_bytes())) {
+                    params.distinct_lines.push((line.to_string(), (key != line).then(|| key.to_string())));
                 }
-                break;
+                true
             }
-            else {
-                // Value wasn't found nor written. Next containers will not be writable. We will just iterate to search
-                // for the value and then go to the step 3 in which we may write the value.
-                if params.debug_internal {
-                    eprintln!("> #{}: We can write and it's writable container. Value \"{}\" not found and not written. Continuing iteration.", idx, line);
+        };
+    }
+    else {
+        for (idx, ref mut container) in params.containers.iter_mut().enumerate()
+        {
+            if could_write && idx == *curr_writable_container_idx {
+                // In write mode we could use check_and_set() if current writable container is the one we iterate over.
+                value_found = container.check_and_set(key);
+                // Either value was found or it was just inserted, so it's written in both cases.
+                value_written = true;
+
+                if value_found {
+                    // We found the value and also wrote it into the container. We're advancing to the step 3 in which we
+                    // will print the value. In step 3 we will not write the value as value_written is now true.
+                    if params.debug_internal {
+                        eprintln!("> #{}: We can write and it's writable container. Value \"{}\" found and written. Advancing to step 3.", idx, line);
+                    }
+                    break;
                 }
-                continue;
-            }
-        }
-        else {
-            // We can't write, so we fall back to the check().
-            value_found = container.check(line);
-
-            if value_found {
-                // If value was found then we mark it as already written to not write it again. We can also advance to
-                // the step 3.
-                if params.debug_internal {
-                    eprintln!("> #{}: Value \"{}\" found so we treat is as already written. Advancing to step 3.", idx, line);
+                else {
+                    // Value wasn't found, but was written. Next containers will not be writable. We will just iterate to
+                    // search for the value.
+                    if params.debug_internal {
+                        eprintln!("> #{}: We can write and it's writable container. Value \"{}\" not found but written. Continuing iteration.", idx, line);
+                    }
+                    continue;
                 }
-                value_written = true;
-                break;
             }
             else {
-                // Value not found. Continuing iteration.
-                if params.debug_internal {
-                    eprintln!("> #{}: We can't write. Value \"{}\" not found. Continuing iteration.", idx, line);
+                // We can't write, so we fall back to the check().
+                value_found = container.check(key);
+
+                if value_found {
+                    // Value was found, so we can advance to the step 3 in which we may promote it into the writable
+                    // container (unless it was already written there).
+                    if params.debug_internal {
+                        eprintln!("> #{}: Value \"{}\" found. Advancing to step 3.", idx, line);
+                    }
+                    break;
+                }
+                else {
+                    // Value not found. Continuing iteration.
+                    if params.debug_internal {
+                        eprintln!("> #{}: We can't write. Value \"{}\" not found. Continuing iteration.", idx, line);
+                    }
+                    continue;
                 }
-                continue;
             }
         }
     }
@@ -186,30 +640,31 @@ fn process_line(line: &String, params: &mut Params, curr_writable_container_idx:
     //    - value_found - Whether given line was found in any of the container.
     //    - value_written - Whether given line was written to any of the writable containers.
     //
-    if value_found {
-        if could_write && !value_written {
-            // Value was found in some container, but was not yet written.
-            // Note that could_write mean that current writable container is not full and is ready to be written to.
-            let curr_writable_container = &mut params.containers[*curr_writable_container_idx];
-
-            if params.debug_internal {
-                eprintln!("> #{}: Value \"{}\" found and written in step 3.", *curr_writable_container_idx, line);
-            }
-
-            // We're node. Value was found and is now written.
-            curr_writable_container.set(line);
+    if value_found && could_write && !value_written && should_promote(params) {
+        // Value was found in some older container, but was not yet written.
+        // Note that could_write mean that current writable container is not full and is ready to be written to.
+        let curr_writable_container = &mut params.containers[*curr_writable_container_idx];
 
-            // Marking value as written, so we can do some additional logic later.
-            // value_written = true; // Uncomment this if used.
+        if params.debug_internal {
+            eprintln!("> #{}: Value \"{}\" found and promoted in step 3.", *curr_writable_container_idx, line);
         }
+
+        // We're done. Value was found and is now written.
+        curr_writable_container.set(key);
+        curr_writable_container.get_container_details().num_promotions += 1;
     }
 
     // 4. Now it's time to print the value. We consider inverse mode.
+    print_line(line, value_found, params, stdout_lock);
+}
+
+/// Prints the line depending on whether it was found and the inverse mode.
+pub(crate) fn print_line(line: &str, value_found: bool, params: &Params, stdout_lock: &mut BufWriter<StdoutLock>) {
     if (!value_found && !params.inverse) || (value_found && params.inverse) {
         if !params.silent {
             // Printing the line.
-            stdout_lock.write(line.as_bytes()).unwrap();
-            stdout_lock.write(b"\n").unwrap();
+            stdout_lock.write_all(line.as_bytes()).unwrap();
+            stdout_lock.write_all(b"\n").unwrap();
             if params.debug_internal {
                 eprintln!("> Value written: {}", line);
             }
@@ -222,12 +677,174 @@ fn process_line(line: &String, params: &mut Params, curr_writable_container_idx:
     }
 }
 
+/// Prints distinct lines collected during processing, with their counts in --count mode or only those which occurred
+/// once in --unique mode.
+fn print_distinct_lines(params: &mut Params, stdout_lock: &mut BufWriter<StdoutLock>) {
+    if params.silent {
+        return;
+    }
+
+    for (line, key) in params.distinct_lines.iter() {
+        let key = key.as_deref().unwrap_or(line);
+        let count: u64 = params.containers.iter().map(|container| container.count(key)).sum();
+
+        match params.output_mode {
+            OutputMode::Count => writeln!(stdout_lock, "{:>7} {}", count, line).unwrap(),
+            OutputMode::Unique if count == 1 => writeln!(stdout_lock, "{}", line).unwrap(),
+            _ => {}
+        }
+    }
+
+    stdout_lock.flush().unwrap();
+}
+
+/// Prints the most frequent lines tracked by all the containers. Counts of the line in all the containers are added
+/// together.
+fn print_top(params: &mut Params, stdout_lock: &mut BufWriter<StdoutLock>) {
+    if params.silent {
+        return;
+    }
+
+    let mut candidates: Vec<String> = params.containers.iter().flat_map(|container| container.get_top()).map(|(value, _)| value).collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let mut top: Vec<(u64, String)> = candidates.into_iter()
+        .map(|value| (params.containers.iter().map(|container| container.count(&value)).sum(), value))
+        .collect();
+    // Most frequent first, lines with equal counts in alphabetical order.
+    top.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    top.truncate(params.top_size);
+
+    for (count, value) in top {
+        writeln!(stdout_lock, "{:>7} {}", count, value).unwrap();
+    }
+
+    stdout_lock.flush().unwrap();
+}
+
+/// Prints estimated number of distinct lines and filter parameters recommended for it.
+fn print_estimate(params: &mut Params, stdout_lock: &mut BufWriter<StdoutLock>) {
+    let distinct = params.hll.estimate();
+    // Estimate has about 1.6% standard error, so the limit gets some headroom.
+    let limit = max(1, (distinct as f64 * 1.1).ceil() as u64);
+
+    writeln!(stdout_lock, "Estimated distinct lines: {}", distinct).unwrap();
+    writeln!(stdout_lock, "Recommended limit (with 10% headroom): {}", limit).unwrap();
+    writeln!(stdout_lock).unwrap();
+    for error_rate in [0.01, 0.001, 0.0001, 0.000001] {
+        let size = bloom_size_for_error_rate(limit, error_rate);
+        writeln!(stdout_lock, "  error rate {:<9} -ble {},{:<9} ({} bytes)", error_rate, limit, error_rate, size).unwrap();
+    }
+    writeln!(stdout_lock, "  xxhash filter        -xls {},{}", limit, MemoryContainerXXH::calc_size_for_limit(limit)).unwrap();
+    writeln!(stdout_lock, "  exact                -el {}", limit).unwrap();
+
+    stdout_lock.flush().unwrap();
+}
+
+/// Returns number of occurrences of the value in all the containers, not counting the current one. Occurrence is
+/// counted in the writable container if there's one.
+fn count_occurrences(key: &str, params: &mut Params, could_write: bool, curr_writable_container_idx: usize) -> u64 {
+    let mut count = 0;
+    for (idx, container) in params.containers.iter_mut().enumerate() {
+        if could_write && idx == curr_writable_container_idx {
+            count += container.count_and_set(key);
+        }
+        else {
+            count += container.count(key);
+        }
+    }
+    count
+}
+
+/// Decides whether value found in a non-writable container should be copied into the writable one.
+fn should_promote(params: &mut Params) -> bool {
+    match params.promote {
+        PromotePolicy::Always => true,
+        PromotePolicy::Never => false,
+        PromotePolicy::Probabilistic => {
+            // Xorshift64, good enough to pick lines and doesn't need any additional dependency.
+            params.random_state ^= params.random_state << 13;
+            params.random_state ^= params.random_state >> 7;
+            params.random_state ^= params.random_state << 17;
+            ((params.random_state >> 11) as f64 / (1u64 << 53) as f64) < params.promote_probability
+        }
+    }
+}
+
+/// Applies on-full policy when there is no writable container. May make another container writable by updating current
+/// writable container index. Returns false if we should stop reading the input.
+fn handle_full(params: &mut Params, curr_writable_container_idx: &mut usize, stdout_lock: &mut BufWriter<StdoutLock>) -> bool {
+    if params.containers_full {
+        // Policy was already applied when the containers got full, and none of them got writable since.
+        return true;
+    }
+    params.containers_full = true;
+    params.num_on_full_events += 1;
+
+    match params.on_full {
+        OnFullPolicy::Ignore => {}
+        OnFullPolicy::Warn => {
+            if params.num_on_full_events == 1 && !params.silent_warnings {
+                eprintln!("Warning: All containers are full, new lines will not be stored.");
+            }
+        }
+        OnFullPolicy::Fail => {
+            stdout_lock.flush().unwrap();
+            eprintln!("Error: All containers are full.");
+            std::process::exit(2);
+        }
+        OnFullPolicy::Stop => {
+            if params.debug_internal {
+                eprintln!("> All containers are full, stopping.");
+            }
+            return false;
+        }
+        OnFullPolicy::Rotate | OnFullPolicy::Scale => {
+            if !params.budget_exhausted && !spawn_successor(params, params.on_full == OnFullPolicy::Scale) {
+                // Checked only once, so we don't try to spawn a successor for each line.
+                params.budget_exhausted = true;
+                if !params.silent_warnings {
+                    eprintln!("Warning: All containers are full and another one doesn't fit into the memory budget, new lines will not be stored.");
+                }
+            }
+        }
+        OnFullPolicy::Evict => {
+            evict_oldest(params);
+            // The newest container is now the only one which isn't full.
+            *curr_writable_container_idx = params.containers.len() - 1;
+        }
+        OnFullPolicy::Slide => {
+            slide_window(params);
+            // The newest generation is now the only one which isn't full.
+            *curr_writable_container_idx = params.containers.len() - 1;
+        }
+    }
+
+    if params.top_size > 0 {
+        // New containers have to track the most frequent lines as well.
+        for container in params.containers.iter_mut() {
+            container.set_top_size(params.top_size);
+        }
+    }
+
+    true
+}
+
 fn debug_args(params: &mut Params) {
     eprintln!("[ INPUT ARGUMENTS ]");
     eprintln!(" - debug:      {}", if params.debug { "True" } else { "False" });
     eprintln!(" - write:      {}", if params.write_mode { "True" } else { "False" });
     eprintln!(" - silent:     {}", if params.silent { "True" } else { "False" });
     eprintln!(" - inverse:    {}", if params.inverse { "True" } else { "False" });
+    eprintln!(" - on full:    {}", on_full_policy_name(params.on_full));
+    eprintln!(" - promote:    {}", promote_policy_name(params.promote));
+    if let Some(max_occurrences) = params.max_occurrences {
+        eprintln!(" - max occurrences: {}", max_occurrences);
+    }
+    if let Some(partition) = params.partition {
+        eprintln!(" - partition:  {}", partition);
+    }
 
     eprintln!();
     eprintln!("[ CONTAINERS ]");
@@ -235,7 +852,7 @@ fn debug_args(params: &mut Params) {
         eprintln!(" < No containers added >");
     }
 
-    for (_i, container) in params.containers.iter_mut().enumerate() {
+    for container in params.containers.iter_mut() {
         let container_usage = container.get_usage();
         let container_write_level = container.get_write_level();
         let container_details = container.get_container_details();
@@ -249,17 +866,45 @@ fn debug_args(params: &mut Params) {
             ConstructionType::BloomLinesAndSize => { "(bloom) limit and size" }
             ConstructionType::BloomLinesAndErrorRate => { "(bloom) limit and error-rate" },
             ConstructionType::XXHLimitAndSize => { "(xxhash) limit and error-rate" },
+            ConstructionType::ExactLimit64 => { "(exact) limit and 64-bit digests" },
+            ConstructionType::ExactLimit128 => { "(exact) limit and 128-bit digests" },
+            ConstructionType::HybridLimitAndSize => { "(hybrid) limit and exact phase size" },
+            ConstructionType::TimedLimitAndTtl => { "(timed) limit and time to live" },
+            ConstructionType::CountMinLimitAndSize => { "(count-min) limit and size" },
         };
 
-        eprintln!(" - Container {kind_str} \"{}\" with type = {}, size = {}, error rate = {}, limit = {}, binary fill = {} %, line fill = {} %",
+        eprintln!(" - Container {kind_str}{} \"{}\" with type = {}, shards = {}, size = {}, error rate = {}, limit = {}, binary fill = {} %, line fill = {} %, created at = {}, expires at = {}",
+                  if container_details.read_only { " (read only)" } else { "" },
                   container_details.path,
                   type_str,
+                  container_details.construction_details.shards,
                   container_details.construction_details.size,
                   container_details.construction_details.error_rate,
                   container_details.construction_details.limit,
                   container_usage,
-                  container_write_level
+                  container_write_level,
+                  container_details.created_at,
+                  container_details.expires_at
         );
     }
     eprintln!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_reader_splits_lines_across_reads() {
+        // Line longer than the buffer makes it grow, the last line has no newline.
+        let long_line = "x".repeat(LineReader::<&[u8]>::CAPACITY * 2 + 5);
+        let input = format!("a\n\n{}\nb\nc", long_line);
+        let mut reader = LineReader::new(input.as_bytes());
+
+        let mut lines = vec![];
+        while let Some(line) = reader.next_line() {
+            lines.push(String::from_utf8(line.unwrap().to_vec()).unwrap());
+        }
+        assert_eq!(lines, vec!["a", "", long_line.as_str(), "b", "c"]);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::container_sharded::shard_suffix;
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::bloom::scalable::discover_successors;
+use crate::{ConstructionDetails, ContainerDetails, DataSource, Params};
+
+/// Placeholder in the rotation file pattern which is replaced by the period, e.g. "seen.{date}.blf".
+pub const DATE_PLACEHOLDER: &str = "{date}";
+
+/// Length of the rotation period.
+#[derive(Copy, Clone, PartialEq)]
+pub enum RotationPeriod {
+    // Period formatted as YYYY-MM-DDTHH.
+    Hourly,
+    // Period formatted as YYYY-MM-DD.
+    Daily,
+}
+
+/// Time-based rotation settings.
+#[derive(Clone)]
+pub struct Rotation {
+    pub pattern: String,
+    pub period: RotationPeriod,
+    pub keep: u64,
+}
+
+/// Rotation of the running process: its settings, construction details of the new files and start of the period whose
+/// files are open.
+pub struct ActiveRotation {
+    pub rotation: Rotation,
+    pub construction_details: ConstructionDetails,
+    pub start: u64,
+}
+
+/// Returns current Unix timestamp in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Returns length of the period in seconds.
+fn period_seconds(period: RotationPeriod) -> u64 {
+    match period {
+        RotationPeriod::Hourly => 3600,
+        RotationPeriod::Daily => 86400,
+    }
+}
+
+/// Converts number of days since Unix epoch into (year, month, day) of the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts (year, month, day) of the proleptic Gregorian calendar into number of days since Unix epoch.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Returns start of the period containing given timestamp.
+pub fn period_start(timestamp: u64, period: RotationPeriod) -> u64 {
+    timestamp - timestamp % period_seconds(period)
+}
+
+/// Formats period starting at given timestamp, e.g. "2024-05-01" or "2024-05-01T13" (UTC).
+pub fn format_period(start: u64, period: RotationPeriod) -> String {
+    let (year, month, day) = civil_from_days((start / 86400) as i64);
+    match period {
+        RotationPeriod::Daily => format!("{year:04}-{month:02}-{day:02}"),
+        RotationPeriod::Hourly => format!("{year:04}-{month:02}-{day:02}T{:02}", start % 86400 / 3600),
+    }
+}
+
+/// Parses period formatted by format_period() and returns its start timestamp.
+pub fn parse_period(value: &str, period: RotationPeriod) -> Option<u64> {
+    let (date, hour) = match period {
+        RotationPeriod::Daily => (value, "0"),
+        RotationPeriod::Hourly => value.split_once('T')?,
+    };
+
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return None;
+    }
+
+    let year: i64 = parts[0].parse().ok()?;
+    let month: u32 = parts[1].parse().ok()?;
+    let day: u32 = parts[2].parse().ok()?;
+    let hour: u64 = hour.parse().ok()?;
+
+    if !(1 ..= 12).contains(&month) || !(1 ..= 31).contains(&day) || hour > 23 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    Some(days as u64 * 86400 + hour * 3600)
+}
+
+/// Returns path of the file for period starting at given timestamp.
+pub fn period_path(rotation: &Rotation, start: u64) -> String {
+    rotation.pattern.replace(DATE_PLACEHOLDER, &format_period(start, rotation.period))
+}
+
+/// Returns (period start, path) pairs of existing files matching the rotation pattern, from the oldest.
+fn discover_periods(rotation: &Rotation) -> Vec<(u64, String)> {
+    let pattern = Path::new(&rotation.pattern);
+    let dir = match pattern.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    let file_pattern = pattern.file_name().unwrap().to_string_lossy().to_string();
+    let (prefix, suffix) = file_pattern.split_once(DATE_PLACEHOLDER).unwrap();
+
+    let mut periods = vec![];
+
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name.len() < prefix.len() + suffix.len() || !file_name.starts_with(prefix) || !file_name.ends_with(suffix) {
+                continue;
+            }
+            let value = &file_name[prefix.len() .. file_name.len() - suffix.len()];
+            // Sharded file is represented by its first shard file.
+            let value = value.strip_suffix(&shard_suffix(0)).unwrap_or(value);
+            if let Some(start) = parse_period(value, rotation.period) {
+                periods.push((start, period_path(rotation, start)));
+            }
+        }
+    }
+
+    periods.sort();
+    periods.dedup();
+    periods
+}
+
+/// Returns (path, expired) pairs of existing files of the periods before the current one, from the oldest.
+fn previous_periods(rotation: &Rotation, now: u64) -> Vec<(String, bool)> {
+    let current_start = period_start(now, rotation.period);
+    // Periods starting before this one are out of retention.
+    let oldest_start = current_start.saturating_sub((rotation.keep - 1) * period_seconds(rotation.period));
+
+    discover_periods(rotation).into_iter()
+        .filter(|(start, _)| *start < current_start)
+        .map(|(start, path)| {
+            let header = <dyn Container>::header_from_file(&path);
+            let expired = start < oldest_start || (header.expires_at != 0 && header.expires_at <= now);
+            (path, expired)
+        })
+        .collect()
+}
+
+/// Returns paths of existing files (successors included) of the retained periods before the current one. Only headers
+/// are read.
+pub fn retained_paths(rotation: &Rotation) -> Vec<String> {
+    let mut paths = vec![];
+
+    for (path, expired) in previous_periods(rotation, now()) {
+        if !expired {
+            paths.push(path.clone());
+            paths.extend(discover_successors(&path));
+        }
+    }
+
+    paths
+}
+
+/// Adds containers of retained periods (read only) and the container of the current period (writable) to the params.
+/// Expired files are removed in write mode and ignored otherwise.
+pub fn open_rotated_containers(params: &mut Params, rotation: &Rotation, construction_details: ConstructionDetails) {
+    let now = now();
+    let period_length = period_seconds(rotation.period);
+    let current_start = period_start(now, rotation.period);
+    let current_path = period_path(rotation, current_start);
+
+    for (path, expired) in previous_periods(rotation, now) {
+        if expired {
+            if params.write_mode {
+                if params.debug {
+                    eprintln!("Removing expired file \"{}\".", path);
+                }
+                <dyn Container>::remove_files(&path).unwrap_or_else(|_| {
+                    eprintln!("Error: Can't remove expired file \"{}\"!", path);
+                    std::process::exit(1);
+                });
+            }
+            continue;
+        }
+
+        for path in [vec![path.clone()], discover_successors(&path)].concat() {
+            let mut container = <dyn Container>::from_file(&path, false);
+            container.get_container_details().read_only = true;
+            params.containers.push(container);
+        }
+    }
+
+    if <dyn Container>::exists(&current_path) {
+        params.containers.push(<dyn Container>::from_file(&current_path, params.write_mode));
+        for successor_path in discover_successors(&current_path) {
+            params.containers.push(<dyn Container>::from_file(&successor_path, params.write_mode));
+        }
+    }
+    else if params.write_mode {
+        params.containers.push(<dyn Container>::from_details(ContainerDetails {
+            path: current_path,
+            construction_details,
+            data_source: DataSource::File,
+            created_at: now,
+            expires_at: current_start + rotation.keep * period_length,
+            read_only: false,
+            hll: HyperLogLog::new(),
+            partition: params.partition,
+            num_promotions: 0,
+        }));
+    }
+
+    params.rotation = Some(ActiveRotation { rotation: rotation.clone(), construction_details, start: current_start });
+}
+
+/// Switches to the files of the current period once it differs from the period whose files are open. Files of the
+/// previous period are saved and closed, then containers are opened again, so the file of the previous period is
+/// retained (read only) and files out of retention are removed. Writable container is looked for from the start again.
+pub fn rotate_if_needed(params: &mut Params, curr_container_idx: &mut usize) {
+    match &params.rotation {
+        Some(active) if period_start(now(), active.rotation.period) != active.start => {}
+        _ => return,
+    }
+    let active = params.rotation.take().unwrap();
+
+    if params.debug_internal {
+        eprintln!("> Period {} has ended, rotating files.", format_period(active.start, active.rotation.period));
+    }
+
+    if params.write_mode {
+        for container in params.containers.iter_mut() {
+            if !container.get_container_details().read_only {
+                container.save();
+            }
+        }
+    }
+
+    params.containers.clear();
+    open_rotated_containers(params, &active.rotation, active.construction_details);
+
+    if params.top_size > 0 {
+        for container in params.containers.iter_mut() {
+            container.set_top_size(params.top_size);
+        }
+    }
+    *curr_container_idx = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse_period() {
+        // 2024-02-29 13:45:10 UTC.
+        let timestamp = 1709214310;
+
+        let daily_start = period_start(timestamp, RotationPeriod::Daily);
+        assert_eq!(format_period(daily_start, RotationPeriod::Daily), "2024-02-29");
+        assert_eq!(parse_period("2024-02-29", RotationPeriod::Daily), Some(daily_start));
+
+        let hourly_start = period_start(timestamp, RotationPeriod::Hourly);
+        assert_eq!(format_period(hourly_start, RotationPeriod::Hourly), "2024-02-29T13");
+        assert_eq!(parse_period("2024-02-29T13", RotationPeriod::Hourly), Some(hourly_start));
+    }
+
+    #[test]
+    fn test_parse_period_rejects_invalid_values() {
+        assert_eq!(parse_period("2024-13-01", RotationPeriod::Daily), None);
+        assert_eq!(parse_period("2024-1-01", RotationPeriod::Daily), None);
+        assert_eq!(parse_period("2024-01-01", RotationPeriod::Hourly), None);
+        assert_eq!(parse_period("2024-01-01T24", RotationPeriod::Hourly), None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use crate::bloom::containers::container::Container;
+use crate::bloom::budget::fits_budget;
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::bloom::sizing::{bloom_error_rate, bloom_size_for_error_rate};
+use crate::bloom::rotation::now;
+use crate::{ConstructionDetails, ConstructionType, ContainerDetails, DataSource, Params};
+
+/// Factor by which limit of each successor grows.
+pub(crate) const GROWTH: u64 = 2;
+
+/// Ratio by which error rate of each successor is tightened. Compound false positive rate of the whole set is bounded
+/// by error rate of the first container divided by (1 - TIGHTENING).
+const TIGHTENING: f64 = 0.5;
+
+/// Returns path of the successor file with given level, e.g. "day.blf" -> "day.1.blf".
+pub fn successor_path(path: &str, level: usize) -> String {
+    let file_path = Path::new(path);
+    match (file_path.file_stem(), file_path.extension()) {
+        (Some(stem), Some(extension)) => file_path
+            .with_file_name(format!("{}.{level}.{}", stem.to_string_lossy(), extension.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{path}.{level}"),
+    }
+}
+
+/// Returns paths of existing successor files of the given file, in order.
+pub fn discover_successors(path: &str) -> Vec<String> {
+    let mut paths = vec![];
+
+    for level in 1 .. {
+        let successor = successor_path(path, level);
+        if !<dyn Container>::exists(&successor) {
+            break;
+        }
+        paths.push(successor);
+    }
+
+    paths
+}
+
+/// Calculates construction details of the successor of the container with given construction details. Successor has
+/// bigger limit and tighter error rate.
+pub fn successor_construction_details(construction_details: &ConstructionDetails) -> ConstructionDetails {
+    let limit = construction_details.limit.saturating_mul(GROWTH);
+
+    match construction_details.construction_type {
+        ConstructionType::BloomLinesAndErrorRate => ConstructionDetails {
+            limit,
+            error_rate: construction_details.error_rate * TIGHTENING,
+            ..*construction_details
+        },
+        ConstructionType::BloomLinesAndSize => {
+            // Error rate is implied by size and limit, so we size successor for the tightened implied error rate.
+            let error_rate = bloom_error_rate(construction_details.size, construction_details.limit) * TIGHTENING;
+            ConstructionDetails {
+                limit,
+                size: bloom_size_for_error_rate(limit, error_rate),
+                ..*construction_details
+            }
+        }
+        ConstructionType::XXHLimitAndSize => ConstructionDetails {
+            // Fingerprint width is fixed, so error rate is tightened by lowering the slot occupancy instead.
+            limit,
+            size: (construction_details.size as f64 * GROWTH as f64 / TIGHTENING) as u64,
+            ..*construction_details
+        },
+        ConstructionType::CountMinLimitAndSize => ConstructionDetails {
+            // Overestimation grows with the number of lines per counter, so rows get wider faster than the limit.
+            limit,
+            size: (construction_details.size as f64 * GROWTH as f64 / TIGHTENING) as u64,
+            ..*construction_details
+        },
+        ConstructionType::HybridLimitAndSize => ConstructionDetails {
+            limit,
+            size: construction_details.size.saturating_mul(GROWTH),
+            ..*construction_details
+        },
+        // Exact and time-aware containers have no false positives worth tightening, so they only grow.
+        ConstructionType::ExactLimit64 | ConstructionType::ExactLimit128 | ConstructionType::TimedLimitAndTtl => ConstructionDetails {
+            limit,
+            ..*construction_details
+        },
+    }
+}
+
+/// Appends successor of the last container to the list of containers. Successor of a file container is stored in the
+/// sibling file, successor of a memory container stays in memory. If grow is false then successor is created with the
+/// same parameters as the last container. Returns false if successor doesn't fit into the memory budget.
+pub fn spawn_successor(params: &mut Params, grow: bool) -> bool {
+    let last = params.containers.last_mut().unwrap().get_container_details();
+    let construction_details = if grow { successor_construction_details(&last.construction_details) } else { last.construction_details };
+    let data_source = last.data_source;
+    let last_path = last.path.clone();
+    let partition = last.partition;
+
+    if !fits_budget(params, &construction_details) {
+        return false;
+    }
+
+    let path = match data_source {
+        DataSource::Memory => format!("memory.{}.blm", params.containers.len()),
+        DataSource::File => {
+            let paths: Vec<String> = params.containers.iter_mut().map(|container| container.get_container_details().path.clone()).collect();
+            // Base file is the first file of which the last container is the file itself or its successor.
+            let base_path = paths.iter()
+                .find(|path| **path == last_path || (1 ..= paths.len()).any(|level| successor_path(path, level) == last_path))
+                .unwrap();
+            let level = (1 ..).find(|level| !paths.contains(&successor_path(base_path, *level))).unwrap();
+            successor_path(base_path, level)
+        }
+    };
+
+    if params.debug_internal {
+        eprintln!("> All containers are full, spawning container \"{}\" with limit {}.", path, construction_details.limit);
+    }
+
+    params.containers.push(<dyn Container>::from_details(ContainerDetails {
+        path,
+        construction_details,
+        data_source,
+        created_at: now(),
+        expires_at: 0,
+        read_only: false,
+        hll: HyperLogLog::new(),
+        partition,
+        num_promotions: 0,
+    }));
+
+    true
+}
+
+/// Removes the oldest container and appends an empty one with the same parameters and path in its place.
+pub fn evict_oldest(params: &mut Params) {
+    let mut oldest = params.containers.remove(0);
+    let details = oldest.get_container_details();
+
+    if params.debug_internal {
+        eprintln!("> All containers are full, evicting oldest container \"{}\".", details.path);
+    }
+
+    params.containers.push(<dyn Container>::from_details(ContainerDetails {
+        path: details.path.clone(),
+        construction_details: details.construction_details,
+        data_source: details.data_source,
+        created_at: now(),
+        expires_at: details.expires_at,
+        read_only: false,
+        hll: HyperLogLog::new(),
+        partition: details.partition,
+        num_promotions: 0,
+    }));
+}
@@ -0,0 +1,221 @@
+use std::cmp::max;
+use std::f64::consts::LN_2;
+use crate::bloom::containers::container_memory_count_min::DEPTH;
+use crate::bloom::containers::container_memory_xxh::{calc_num_slots, KEY_BITS, NUM_TRIES, SLOTS_PER_WORD};
+
+/// False positive rate targeted by filters which are sized automatically, e.g. the one hybrid container spills into.
+pub const DEFAULT_ERROR_RATE: f64 = 0.001;
+
+/// Calculates expected false positive rate of the Bloom filter with given size in bytes, holding given number of items
+/// and using optimal number of hash functions.
+pub fn bloom_error_rate(size: u64, limit: u64) -> f64 {
+    if limit == 0 {
+        return 0.0;
+    }
+    let bits_per_item = (size * 8) as f64 / limit as f64;
+    (-bits_per_item * LN_2 * LN_2).exp()
+}
+
+/// Estimates number of items of the Bloom filter with given number of bits, of which num_set_bits are set, using
+/// num_hashes hash functions. Returns infinity for the completely full filter.
+pub fn bloom_num_items_from_fill(num_bits: u64, num_set_bits: u64, num_hashes: u64) -> f64 {
+    -(num_bits as f64 / num_hashes as f64) * (1.0 - num_set_bits as f64 / num_bits as f64).ln()
+}
+
+/// Calculates false positive rate of the Bloom filter with given number of bits, of which num_set_bits are set, using
+/// num_hashes hash functions.
+pub fn bloom_error_rate_from_fill(num_bits: u64, num_set_bits: u64, num_hashes: u64) -> f64 {
+    (num_set_bits as f64 / num_bits as f64).powi(num_hashes as i32)
+}
+
+/// Calculates size in bytes of the Bloom filter holding given number of items with given false positive rate.
+pub fn bloom_size_for_error_rate(limit: u64, error_rate: f64) -> u64 {
+    (limit as f64 * error_rate.ln() / (-8.0 * LN_2 * LN_2)).ceil() as u64
+}
+
+/// Calculates optimal number of hash functions of the Bloom filter with given size in bytes holding given number of
+/// items. Rounded up, as in filters created with the bloomfilter crate.
+pub fn bloom_num_hashes(size: u64, limit: u64) -> u64 {
+    if limit == 0 {
+        return 1;
+    }
+    max(1, ((size * 8) as f64 / limit as f64 * LN_2).ceil() as u64)
+}
+
+/// Calculates expected false positive rate of the xxHash filter with given size in bytes holding given number of items.
+/// Lookup is positive when fingerprint matches in one of the probed slots, or when all of them are occupied.
+pub fn xxh_error_rate(size: u64, limit: u64) -> f64 {
+    let num_slots = calc_num_slots(size);
+    if num_slots == 0 {
+        return 1.0;
+    }
+    xxh_error_rate_for_occupancy(limit as f64 / num_slots as f64)
+}
+
+/// Calculates expected false positive rate of the xxHash filter with given ratio of items to slots.
+fn xxh_error_rate_for_occupancy(occupancy: f64) -> f64 {
+    if occupancy >= 1.0 {
+        return 1.0;
+    }
+    let match_rate = 0.5f64.powi(KEY_BITS as i32);
+    let runs = xxh_run_probabilities(occupancy);
+
+    let mut error_rate = 0.0;
+    for (i, run) in runs.iter().enumerate() {
+        error_rate += run * (1.0 - match_rate).powi(i as i32) * match_rate;
+    }
+    (error_rate + runs[NUM_TRIES as usize - 1] * (1.0 - match_rate).powi(NUM_TRIES as i32)).min(1.0)
+}
+
+/// Calculates probabilities that first 1 ..= NUM_TRIES probed slots are all occupied. Colliding keys are moved to the
+/// following slots, so occupied slots cluster and runs are much likelier than for independently occupied slots. Each
+/// slot is the home of Poisson distributed number of keys, and keys which didn't fit into previous slots are carried
+/// over to it. The stationary distribution of the carry is found by iterating the chain.
+fn xxh_run_probabilities(occupancy: f64) -> [f64; NUM_TRIES as usize] {
+    const MAX_CARRY: usize = 64;
+
+    let mut arrivals = [0.0; MAX_CARRY + 1];
+    arrivals[0] = (-occupancy).exp();
+    for i in 1 ..= MAX_CARRY {
+        arrivals[i] = arrivals[i - 1] * occupancy / i as f64;
+    }
+
+    // Keys arriving to the slot with given carry: one of them stays, the rest are carried to the next slot.
+    let step = |carry: &[f64; MAX_CARRY + 1], occupied_only: bool| {
+        let mut next = [0.0; MAX_CARRY + 1];
+        for (c, probability) in carry.iter().enumerate().filter(|(_, probability)| **probability > 0.0) {
+            for (a, arrival) in arrivals.iter().enumerate().take(MAX_CARRY + 1 - c) {
+                if c + a == 0 {
+                    if !occupied_only {
+                        next[0] += probability * arrival;
+                    }
+                } else {
+                    next[c + a - 1] += probability * arrival;
+                }
+            }
+        }
+        next
+    };
+
+    let mut carry = [0.0; MAX_CARRY + 1];
+    carry[0] = 1.0;
+    for _ in 0 .. 10000 {
+        let next = step(&carry, false);
+        let change: f64 = next.iter().zip(carry.iter()).map(|(a, b)| (a - b).abs()).sum();
+        carry = next;
+        if change < 1e-12 {
+            break;
+        }
+    }
+
+    let mut runs = [0.0; NUM_TRIES as usize];
+    for run in runs.iter_mut() {
+        carry = step(&carry, true);
+        *run = carry.iter().sum();
+    }
+    runs
+}
+
+/// Calculates size in bytes of the xxHash filter holding given number of items with given false positive rate. Returns
+/// None if the rate can't be reached.
+pub fn xxh_size_for_error_rate(limit: u64, error_rate: f64) -> Option<u64> {
+    if error_rate <= 0.0 {
+        return None;
+    }
+
+    // Error rate rises with occupancy, so we bisect it and then settle the exact number of bytes.
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0 .. 60 {
+        let mid = (low + high) / 2.0;
+        if xxh_error_rate_for_occupancy(mid) <= error_rate { low = mid } else { high = mid }
+    }
+    if low <= 0.0 {
+        return None;
+    }
+    let size = (limit as f64 / low / SLOTS_PER_WORD as f64 * 8.0).ceil();
+    if size >= (u64::MAX / 16) as f64 {
+        return None;
+    }
+
+    // Bisected occupancy is only approximate for large filters, so the exact number of bytes is bisected around it.
+    let (mut low, mut high) = (max(1, size as u64 / 2), max(1, size as u64) * 2);
+    if xxh_error_rate(high, limit) > error_rate {
+        return None;
+    }
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if xxh_error_rate(mid, limit) <= error_rate { high = mid } else { low = mid + 1 }
+    }
+    Some(low)
+}
+
+/// Calculates probability that count of the value in the count-min sketch with given size in bytes holding given number
+/// of distinct values is overestimated, i.e. that its counters in all the rows are shared with other values. Conservative
+/// update overestimates less, so it's an upper bound.
+pub fn count_min_error_rate(size: u64, limit: u64) -> f64 {
+    let width = size / 4 / DEPTH;
+    if width == 0 {
+        return 1.0;
+    }
+    (1.0 - (-(limit as f64) / width as f64).exp()).powi(DEPTH as i32)
+}
+
+/// Calculates size in bytes of the count-min sketch holding given number of distinct values whose counts are
+/// overestimated with given probability.
+pub fn count_min_size_for_error_rate(limit: u64, error_rate: f64) -> u64 {
+    // Number of values per counter at which all the counters of a value are shared with the given probability.
+    let load = -(1.0 - error_rate.powf(1.0 / DEPTH as f64)).ln();
+    (limit as f64 / load).ceil() as u64 * DEPTH * 4
+}
+
+/// Calculates expected false positive rate of the exact container holding given number of digests of given width.
+pub fn exact_error_rate(limit: u64, digest_bits: i32) -> f64 {
+    (limit as f64 * 0.5f64.powi(digest_bits)).min(1.0)
+}
+
+/// Formats number of bytes using binary units, e.g. "1.50 GiB".
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{size} B") } else { format!("{value:.2} {}", UNITS[unit]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_size_and_error_rate_agree() {
+        let size = bloom_size_for_error_rate(1000000, 0.001);
+        assert!((bloom_error_rate(size, 1000000) - 0.001).abs() < 0.0001);
+        assert_eq!(bloom_num_hashes(size, 1000000), 10);
+    }
+
+    #[test]
+    fn test_xxh_error_rate_counts_clustering() {
+        // Rates measured by querying 1M new lines of filters holding 1M lines.
+        for (occupancy, measured) in [(0.1, 4.28e-4), (0.18, 3.7e-3), (0.3, 2.2e-2), (0.5, 0.111)] {
+            let estimated = xxh_error_rate_for_occupancy(occupancy);
+            assert!(estimated > measured * 0.7 && estimated < measured * 1.3, "{occupancy}: {estimated} vs {measured}");
+        }
+    }
+
+    #[test]
+    fn test_count_min_size_for_error_rate() {
+        let size = count_min_size_for_error_rate(1000000, 0.001);
+        assert!((count_min_error_rate(size, 1000000) - 0.001).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_xxh_size_for_error_rate() {
+        let size = xxh_size_for_error_rate(1000000, 0.001).unwrap();
+        assert!(xxh_error_rate(size, 1000000) <= 0.001);
+        assert!(xxh_error_rate(size - 1, 1000000) > 0.001);
+        assert_eq!(xxh_size_for_error_rate(1000000, 0.0), None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::bloom::rotation::days_from_civil;
+
+/// Parses duration like "90", "90s", "10m", "2h" or "7d" into number of seconds.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last()? {
+        's' => (&value[.. value.len() - 1], 1),
+        'm' => (&value[.. value.len() - 1], 60),
+        'h' => (&value[.. value.len() - 1], 3600),
+        'd' => (&value[.. value.len() - 1], 86400),
+        _ => (value, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses time zone suffix of the ISO 8601 time ("Z", "+HH:MM", "+HHMM" or "+HH", may be negative) into its offset from
+/// UTC in seconds. Time without the suffix is treated as UTC.
+fn parse_offset(value: &str) -> Option<i64> {
+    let sign = match value.chars().next() {
+        None => return Some(0),
+        Some('Z') if value.len() == 1 => return Some(0),
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None,
+    };
+
+    if !value.is_ascii() {
+        return None;
+    }
+    let (hours, minutes) = match value.len() {
+        3 => (&value[1 .. 3], "00"),
+        5 => (&value[1 .. 3], &value[3 .. 5]),
+        6 if value.as_bytes()[3] == b':' => (&value[1 .. 3], &value[4 .. 6]),
+        _ => return None,
+    };
+    if !hours.bytes().chain(minutes.bytes()).all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses timestamp given either as Unix time in seconds (fraction is ignored) or as ISO 8601 date and time, e.g.
+/// "2024-05-01T13:45:10Z" or "2024-05-01T15:45:10+02:00" (fraction is ignored, time zone offset is applied).
+pub fn parse_timestamp(value: &str) -> Option<u64> {
+    if let Some((seconds, _)) = value.split_once('.').filter(|(seconds, _)| !seconds.contains('-')) {
+        return seconds.parse().ok();
+    }
+
+    if let Ok(seconds) = value.parse() {
+        return Some(seconds);
+    }
+
+    let (date, time) = value.split_once('T')?;
+    let date: Vec<&str> = date.split('-').collect();
+    let suffix = time.get(8 ..)?;
+    let time: Vec<&str> = time.get(.. 8)?.split(':').collect();
+
+    // Fraction of the second is skipped, the rest is the time zone.
+    let zone = match suffix.strip_prefix('.') {
+        Some(fraction) => fraction.trim_start_matches(|c: char| c.is_ascii_digit()),
+        None => suffix,
+    };
+    let offset = parse_offset(zone)?;
+
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+
+    let year: i64 = date[0].parse().ok()?;
+    let month: u32 = date[1].parse().ok()?;
+    let day: u32 = date[2].parse().ok()?;
+    let hour: u64 = time[0].parse().ok()?;
+    let minute: u64 = time[1].parse().ok()?;
+    let second: u64 = time[2].parse().ok()?;
+
+    if !(1 ..= 12).contains(&month) || !(1 ..= 31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+
+    let timestamp = days * 86400 + (hour * 3600 + minute * 60 + second) as i64 - offset;
+    u64::try_from(timestamp).ok()
+}
+
+/// Splits line into the given 1-based whitespace-separated field and the rest of the line, which is used as a key, so
+/// lines differing only in the timestamp are treated as the same line.
+pub fn extract_field(line: &str, field: usize) -> Option<(&str, String)> {
+    let value = line.split_whitespace().nth(field - 1)?;
+    let key: Vec<&str> = line.split_whitespace().enumerate().filter(|(idx, _)| *idx != field - 1).map(|(_, part)| part).collect();
+    Some((value, key.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("10m"), Some(600));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("7d"), Some(604800));
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("10x"), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1709214310"), Some(1709214310));
+        assert_eq!(parse_timestamp("1709214310.123"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T13:45:10Z"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T13:45:10.500+00:00"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T13:45:10"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T15:45:10+02:00"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T15:45:10.5+0200"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T08:45:10-05"), Some(1709214310));
+        assert_eq!(parse_timestamp("2024-02-29T13:45:10 UTC"), None);
+        assert_eq!(parse_timestamp("2024-02-29T13:45:10+2:00"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_extract_field() {
+        let (value, key) = extract_field("2024-02-29T13:45:10Z ERROR disk full", 1).unwrap();
+        assert_eq!(value, "2024-02-29T13:45:10Z");
+        assert_eq!(key, "ERROR disk full");
+        assert!(extract_field("ERROR", 2).is_none());
+    }
+}
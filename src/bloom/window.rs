@@ -0,0 +1,101 @@
+use crate::bloom::containers::container::Container;
+use crate::bloom::containers::container_memory_xxh::MemoryContainerXXH;
+use crate::bloom::hyperloglog::HyperLogLog;
+use crate::bloom::rotation::now;
+use crate::bloom::scalable::successor_path;
+use crate::bloom::sizing::DEFAULT_ERROR_RATE;
+use crate::{ConstructionDetails, ConstructionType, ContainerDetails, DataSource, Params};
+
+/// Sliding window settings. Window is made of rotating generations, each holding window / generations lines, so
+/// membership reflects between window * (generations - 1) / generations and window last inserted lines.
+pub struct Window {
+    pub size: u64,
+    pub generations: u64,
+}
+
+/// Returns path of the generation with given index. First generation uses the base path itself.
+fn generation_path(base_path: &str, idx: usize) -> String {
+    if idx == 0 { base_path.to_string() } else { successor_path(base_path, idx) }
+}
+
+/// Returns construction details of a single generation. When no construction details were passed, xxHash filter sized
+/// for the generation's limit is used. Lines are looked up in all the generations, so each one gets an equal share of the
+/// DEFAULT_ERROR_RATE.
+pub fn generation_construction_details(window: &Window, construction_details: Option<ConstructionDetails>) -> ConstructionDetails {
+    let limit = window.size.div_ceil(window.generations);
+
+    match construction_details {
+        Some(construction_details) => ConstructionDetails {
+            limit,
+            ..construction_details
+        },
+        None => ConstructionDetails {
+            construction_type: ConstructionType::XXHLimitAndSize,
+            limit,
+            size: MemoryContainerXXH::calc_size_for_limit_and_error_rate(limit, DEFAULT_ERROR_RATE / window.generations as f64),
+            error_rate: 0.0,
+            ttl: 0,
+            shards: 1,
+        },
+    }
+}
+
+/// Adds containers of all generations to the params. Generations of the file window are stored in base file and its
+/// siblings (file.1.blf, file.2.blf, ...), from the oldest.
+pub fn open_window_containers(params: &mut Params, window: &Window, base_path: Option<&String>, construction_details: ConstructionDetails) {
+    for idx in 0 .. window.generations as usize {
+        let (path, data_source) = match base_path {
+            Some(base_path) => (generation_path(base_path, idx), DataSource::File),
+            None => (format!("memory.{idx}.blm"), DataSource::Memory),
+        };
+
+        if matches!(data_source, DataSource::File) && <dyn Container>::exists(&path) {
+            params.containers.push(<dyn Container>::from_file(&path, params.write_mode));
+            continue;
+        }
+
+        params.containers.push(<dyn Container>::from_details(ContainerDetails {
+            path,
+            construction_details,
+            data_source,
+            created_at: now(),
+            expires_at: 0,
+            read_only: false,
+            hll: HyperLogLog::new(),
+            partition: params.partition,
+            num_promotions: 0,
+        }));
+    }
+}
+
+/// Drops the oldest generation and appends an empty one. Generations are renamed by position, so the oldest
+/// generation of the file window is always stored in the base file.
+pub fn slide_window(params: &mut Params) {
+    let mut oldest = params.containers.remove(0);
+    let details = oldest.get_container_details();
+    let base_path = details.path.clone();
+
+    if params.debug_internal {
+        eprintln!("> All generations are full, dropping the oldest one.");
+    }
+
+    params.containers.push(<dyn Container>::from_details(ContainerDetails {
+        path: base_path.clone(),
+        construction_details: details.construction_details,
+        data_source: details.data_source,
+        created_at: now(),
+        expires_at: 0,
+        read_only: false,
+        hll: HyperLogLog::new(),
+        partition: details.partition,
+        num_promotions: 0,
+    }));
+
+    for (idx, container) in params.containers.iter_mut().enumerate() {
+        let details = container.get_container_details();
+        details.path = match details.data_source {
+            DataSource::File => generation_path(&base_path, idx),
+            DataSource::Memory => format!("memory.{idx}.blm"),
+        };
+    }
+}
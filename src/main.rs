@@ -1,6 +1,5 @@
 extern crate bit_set;
 extern crate bit_vec;
-extern crate bloomfilter;
 extern crate crc32fast;
 extern crate parse_size;
 extern crate memory_stats;
@@ -9,18 +8,45 @@ extern crate byteorder;
 extern crate num_enum;
 
 mod bloom {
+    pub mod budget;
+    pub mod combine;
     pub mod containers;
+    pub mod hyperloglog;
+    pub mod partition;
+    pub mod pipeline;
+    pub mod plan;
     pub mod process;
+    pub mod rotation;
+    pub mod scalable;
+    pub mod sizing;
+    pub mod ttl;
+    pub mod window;
 }
 
 use std::{env};
-use std::cmp::max;
+use std::cmp::min;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufWriter;
 
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use num_enum::TryFromPrimitive;
 use parse_size::parse_size;
+use bloom::budget::{containers_footprint, fit_to_budget, footprint};
+use bloom::combine::{combine, compare, SetOperation};
 use bloom::containers::container::Container;
+use bloom::containers::container_memory_exact::MemoryContainerExact;
+use bloom::containers::container_sharded::{is_valid_num_shards, MAX_SHARDS};
+use bloom::hyperloglog::HyperLogLog;
+use bloom::partition::{partition_name, ForeignLines, Partition};
+use bloom::plan::plan;
 use bloom::process::process;
+use bloom::rotation::{now, open_rotated_containers, retained_paths, ActiveRotation, Rotation, RotationPeriod, DATE_PLACEHOLDER};
+use bloom::scalable::discover_successors;
+use bloom::sizing::{bloom_error_rate, count_min_error_rate, count_min_size_for_error_rate, format_size, xxh_error_rate, DEFAULT_ERROR_RATE};
+use bloom::ttl::parse_duration;
+use bloom::window::{generation_construction_details, open_window_containers, Window};
 
 #[derive(Copy, Clone)]
 enum DataSource {
@@ -37,21 +63,86 @@ enum ConstructionType {
     BloomLinesAndErrorRate,
     // -xs NUM
     XXHLimitAndSize,
+    // -el NUM[,64]
+    ExactLimit64,
+    // -el NUM,128
+    ExactLimit128,
+    // -hls NUM,NUM[UNIT]
+    HybridLimitAndSize,
+    // --ttl DURATION[,NUM]
+    TimedLimitAndTtl,
+    // -cms NUM,NUM[UNIT]
+    CountMinLimitAndSize,
 }
 
 
+/// Policy applied when all containers are full.
+#[derive(Copy, Clone, PartialEq)]
+pub enum OnFullPolicy {
+    // Silently disables writes (default).
+    Ignore,
+    // Exits with non-zero code.
+    Fail,
+    // Prints warning once and disables writes.
+    Warn,
+    // Creates new container with the same parameters as the last one.
+    Rotate,
+    // Replaces the oldest container with an empty one.
+    Evict,
+    // Stops reading the input.
+    Stop,
+    // Creates successor with bigger limit and tighter error rate.
+    Scale,
+    // Drops the oldest generation of the sliding window (set by --window).
+    Slide,
+}
+
+/// Policy applied when line is found in a container other than the current writable one.
+#[derive(Copy, Clone, PartialEq)]
+pub enum PromotePolicy {
+    // Copies the line into the writable container, so hot lines survive rotations (default).
+    Always,
+    // Never copies the line, so containers hold lines first seen in them.
+    Never,
+    // Copies the line with given probability, so only frequently repeated lines are likely to be copied.
+    Probabilistic,
+}
+
+/// What is printed for the input lines.
+#[derive(Copy, Clone, PartialEq)]
+pub enum OutputMode {
+    // Prints new lines (or duplicates with -i) as they come (default).
+    Lines,
+    // Prints line the first time it repeats.
+    Repeated,
+    // Prints lines which occurred only once, at the end of input.
+    Unique,
+    // Prints each distinct line with its estimated number of occurrences, at the end of input.
+    Count,
+    // Prints the most frequent lines with their estimated number of occurrences, at the end of input.
+    Top,
+}
+
 #[derive(Copy, Clone)]
 struct ConstructionDetails {
     construction_type: ConstructionType,
     limit: u64,
     error_rate: f64,
     size: u64,
+    ttl: u64,  // Number of seconds after which values expire (0 if never)
+    shards: usize,  // Number of shards the container is split into (1 if not sharded)
 }
 
 pub struct ContainerDetails {
     path: String,
     data_source: DataSource,
-    construction_details: ConstructionDetails
+    construction_details: ConstructionDetails,
+    created_at: u64,  // Unix timestamp of container creation (0 if unknown)
+    expires_at: u64,  // Unix timestamp after which container is expired (0 if never)
+    read_only: bool,  // Whether container is only checked and never written to
+    hll: HyperLogLog,  // Estimator of the number of distinct lines written into the container
+    partition: Option<Partition>,  // Part of the input whose lines the container holds (None for the whole input)
+    num_promotions: u64  // Number of lines found in other containers and copied into this one while running
 }
 
 pub struct Params {
@@ -63,7 +154,60 @@ pub struct Params {
     inverse: bool,
     debug_internal: bool,
     line_buffered: bool,  // New field for buffering mode
-    silent_warnings: bool  // New field for silencing warnings
+    silent_warnings: bool,  // New field for silencing warnings
+    on_full: OnFullPolicy,  // What to do when all containers are full
+    num_on_full_events: u64,  // Number of times all containers got full and the on-full policy was applied
+    containers_full: bool,  // Whether all containers are full since the on-full policy was last applied
+    time_aware: bool,  // Whether containers need current time for each line
+    ttl_field: Option<usize>,  // 1-based field of the line holding its timestamp
+    promote: PromotePolicy,  // What to do with lines found in non-writable containers
+    promote_probability: f64,  // Probability of promotion for the probabilistic policy
+    random_state: u64,  // State of the pseudo-random generator used by probabilistic promotion
+    max_occurrences: Option<u64>,  // Number of occurrences of each line to let through
+    output_mode: OutputMode,  // What is printed for the input lines
+    distinct_lines: Vec<(String, Option<String>)>,  // Distinct lines and their keys (if differ) printed at the end of input
+    distinct_digests: HashSet<u128>,  // Digests of the keys of distinct lines, so each of them is collected once
+    top_size: usize,  // Number of the most frequent lines to report
+    top_every: Option<u64>,  // Number of lines after which the most frequent lines are reported again
+    estimate_only: bool,  // Whether lines are only counted to estimate number of distinct ones
+    hll: HyperLogLog,  // Estimator of the number of distinct lines in estimate-only mode
+    memory_budget: Option<u64>,  // Number of bytes all the containers may take together
+    budget_exhausted: bool,  // Whether another container didn't fit into the memory budget
+    shared: bool,  // Whether other processes write into the same files at the same time
+    threads: usize,  // Number of threads reading and looking lines up
+    partition: Option<Partition>,  // Part of the input whose lines are processed (None for the whole input)
+    foreign_lines: ForeignLines,  // What to do with lines of other partitions
+    rotation: Option<ActiveRotation>  // Time-based rotation whose files are open, checked for the period change
+}
+
+impl Params {
+    /// Checks whether lines have to be counted instead of only checked for existence.
+    fn needs_counts(&self) -> bool {
+        self.output_mode != OutputMode::Lines || self.max_occurrences.is_some_and(|max_occurrences| max_occurrences > 1)
+    }
+}
+
+/// Returns name of the on-full policy as passed in --on-full parameter.
+pub fn on_full_policy_name(policy: OnFullPolicy) -> &'static str {
+    match policy {
+        OnFullPolicy::Ignore => "ignore",
+        OnFullPolicy::Fail => "fail",
+        OnFullPolicy::Warn => "warn",
+        OnFullPolicy::Rotate => "rotate",
+        OnFullPolicy::Evict => "evict",
+        OnFullPolicy::Stop => "stop",
+        OnFullPolicy::Scale => "scale",
+        OnFullPolicy::Slide => "slide",
+    }
+}
+
+/// Returns name of the promote policy as passed in --promote parameter.
+pub fn promote_policy_name(policy: PromotePolicy) -> &'static str {
+    match policy {
+        PromotePolicy::Always => "always",
+        PromotePolicy::Never => "never",
+        PromotePolicy::Probabilistic => "probabilistic",
+    }
 }
 
 fn print_help() {
@@ -72,6 +216,31 @@ fn print_help() {
     println!();
     println!("USAGE:");
     println!("  bloom_filter [OPTIONS]");
+    println!("  bloom_filter plan --items NUM (--fpr RATE | --memory SIZE)");
+    println!("  bloom_filter (merge | intersect | subtract) -o OUTPUT FILE FILE [FILE...]");
+    println!("  bloom_filter compare [--json] FILE FILE");
+    println!();
+    println!("SUBCOMMANDS:");
+    println!();
+    println!("  plan                                        Prints size, number of hash functions or fingerprint width, expected false");
+    println!("                                              positive rate and lookup cost of each container type holding NUM lines");
+    println!("                                              with given false positive rate or memory size, with ready to paste");
+    println!("                                              arguments.");
+    println!();
+    println!("  merge                                       Writes union of compatible filter files (same type, size and hash");
+    println!("                                              keys) into OUTPUT. Number of writes is summed, so lines written into");
+    println!("                                              several files are counted more than once.");
+    println!();
+    println!("  intersect                                   Writes intersection of compatible filter files into OUTPUT, so only lines");
+    println!("                                              written into all of them are found in it.");
+    println!();
+    println!("  subtract                                    Writes lines of the first xxHash or exact file which aren't in any of the");
+    println!("                                              other files into OUTPUT. Intersect and subtract print number of lines");
+    println!("                                              and false positive rate of the result estimated from its bit fill.");
+    println!();
+    println!("  compare                                     Estimates number of lines of two compatible filter files, number of");
+    println!("                                              their common lines, lines of their union and their Jaccard similarity.");
+    println!("                                              Prints a JSON object with --json.");
     println!();
     println!("DEFAULT BEHAVIOR:");
     println!();
@@ -83,6 +252,20 @@ fn print_help() {
     println!();
     println!("  -w,   --write                               Creates an empty Bloom filter file or updates an existing one.");
     println!();
+    println!("  --shared                                    Lets several bloom processes write into the same files at the same time.");
+    println!("                                              Files are updated in place with atomic operations, so lines written by");
+    println!("                                              one process are seen by the others immediately. Requires -w and works");
+    println!("                                              with -xls, -bls and -ble containers only. xxHash files created by older");
+    println!("                                              versions can't be shared, as their slots can't be written atomically.");
+    println!();
+    println!("  --partition I/N                             Splits the input into N partitions by the hash of the line and processes");
+    println!("                                              only the I-th one (1 to N), e.g. to dedup the same stream on N hosts.");
+    println!("                                              Partition is stored in the files, which are refused when used with other");
+    println!("                                              partition.");
+    println!();
+    println!("  --foreign-lines drop|pass|FILE              What to do with lines of other partitions: drop them (default), pass");
+    println!("                                              them through without looking them up, or write them into FILE.");
+    println!();
     println!("  -xls,  --xxh-limit-and-size NUM,NUM         Uses xxHash filter. First number limits the number of lines to write into");
     println!("                                              the Bloom filter for each file. You can use K, M & G units for the limit.");
     println!("                                              Second number specifies Bloom filter size in bytes or given unit.");
@@ -100,7 +283,119 @@ fn print_help() {
     println!("                                              for the given file (> 0 and < 1). Use -ble once to specify settings for");
     println!("                                              all files or use it multiple times for each file.");
     println!();
-    println!("  -i,  --inverse                              Will output lines that .");
+    println!("  -el,  --exact-limit NUM[,BITS]              Uses exact hash-set container which stores full xxHash digests, so there");
+    println!("                                              are no false positives. NUM limits the number of lines to write into the");
+    println!("                                              container. BITS is digest width, either 64 (default) or 128. Table size");
+    println!("                                              is derived from the limit. Use -el once to specify settings for all files");
+    println!("                                              or use it multiple times for each file.");
+    println!();
+    println!("  -hls, --hybrid-limit-and-size NUM,NUM       Uses hybrid container which stores exact digests until they reach the given");
+    println!("                                              memory size, then converts into xxHash filters sized for the stored lines");
+    println!("                                              and 0.1% false positive rate, adding larger ones as more lines arrive.");
+    println!("                                              First number limits the number of lines to write into the container.");
+    println!("                                              Second number specifies memory threshold of the exact phase in bytes or");
+    println!("                                              given unit. Use -hls once to specify settings for all files or use it");
+    println!("                                              multiple times for each file.");
+    println!();
+    println!("  -cms, --count-min-limit-and-size NUM,NUM    Uses count-min sketch container which estimates number of occurrences of");
+    println!("                                              each line (never lower than the real one). First number limits the number");
+    println!("                                              of distinct lines to write into the container. Second number specifies");
+    println!("                                              memory size in bytes or given unit. Use -cms once to specify settings for");
+    println!("                                              all files or use it multiple times for each file.");
+    println!();
+    println!("  --ttl DURATION[,NUM]                        Uses time-aware container in which lines expire after DURATION (e.g. 90s,");
+    println!("                                              10m, 2h, 7d), so they are printed again once expired. NUM limits the number");
+    println!("                                              of live lines (default 10M). Lines are kept as 32-bit fingerprints, so");
+    println!("                                              new line is taken for a live one with probability of about NUM / 2^32.");
+    println!("                                              Use --ttl once to specify settings for all files or use it multiple times");
+    println!("                                              for each file.");
+    println!();
+    println!("  --ttl-field NUM                             Takes line's timestamp from its NUM-th whitespace-separated field (Unix time");
+    println!("                                              or ISO 8601, UTC unless time zone offset is given) instead of the wall");
+    println!("                                              clock, e.g. for replaying old logs.");
+    println!("                                              The field itself is ignored when comparing lines.");
+    println!();
+    println!("  --on-full POLICY                            What to do when all containers are full:");
+    println!("                                                ignore - silently stop writing new lines (default),");
+    println!("                                                fail   - exit with code 2,");
+    println!("                                                warn   - print warning once and stop writing new lines,");
+    println!("                                                rotate - create new container with parameters of the last one,");
+    println!("                                                evict  - replace the oldest container with an empty one,");
+    println!("                                                stop   - stop reading the input,");
+    println!("                                                scale  - same as -sc.");
+    println!();
+    println!("  --memory-budget SIZE                        Limits memory taken by all the containers together. Budget left by existing");
+    println!("                                              files and fixed-size (-el, --ttl) containers is split between the new ones");
+    println!("                                              proportionally to their limits, overriding their sizes. Without container");
+    println!("                                              options, the type with the lowest false positive rate is picked. Refuses");
+    println!("                                              to start if existing files don't fit, and successors spawned by -sc or");
+    println!("                                              --on-full rotate are only created while they fit.");
+    println!();
+    println!("  --shards NUM                                Splits each new container into NUM shards (power of two, up to 4096)");
+    println!("                                              selected by the hash of the line, each sharing the limit and size evenly.");
+    println!("                                              Shards of the file are stored in separate files (e.g. seen.shard-0003.blf)");
+    println!("                                              and only modified ones are rewritten. Existing sharded files are opened by");
+    println!("                                              their base path without --shards.");
+    println!();
+    println!("  --threads NUM                               Reads and looks lines up in NUM threads (default 1, 0 uses all the cores).");
+    println!("                                              Lines are still written into containers and printed in input order, so");
+    println!("                                              the output doesn't change. Regular files passed on the standard input are");
+    println!("                                              read in parallel. Ignored with --ttl, --ttl-field, --window, --estimate,");
+    println!("                                              --max-occurrences, --repeated, --unique, --count, --top and --on-full");
+    println!("                                              evict.");
+    println!();
+    println!("  --max-occurrences NUM                       Lets each line through at most NUM times instead of once. Counts are kept");
+    println!("                                              in count-min sketch containers (10M lines by default, see -cms), so they");
+    println!("                                              persist in files between runs.");
+    println!();
+    println!("  --promote POLICY[,PROBABILITY]              What to do with lines found in a container other than the writable one:");
+    println!("                                                always        - copy line into the writable container, so it stays");
+    println!("                                                                known after older containers are dropped (default),");
+    println!("                                                never         - keep first-seen semantics, don't copy,");
+    println!("                                                probabilistic - copy with given PROBABILITY (default 0.1), so mostly");
+    println!("                                                                frequently repeated lines are copied.");
+    println!();
+    println!("  -sc,  --scalable                            When all containers are full, spawns successor with doubled limit and halved");
+    println!("                                              error rate, so compound error rate stays bounded. Successors of the file");
+    println!("                                              containers are stored in sibling files (file.1.blf, file.2.blf, ...) and");
+    println!("                                              are loaded back whenever the file is used again.");
+    println!();
+    println!("  --rotate PATTERN,PERIOD,KEEP                Rotates filter files by time. PATTERN is a file path with {{date}} placeholder");
+    println!("                                              replaced by the current period (UTC), e.g. seen.{{date}}.blf. PERIOD is hourly");
+    println!("                                              or daily. Lines are written into the current period's file and checked");
+    println!("                                              against files of the last KEEP periods. Older files are removed in write");
+    println!("                                              mode and ignored otherwise. When the period changes while running, its");
+    println!("                                              file is saved and the next period's one is opened. Can't be combined with -f.");
+    println!();
+    println!("  --window NUM[,GENERATIONS]                  Deduplicates only within the last NUM lines. Window is split into GENERATIONS");
+    println!("                                              (default 4) containers, the oldest one is dropped when all are full, so");
+    println!("                                              lines are remembered for between NUM * (GENERATIONS - 1) / GENERATIONS and");
+    println!("                                              NUM inserted lines. Single -f stores generations in FILE, FILE.1, ...");
+    println!();
+    println!("  -i,  --inverse                              Will output lines that were already seen (duplicates) instead of new ones.");
+    println!();
+    println!("  --all-repeated                              Same as -i, outputs all occurrences of the lines except the first ones.");
+    println!();
+    println!("  --repeated                                  Will output line only the first time it repeats, like \"sort | uniq -d\".");
+    println!();
+    println!("  --unique                                    Will output lines which occurred only once at the end of input, like");
+    println!("                                              \"sort | uniq -u\". Lines are kept in memory in order of appearance.");
+    println!();
+    println!("  --count                                     Will output each distinct line prefixed by its estimated number of");
+    println!("                                              occurrences at the end of input, like \"sort | uniq -c\". Lines are kept in");
+    println!("                                              memory in order of appearance.");
+    println!();
+    println!("  --top NUM[,EVERY]                           Will output NUM most frequent lines prefixed by their estimated number of");
+    println!("                                              occurrences at the end of input and every EVERY lines, if given. Tracked");
+    println!("                                              lines are stored in count-min sketch files, so sketches of several files");
+    println!("                                              are added together, e.g. bloom --top 10 -f mon.blf -f tue.blf < /dev/null.");
+    println!();
+    println!("  --estimate                                  Will only estimate number of distinct lines (using HyperLogLog, about 1.6%");
+    println!("                                              error) and output parameters of filters recommended to hold them.");
+    println!();
+    println!("                                              Modes above (except -i and --estimate) count lines in count-min sketch containers. By");
+    println!("                                              default the sketch holds 10M lines, whose counts are overestimated with 0.1%");
+    println!("                                              probability, or takes the whole --memory-budget. Use -cms to size it yourself.");
     println!();
     println!("  -d,  --debug                                Will output debug information.");
     println!("  -dm,  --debug-memory                        Will output debug information about memory usage.");
@@ -123,7 +418,31 @@ fn print_help() {
     println!("  $ bloom_filter  -bls 10,100MiB  < input.txt");
 }
 
+/// Expected false positive rate above which size passed in -bls or -xls is reported as too small for the limit.
+const WARN_ERROR_RATE: f64 = 0.1;
+
 fn main() {
+    if env::args().nth(1).as_deref() == Some("plan") {
+        plan(&env::args().skip(2).collect::<Vec<String>>());
+        return;
+    }
+
+    if env::args().nth(1).as_deref() == Some("compare") {
+        compare(&env::args().skip(2).collect::<Vec<String>>());
+        return;
+    }
+
+    let operation = match env::args().nth(1).as_deref() {
+        Some("merge") => Some(SetOperation::Union),
+        Some("intersect") => Some(SetOperation::Intersection),
+        Some("subtract") => Some(SetOperation::Difference),
+        _ => None,
+    };
+    if let Some(operation) = operation {
+        combine(operation, &env::args().skip(2).collect::<Vec<String>>());
+        return;
+    }
+
     let mut params = Params {
         debug: false,
         debug_memory: false,
@@ -133,12 +452,45 @@ fn main() {
         inverse: false,
         debug_internal: false,
         line_buffered: false,  // Default to block buffering
-        silent_warnings: false  // Default to not silencing warnings
+        silent_warnings: false,  // Default to not silencing warnings
+        on_full: OnFullPolicy::Ignore,
+        num_on_full_events: 0,
+        containers_full: false,
+        time_aware: false,
+        ttl_field: None,
+        promote: PromotePolicy::Always,
+        promote_probability: 0.1,
+        // Seeding with the current time. Exact sequence doesn't matter, we only need it to differ between lines.
+        random_state: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64 | 1,
+        max_occurrences: None,
+        output_mode: OutputMode::Lines,
+        distinct_lines: vec![],
+        distinct_digests: HashSet::new(),
+        top_size: 0,
+        top_every: None,
+        estimate_only: false,
+        hll: HyperLogLog::new(),
+        memory_budget: None,
+        budget_exhausted: false,
+        shared: false,
+        threads: 1,
+        partition: None,
+        foreign_lines: ForeignLines::Drop,
+        rotation: None
     };
 
     // List of passed file paths.
     let mut file_paths: Vec<String> = vec![];
 
+    // Time-based rotation of filter files, if requested.
+    let mut rotation: Option<Rotation> = None;
+
+    // Sliding window by line count, if requested.
+    let mut window: Option<Window> = None;
+
+    // Number of shards each new container is split into.
+    let mut num_shards: usize = 1;
+
     // List of passed construction details (pairs of limit and error rate or size).
     let mut constructions_details: Vec<ConstructionDetails> = vec![];
 
@@ -193,7 +545,9 @@ fn main() {
                     construction_type: ConstructionType::XXHLimitAndSize,
                     limit,
                     size,
-                    error_rate: 0.0
+                    error_rate: 0.0,
+                    ttl: 0,
+                    shards: 1
                 });
 
                 idx += 1;
@@ -227,7 +581,9 @@ fn main() {
                     construction_type: ConstructionType::BloomLinesAndSize,
                     limit,
                     size,
-                    error_rate: 0.0
+                    error_rate: 0.0,
+                    ttl: 0,
+                    shards: 1
                 });
 
                 idx += 1;
@@ -268,17 +624,476 @@ fn main() {
                     construction_type: ConstructionType::BloomLinesAndErrorRate,
                     limit,
                     error_rate,
-                    size: 0
+                    size: 0,
+                    ttl: 0,
+                    shards: 1
+                });
+
+                idx += 1;
+            }
+
+            // Specifies limit and digest width of the exact hash-set container.
+            "-el" | "--exact-limit" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after -el or --exact-limit parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() > 2 {
+                    eprintln!("Error: -el or --exact-limit expects one or two parameters.");
+                    std::process::exit(1);
+                }
+
+                let limit = parse_size(pair[0]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse limit passed in -el or --exact-limit parameter.");
+                    std::process::exit(1);
+                });
+
+                let (construction_type, words_per_slot) = match pair.get(1).copied().unwrap_or("64") {
+                    "64" => (ConstructionType::ExactLimit64, 1),
+                    "128" => (ConstructionType::ExactLimit128, 2),
+                    digest_bits => {
+                        eprintln!("Error: Digest width passed in -el or --exact-limit must be 64 or 128. \"{}\" passed.", digest_bits);
+                        std::process::exit(1);
+                    }
+                };
+
+                // Exits if the table for the limit would be too big, before any file is touched.
+                MemoryContainerExact::calc_size_for_limit(limit, words_per_slot);
+
+                constructions_details.push(ConstructionDetails {
+                    construction_type,
+                    limit,
+                    // Table size is derived from the limit when container is created.
+                    size: 0,
+                    error_rate: 0.0,
+                    ttl: 0,
+                    shards: 1
+                });
+
+                idx += 1;
+            }
+
+            // Specified limit and exact phase memory threshold of the hybrid container.
+            "-hls" | "--hybrid-limit-and-size" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after -hls or --hybrid-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() != 2 {
+                    eprintln!("Error: -hls or --hybrid-limit-and-size expects two parameters.");
+                    std::process::exit(1);
+                }
+
+                let limit = parse_size(pair[0]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse limit passed in -hls or --hybrid-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                let size = parse_size(pair[1]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse memory threshold passed in -hls or --hybrid-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                constructions_details.push(ConstructionDetails {
+                    construction_type: ConstructionType::HybridLimitAndSize,
+                    limit,
+                    size,
+                    error_rate: 0.0,
+                    ttl: 0,
+                    shards: 1
                 });
 
                 idx += 1;
             }
 
+            // Specified limit and size of the count-min sketch in given unit.
+            "-cms" | "--count-min-limit-and-size" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after -cms or --count-min-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() != 2 {
+                    eprintln!("Error: -cms or --count-min-limit-and-size expects two parameters.");
+                    std::process::exit(1);
+                }
+
+                let limit = parse_size(pair[0]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse limit passed in -cms or --count-min-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                let size = parse_size(pair[1]).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse sketch size passed in -cms or --count-min-limit-and-size parameter.");
+                    std::process::exit(1);
+                });
+
+                constructions_details.push(ConstructionDetails {
+                    construction_type: ConstructionType::CountMinLimitAndSize,
+                    limit,
+                    size,
+                    error_rate: 0.0,
+                    ttl: 0,
+                    shards: 1
+                });
+
+                idx += 1;
+            }
+
+            // Memory taken by all the containers together.
+            "--memory-budget" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --memory-budget parameter.");
+                    std::process::exit(1);
+                });
+
+                params.memory_budget = Some(parse_size(&value).ok().filter(|budget| *budget > 0).unwrap_or_else(|| {
+                    eprintln!("Error: Could not parse size passed in --memory-budget parameter.");
+                    std::process::exit(1);
+                }));
+
+                idx += 1;
+            }
+
+            // Number of threads reading and looking lines up.
+            "--threads" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --threads parameter.");
+                    std::process::exit(1);
+                });
+
+                let threads: usize = value.parse().unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse number passed in --threads parameter. \"{}\" passed.", value);
+                    std::process::exit(1);
+                });
+
+                params.threads = if threads == 0 {
+                    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+                } else {
+                    threads
+                };
+
+                idx += 1;
+            }
+
+            // Number of occurrences of each line to let through.
+            "--max-occurrences" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --max-occurrences parameter.");
+                    std::process::exit(1);
+                });
+
+                let max_occurrences: u64 = value.parse().unwrap_or(0);
+
+                if max_occurrences == 0 {
+                    eprintln!("Error: Number passed in --max-occurrences must be greater than 0. \"{}\" passed.", value);
+                    std::process::exit(1);
+                }
+
+                params.max_occurrences = Some(max_occurrences);
+
+                idx += 1;
+            }
+
+            // Time-based rotation of filter files.
+            "--rotate" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --rotate parameter.");
+                    std::process::exit(1);
+                });
+
+                // Splitting from the right, so pattern itself may contain commas.
+                let parts: Vec<&str> = value.rsplitn(3, ",").collect();
+
+                if parts.len() != 3 {
+                    eprintln!("Error: --rotate expects three parameters.");
+                    std::process::exit(1);
+                }
+
+                let pattern = parts[2].to_string();
+
+                if pattern.matches(DATE_PLACEHOLDER).count() != 1 || Path::new(&pattern).file_name().is_none_or(|name| !name.to_string_lossy().contains(DATE_PLACEHOLDER)) {
+                    eprintln!("Error: File pattern passed in --rotate must contain exactly one {} placeholder in the file name.", DATE_PLACEHOLDER);
+                    std::process::exit(1);
+                }
+
+                let period = match parts[1] {
+                    "hourly" => RotationPeriod::Hourly,
+                    "daily" => RotationPeriod::Daily,
+                    _ => {
+                        eprintln!("Error: Period passed in --rotate must be hourly or daily. \"{}\" passed.", parts[1]);
+                        std::process::exit(1);
+                    }
+                };
+
+                let keep: u64 = parts[0].parse().unwrap_or(0);
+
+                if keep == 0 {
+                    eprintln!("Error: Keep count passed in --rotate must be a number greater than 0. \"{}\" passed.", parts[0]);
+                    std::process::exit(1);
+                }
+
+                rotation = Some(Rotation { pattern, period, keep });
+
+                idx += 1;
+            }
+
+            // Number of shards of each new container.
+            "--shards" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --shards parameter.");
+                    std::process::exit(1);
+                });
+
+                num_shards = value.parse().ok().filter(|num_shards| is_valid_num_shards(*num_shards)).unwrap_or_else(|| {
+                    eprintln!("Error: Number of shards passed in --shards must be a power of two up to {}. \"{}\" passed.", MAX_SHARDS, value);
+                    std::process::exit(1);
+                });
+
+                idx += 1;
+            }
+
+            // Sliding window of the last lines.
+            "--window" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --window parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() > 2 {
+                    eprintln!("Error: --window expects one or two parameters.");
+                    std::process::exit(1);
+                }
+
+                let size = parse_size(pair[0]).unwrap_or(0);
+
+                if size == 0 {
+                    eprintln!("Error: Window size passed in --window must be a number greater than 0. \"{}\" passed.", pair[0]);
+                    std::process::exit(1);
+                }
+
+                let generations: u64 = pair.get(1).copied().unwrap_or("4").parse().unwrap_or(0);
+
+                if generations < 2 || generations > size {
+                    eprintln!("Error: Number of generations passed in --window must be between 2 and the window size.");
+                    std::process::exit(1);
+                }
+
+                window = Some(Window { size, generations });
+
+                idx += 1;
+            }
+
+            // Specifies time to live and limit of the time-aware container.
+            "--ttl" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --ttl parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() > 2 {
+                    eprintln!("Error: --ttl expects one or two parameters.");
+                    std::process::exit(1);
+                }
+
+                let ttl = parse_duration(pair[0]).filter(|ttl| *ttl > 0).unwrap_or_else(|| {
+                    eprintln!("Error: Could not parse duration passed in --ttl parameter.");
+                    std::process::exit(1);
+                });
+
+                let limit = parse_size(pair.get(1).copied().unwrap_or("10M")).unwrap_or_else(|_| {
+                    eprintln!("Error: Could not parse limit passed in --ttl parameter.");
+                    std::process::exit(1);
+                });
+
+                constructions_details.push(ConstructionDetails {
+                    construction_type: ConstructionType::TimedLimitAndTtl,
+                    limit,
+                    // Table size is derived from the limit when container is created.
+                    size: 0,
+                    error_rate: 0.0,
+                    ttl,
+                    shards: 1
+                });
+
+                idx += 1;
+            }
+
+            // Field holding the timestamp of the line.
+            "--ttl-field" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --ttl-field parameter.");
+                    std::process::exit(1);
+                });
+
+                let field: usize = value.parse().unwrap_or(0);
+
+                if field == 0 {
+                    eprintln!("Error: Field passed in --ttl-field must be a number greater than 0. \"{}\" passed.", value);
+                    std::process::exit(1);
+                }
+
+                params.ttl_field = Some(field);
+
+                idx += 1;
+            }
+
             // Whether we want to update (write to) Bloom filter files.
             "-w" | "--write" => params.write_mode = true,
 
+            // Other processes write into the same files.
+            "--shared" => params.shared = true,
+
+            // Part of the input processed by this instance.
+            "--partition" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --partition parameter.");
+                    std::process::exit(1);
+                });
+
+                params.partition = Some(Partition::parse(&value).unwrap_or_else(|| {
+                    eprintln!("Error: Partition passed in --partition must be I/N with I between 1 and N. \"{}\" passed.", value);
+                    std::process::exit(1);
+                }));
+
+                idx += 1;
+            }
+
+            // What to do with lines of other partitions.
+            "--foreign-lines" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --foreign-lines parameter.");
+                    std::process::exit(1);
+                });
+
+                params.foreign_lines = match value.as_str() {
+                    "drop" => ForeignLines::Drop,
+                    "pass" => ForeignLines::Pass,
+                    path => ForeignLines::File(BufWriter::new(File::create(path).unwrap_or_else(|_| {
+                        eprintln!("Error: Can't open file \"{}\" for writing!", path);
+                        std::process::exit(1);
+                    }))),
+                };
+
+                idx += 1;
+            }
+
+            // Whether full containers should spawn successors.
+            "-sc" | "--scalable" => params.on_full = OnFullPolicy::Scale,
+
+            // Policy applied when all containers are full.
+            "--on-full" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --on-full parameter.");
+                    std::process::exit(1);
+                });
+
+                params.on_full = match value.as_str() {
+                    "ignore" => OnFullPolicy::Ignore,
+                    "fail" => OnFullPolicy::Fail,
+                    "warn" => OnFullPolicy::Warn,
+                    "rotate" => OnFullPolicy::Rotate,
+                    "evict" => OnFullPolicy::Evict,
+                    "stop" => OnFullPolicy::Stop,
+                    "scale" => OnFullPolicy::Scale,
+                    _ => {
+                        eprintln!("Error: --on-full expects one of: ignore, fail, warn, rotate, evict, stop, scale. \"{}\" passed.", value);
+                        std::process::exit(1);
+                    }
+                };
+
+                idx += 1;
+            }
+
+            // Policy applied when line is found in non-writable container.
+            "--promote" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --promote parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                params.promote = match pair[0] {
+                    "always" => PromotePolicy::Always,
+                    "never" => PromotePolicy::Never,
+                    "probabilistic" => PromotePolicy::Probabilistic,
+                    _ => {
+                        eprintln!("Error: --promote expects one of: always, never, probabilistic. \"{}\" passed.", pair[0]);
+                        std::process::exit(1);
+                    }
+                };
+
+                if pair.len() > 1 {
+                    if params.promote != PromotePolicy::Probabilistic || pair.len() > 2 {
+                        eprintln!("Error: Probability may be passed in --promote only for probabilistic policy.");
+                        std::process::exit(1);
+                    }
+
+                    params.promote_probability = pair[1].parse().ok().filter(|p| (0.0 ..= 1.0).contains(p)).unwrap_or_else(|| {
+                        eprintln!("Error: Probability passed in --promote must be a number between 0 and 1. \"{}\" passed.", pair[1]);
+                        std::process::exit(1);
+                    });
+                }
+
+                idx += 1;
+            }
+
             // Will inverse functionality.
-            "-i" | "--inverse" => params.inverse = true,
+            "-i" | "--inverse" | "--all-repeated" => params.inverse = true,
+
+            // uniq-like output modes.
+            "--repeated" => params.output_mode = OutputMode::Repeated,
+            "--unique" => params.output_mode = OutputMode::Unique,
+            "--count" => params.output_mode = OutputMode::Count,
+
+            // Only estimates number of distinct lines.
+            "--estimate" => params.estimate_only = true,
+
+            // Report of the most frequent lines.
+            "--top" => {
+                let value = env::args().nth(idx + 1).unwrap_or_else(|| {
+                    eprintln!("Error: No value provided after --top parameter.");
+                    std::process::exit(1);
+                });
+
+                let pair: Vec<&str> = value.split(",").collect();
+
+                if pair.len() > 2 {
+                    eprintln!("Error: --top expects one or two parameters.");
+                    std::process::exit(1);
+                }
+
+                params.top_size = pair[0].parse().unwrap_or(0);
+
+                if params.top_size == 0 {
+                    eprintln!("Error: Number of lines passed in --top must be greater than 0. \"{}\" passed.", pair[0]);
+                    std::process::exit(1);
+                }
+
+                if let Some(every) = pair.get(1) {
+                    params.top_every = Some(every.parse().ok().filter(|every| *every > 0).unwrap_or_else(|| {
+                        eprintln!("Error: Number of lines passed in --top must be greater than 0. \"{}\" passed.", every);
+                        std::process::exit(1);
+                    }));
+                }
+
+                params.output_mode = OutputMode::Top;
+
+                idx += 1;
+            }
 
             // Will output debug information.
             "-d" | "--debug" => params.debug = true,
@@ -319,84 +1134,353 @@ fn main() {
         params.debug_memory = true
     }
 
-    if file_paths.is_empty() && !params.write_mode {
+    for construction_details in constructions_details.iter() {
+        let (option, error_rate) = match construction_details.construction_type {
+            ConstructionType::BloomLinesAndSize => ("-bls", bloom_error_rate(construction_details.size, construction_details.limit)),
+            ConstructionType::XXHLimitAndSize => ("-xls", xxh_error_rate(construction_details.size, construction_details.limit)),
+            ConstructionType::CountMinLimitAndSize => {
+                let error_rate = count_min_error_rate(construction_details.size, construction_details.limit);
+                if error_rate > WARN_ERROR_RATE && !params.silent_warnings {
+                    eprintln!(
+                        "Warning: -cms {},{} can't hold that many lines, counts are overestimated with probability of {:.1} %.",
+                        construction_details.limit, construction_details.size, error_rate * 100.0
+                    );
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        if error_rate > WARN_ERROR_RATE && !params.silent_warnings {
+            eprintln!(
+                "Warning: {} {},{} can't hold that many lines, expected false positive rate is {:.1} %. See \"bloom plan --items {} --fpr RATE\".",
+                option, construction_details.limit, construction_details.size, error_rate * 100.0, construction_details.limit
+            );
+        }
+    }
+
+    if rotation.is_some() && !file_paths.is_empty() {
+        eprintln!("Error: --rotate can't be combined with -f or --file parameters.");
+        std::process::exit(1);
+    }
+
+    if rotation.is_some() && constructions_details.len() > 1 {
+        eprintln!("Error: --rotate accepts at most one -xls / -bls / -ble / -el / -hls / --ttl / -cms parameter.");
+        std::process::exit(1);
+    }
+
+    if window.is_some() {
+        if rotation.is_some() {
+            eprintln!("Error: --window can't be combined with --rotate parameter.");
+            std::process::exit(1);
+        }
+
+        if file_paths.len() > 1 {
+            eprintln!("Error: --window accepts at most one -f or --file parameter. Generations are stored next to it.");
+            std::process::exit(1);
+        }
+
+        if constructions_details.len() > 1 {
+            eprintln!("Error: --window accepts at most one -xls / -bls / -ble / -el / -hls / --ttl / -cms parameter.");
+            std::process::exit(1);
+        }
+
+        if params.on_full != OnFullPolicy::Ignore {
+            eprintln!("Error: --window can't be combined with -sc or --on-full parameters.");
+            std::process::exit(1);
+        }
+
+        params.on_full = OnFullPolicy::Slide;
+    }
+
+    if params.shared {
+        if !params.write_mode || file_paths.is_empty() {
+            eprintln!("Error: --shared requires -w and -f parameters.");
+            std::process::exit(1);
+        }
+
+        if matches!(params.on_full, OnFullPolicy::Rotate | OnFullPolicy::Scale | OnFullPolicy::Evict | OnFullPolicy::Slide) || rotation.is_some() {
+            eprintln!("Error: --shared can't be combined with --rotate, --window or --on-full rotate / scale / evict, as they replace files.");
+            std::process::exit(1);
+        }
+
+        let supported = constructions_details.iter().all(|construction_details| matches!(construction_details.construction_type,
+            ConstructionType::BloomLinesAndSize | ConstructionType::BloomLinesAndErrorRate | ConstructionType::XXHLimitAndSize));
+        if !supported || params.needs_counts() {
+            eprintln!("Error: --shared works only with -xls / -bls / -ble containers.");
+            std::process::exit(1);
+        }
+    }
+
+    if params.partition.is_none() && !matches!(params.foreign_lines, ForeignLines::Drop) {
+        eprintln!("Error: --foreign-lines requires --partition parameter.");
+        std::process::exit(1);
+    }
+
+    if file_paths.is_empty() && rotation.is_none() && !params.write_mode {
         // When no paths were given then we're assuming that we work on the memory, so need to enable writing.
         params.write_mode = true;
     }
 
     if !file_paths.is_empty() && constructions_details.len() > 1 && constructions_details.len() != file_paths.len() {
-        eprintln!("Error: Number of passed -xls / -bls / -ble parameters should be exactly zero or one or match the number of file paths.");
+        eprintln!("Error: Number of passed -xls / -bls / -ble / -el / -hls / --ttl / -cms parameters should be exactly zero or one or match the number of file paths.");
         std::process::exit(1);
     }
 
+    if !constructions_details.is_empty() && file_paths.len() > constructions_details.len() {
+        // Passed construction details are used only for the files of the same index.
+        file_paths.truncate(constructions_details.len());
+    }
+
+    // Without container options the type could be picked to fit the memory budget.
+    let default_constructions = constructions_details.is_empty() && !params.needs_counts();
+
+    if params.output_mode != OutputMode::Lines && (params.inverse || params.max_occurrences.is_some()) {
+        eprintln!("Error: --repeated, --unique, --count and --top can't be combined with -i or --max-occurrences parameters.");
+        std::process::exit(1);
+    }
+
+    if constructions_details.is_empty() && params.needs_counts() {
+        // Counting occurrences needs count-min sketch instead of the default xxHash filter. Its size is overridden by
+        // the memory budget, if there's one.
+        let limit = parse_size("10M").unwrap();
+        constructions_details.push(ConstructionDetails {
+            size: count_min_size_for_error_rate(limit, DEFAULT_ERROR_RATE),
+            error_rate: 0.0,
+            limit,
+            construction_type: ConstructionType::CountMinLimitAndSize,
+            ttl: 0,
+            shards: 1
+        });
+    }
+
+    if let Some(window) = &window {
+        // Each generation holds its share of the window, so passed limit is overridden.
+        let construction_details = generation_construction_details(window, constructions_details.first().copied());
+        constructions_details = vec![construction_details];
+    }
+
     if constructions_details.is_empty() {
-        // Adding default xxHash memory containers (one or number of file paths passed).
-        let num_containers = max(1, file_paths.len());
-        for idx in 0 .. num_containers {
-            params.containers.push(<dyn Container>::from_details(ContainerDetails {
-                path: if file_paths.is_empty()  { format!("memory.{idx}.out") } else { file_paths[idx].to_string() },
-                construction_details: ConstructionDetails {
-                    size: parse_size("2Gb").unwrap(),
-                    error_rate: 0.0,
-                    limit: parse_size("200M").unwrap(),
-                    construction_type: ConstructionType::XXHLimitAndSize
-                },
-                data_source: if file_paths.is_empty() { DataSource::Memory } else { DataSource::File },
-            }));
+        // Adding default xxHash construction details used for memory container or all the files.
+        constructions_details.push(ConstructionDetails {
+            size: parse_size("2Gb").unwrap(),
+            error_rate: 0.0,
+            limit: parse_size("200M").unwrap(),
+            construction_type: ConstructionType::XXHLimitAndSize,
+            ttl: 0,
+            shards: 1
+        });
+    }
+
+    if params.estimate_only {
+        // Lines are only counted, so containers are neither needed nor written.
+        process(&mut params);
+        return;
+    }
+
+    if let Some(budget) = params.memory_budget {
+        // Counting containers which will be created from each construction details and memory taken by existing files.
+        let mut num_containers = vec![0; constructions_details.len()];
+        let mut existing = 0;
+
+        if let Some(window) = &window {
+            num_containers[0] = window.generations;
+        }
+        else if let Some(rotation) = &rotation {
+            // Files of the retained periods are opened next to the current one, so they take their share of the budget.
+            for path in retained_paths(rotation) {
+                existing += footprint(&<dyn Container>::header_from_file(&path).construction_details);
+            }
+            num_containers[0] = 1;
         }
+        else if !file_paths.is_empty() {
+            for (idx, path) in file_paths.iter().enumerate() {
+                if <dyn Container>::exists(path) {
+                    let mut paths = vec![path.clone()];
+                    paths.extend(discover_successors(path));
+                    // Only headers are read, so we can refuse to start before loading too big files.
+                    for path in paths {
+                        existing += footprint(&<dyn Container>::header_from_file(&path).construction_details);
+                    }
+                }
+                else {
+                    num_containers[min(idx, constructions_details.len() - 1)] += 1;
+                }
+            }
+        }
+        else {
+            num_containers.fill(1);
+        }
+
+        fit_to_budget(&mut constructions_details, &num_containers, existing, budget, default_constructions);
     }
 
-    if !file_paths.is_empty() {
-        // Adding file containers.
-        for (idx, ref mut construction_details) in constructions_details.iter_mut().enumerate() {
-            let path = file_paths[idx].to_string();
-            if Path::new(&path).exists() {
+    // Containers are split into shards only once their limits and sizes are final.
+    for construction_details in constructions_details.iter_mut() {
+        construction_details.shards = num_shards;
+    }
+
+    if let Some(rotation) = &rotation {
+        // Adding containers of the retained periods and the current one.
+        open_rotated_containers(&mut params, rotation, constructions_details[0]);
+    }
+    else if let Some(window) = &window {
+        // Adding containers of all the window generations.
+        open_window_containers(&mut params, window, file_paths.first(), constructions_details[0]);
+    }
+    else if !file_paths.is_empty() {
+        // Adding file containers. Default construction details are shared between all the files.
+        for (idx, path) in file_paths.iter().enumerate() {
+            let construction_details = constructions_details[min(idx, constructions_details.len() - 1)];
+            if params.shared {
+                // File is created unless other process has already done it.
+                params.containers.push(<dyn Container>::from_shared_file(ContainerDetails {
+                    path: path.to_string(),
+                    construction_details,
+                    data_source: DataSource::File,
+                    created_at: now(),
+                    expires_at: 0,
+                    read_only: false,
+                    hll: HyperLogLog::new(),
+                    partition: params.partition,
+                    num_promotions: 0,
+                }));
+            }
+            else if <dyn Container>::exists(path) {
                 // Creating container from existing file. Input parameters will be overridden by those inside file's
                 // header.
-                params.containers.push(<dyn Container>::from_file(&path));
+                params.containers.push(<dyn Container>::from_file(path, params.write_mode));
+
+                // Loading successors spawned in previous runs, whatever policy is used now, as they hold lines too.
+                for successor_path in discover_successors(path) {
+                    params.containers.push(<dyn Container>::from_file(&successor_path, params.write_mode));
+                }
             }
             else {
                 params.containers.push(<dyn Container>::from_details(ContainerDetails {
-                    path: path,
-                    construction_details: **construction_details,
+                    path: path.to_string(),
+                    construction_details,
                     data_source: DataSource::File,
+                    created_at: now(),
+                    expires_at: 0,
+                    read_only: false,
+                    hll: HyperLogLog::new(),
+                    partition: params.partition,
+                    num_promotions: 0,
                 }));
             }
         }
     }
-    else if !constructions_details.is_empty() {
+    else {
         // Adding memory containers.
-        for (idx, ref mut construction_details) in constructions_details.iter_mut().enumerate() {
+        for (idx, construction_details) in constructions_details.iter().enumerate() {
             params.containers.push(<dyn Container>::from_details(ContainerDetails {
-                path: format!("memory.{idx}.blm"),
-                construction_details: **construction_details,
+                path: if default_constructions { format!("memory.{idx}.out") } else { format!("memory.{idx}.blm") },
+                construction_details: *construction_details,
                 data_source: DataSource::Memory,
+                created_at: now(),
+                expires_at: 0,
+                read_only: false,
+                hll: HyperLogLog::new(),
+                partition: params.partition,
+                num_promotions: 0,
             }));
         }
     }
 
+    if let Some(budget) = params.memory_budget {
+        // Files of the window generations and retained periods are only known once loaded.
+        let footprint = containers_footprint(&mut params);
+        if footprint > budget {
+            eprintln!("Error: Containers need {}, which exceeds memory budget of {}.", format_size(footprint), format_size(budget));
+            std::process::exit(1);
+        }
+    }
+
+    // Containers of other partitions hold other lines, so they can't be mixed.
+    for container in params.containers.iter_mut() {
+        let container_details = container.get_container_details();
+        if container_details.partition != params.partition {
+            eprintln!("Error: File \"{}\" holds lines of partition {}, but partition {} is processed (see --partition).", container_details.path, partition_name(container_details.partition), partition_name(params.partition));
+            std::process::exit(1);
+        }
+    }
+
+    if params.needs_counts() {
+        // Other containers remember only whether line occurred, so counts would never exceed their number.
+        for container in params.containers.iter_mut() {
+            let container_details = container.get_container_details();
+            if !matches!(container_details.construction_details.construction_type, ConstructionType::CountMinLimitAndSize) {
+                eprintln!("Error: Counting lines requires count-min sketch containers, but \"{}\" isn't one.", container_details.path);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if params.top_size > 0 {
+        for container in params.containers.iter_mut() {
+            container.set_top_size(params.top_size);
+        }
+    }
+
+    // Time-aware containers need current time of each line. Loaded files may be time-aware even without --ttl.
+    for container in params.containers.iter_mut() {
+        if matches!(container.get_container_details().construction_details.construction_type, ConstructionType::TimedLimitAndTtl) {
+            params.time_aware = true;
+        }
+    }
+
     process(&mut params);
 
+    let reported = !matches!(params.on_full, OnFullPolicy::Ignore | OnFullPolicy::Warn | OnFullPolicy::Slide);
+    if params.num_on_full_events > 0 && reported && !params.debug && !params.silent_warnings {
+        // Full containers change which lines are stored, so it's reported even without -d. Ignored full containers
+        // are silent, warning was already printed and sliding window is expected to be full, so only -d reports them.
+        eprintln!("Warning: Containers were full, on-full policy \"{}\" was triggered {} time(s).", on_full_policy_name(params.on_full), params.num_on_full_events);
+    }
+
     if params.debug {
         eprintln!();
         eprintln!("[ CONTAINERS' STATUS ]");
-        for (_i, container) in params.containers.iter_mut().enumerate() {
+        for container in params.containers.iter_mut() {
             let path = container.get_container_details().path.clone();
-            eprintln!("- \"{}\": binary fill: {} %, line fill: {} %", path, container.get_usage(), container.get_write_level());
+            let distinct = container.get_container_details().hll.estimate();
+            let promoted = container.get_container_details().num_promotions;
+            match container.get_status() {
+                Some(status) => eprintln!("- \"{}\": binary fill: {} %, line fill: {} %, distinct lines: ~{}, promoted: {} line(s), {}", path, container.get_usage(), container.get_write_level(), distinct, promoted, status),
+                None => eprintln!("- \"{}\": binary fill: {} %, line fill: {} %, distinct lines: ~{}, promoted: {} line(s)", path, container.get_usage(), container.get_write_level(), distinct, promoted),
+            }
+        }
+        eprintln!();
+        eprintln!("[ DISTINCT LINES ]");
+        let mut hll = HyperLogLog::new();
+        for container in params.containers.iter_mut() {
+            hll.merge(&container.get_container_details().hll);
         }
+        eprintln!("- all containers: ~{}", hll.estimate());
+        eprintln!();
+        eprintln!("[ ON FULL ]");
+        eprintln!("- policy: {}, triggered: {} time(s)", on_full_policy_name(params.on_full), params.num_on_full_events);
+        eprintln!();
+        eprintln!("[ PROMOTION ]");
+        let num_promotions: u64 = params.containers.iter_mut().map(|container| container.get_container_details().num_promotions).sum();
+        eprintln!("- policy: {}, promoted: {} line(s)", promote_policy_name(params.promote), num_promotions);
         eprintln!();
     }
 
     if params.write_mode {
         // Writing file containers.
-        for (_i, container) in params.containers.iter_mut().enumerate() {
-            match container.get_container_details().data_source {
+        for container in params.containers.iter_mut() {
+            let details = container.get_container_details();
+            match details.data_source {
                 DataSource::Memory => {}
+                // Read-only containers are never modified, so there's no need to rewrite them.
+                DataSource::File if details.read_only => {}
                 DataSource::File => container.save()
             }
 
             if params.debug {
-                eprintln!("")
+                eprintln!()
             }
         }
     }
@@ -1,6 +1,37 @@
 use std::io::Write;
 use std::process::{Command, Stdio};
 
+/// Returns path of the temporary file with given name, unique for the test and the test process.
+fn temp_path(test: &str, name: &str) -> String {
+    std::env::temp_dir().join(format!("bloom_{}_{}_{}.blf", test, std::process::id(), name)).to_str().unwrap().to_string()
+}
+
+/// Returns input made of the numbers, one per line.
+fn lines(numbers: impl IntoIterator<Item = u32>) -> String {
+    numbers.into_iter().map(|i| format!("{}\n", i)).collect()
+}
+
+/// Runs bloom with the input. Returns whether it succeeded and its output.
+fn run(args: &[&str], input: String) -> (bool, String) {
+    let mut child = Command::new("./target/debug/bloom")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    // Input is written while the output is read, so neither of the pipes fills up. Refused runs exit without reading
+    // the input.
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    (output.status.success(), String::from_utf8(output.stdout).expect("Output not UTF-8"))
+}
+
 #[test]
 fn test_basic_deduplication() {
     let mut child = Command::new("./target/debug/bloom")
@@ -111,3 +142,1160 @@ fn test_silent_warnings() {
     // Check that no warnings are present in stderr
     assert!(stderr_str.is_empty(), "Expected no warnings, but got: {}", stderr_str);
 }
+
+#[test]
+fn test_exact_container_deduplication() {
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-el", "1K"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    // Write numbers 1-1000 twice to stdin, so the table gets close to its limit.
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=1000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    for i in 1..=1000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+
+    // Exact container must not report any false positives.
+    let output_lines: Vec<&str> = output_str.lines().collect();
+    assert_eq!(output_lines.len(), 1000, "Expected 1000 unique lines");
+}
+
+#[test]
+fn test_exact_container_file_round_trip() {
+    let path = std::env::temp_dir().join(format!("bloom_exact_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // First run creates the file with 128-bit digests.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-el", "100,128", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=10 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+    child.wait_with_output().expect("Failed to wait on bloom");
+
+    // Second run loads the file, so only new lines are printed.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=15 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let _ = std::fs::remove_file(&path);
+
+    let output_lines: Vec<&str> = output_str.lines().collect();
+    assert_eq!(output_lines, vec!["11", "12", "13", "14", "15"]);
+}
+
+#[test]
+fn test_hybrid_filter_grows_with_lines() {
+    let path = std::env::temp_dir().join(format!("bloom_hybrid_growth_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // Filled to the limit, the spilled filters should lose at most 0.1% of unique lines.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-hls", "20000,256", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=20000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert!(output_str.lines().count() >= 19980, "Expected at most 20 lost lines, got {}", output_str.lines().count());
+    let _ = std::fs::remove_file(&path);
+
+    // Large limit shouldn't reserve the filter for all the lines up front.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-hls", "1G,256", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=1000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    assert_eq!(String::from_utf8(output.stdout).expect("Output not UTF-8").lines().count(), 1000);
+    let file_size = std::fs::metadata(&path).expect("Failed to read file metadata").len();
+    let _ = std::fs::remove_file(&path);
+    assert!(file_size < 1024 * 1024, "Expected small file, got {} bytes", file_size);
+}
+
+#[test]
+fn test_hybrid_container_spills_and_round_trips() {
+    let path = std::env::temp_dir().join(format!("bloom_hybrid_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // Exact phase is limited to 256 bytes, so 100 lines will make the container spill into the filter.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-hls", "1M,256", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=100 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    for i in 1..=100 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert_eq!(output_str.lines().count(), 100, "Expected 100 unique lines");
+
+    // Second run loads the spilled container and reports its phase.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-d", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=110 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let stderr_str = String::from_utf8(output.stderr).expect("Stderr not UTF-8");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output_str.lines().count(), 10, "Expected only 10 new lines");
+    assert!(stderr_str.contains("phase: filter"), "Expected container to be in filter phase");
+}
+
+#[test]
+fn test_scalable_spawns_successors() {
+    // Exact container is full after 10 lines, so without successors lines 11-100 would be printed twice.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-sc", "-el", "10"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=100 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    for i in 1..=100 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert_eq!(output_str.lines().count(), 100, "Expected 100 unique lines");
+}
+
+#[test]
+fn test_scalable_reloads_successor_files() {
+    let dir = std::env::temp_dir().join(format!("bloom_scalable_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("day.blf");
+
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-sc", "-el", "10", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=50 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+    child.wait_with_output().expect("Failed to wait on bloom");
+
+    // Limits are 10, 20 and 40, so two successor files should be created.
+    assert!(dir.join("day.1.blf").exists(), "Expected first successor file");
+    assert!(dir.join("day.2.blf").exists(), "Expected second successor file");
+
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-sc", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=60 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert_eq!(output_str.lines().count(), 10, "Expected only 10 new lines");
+
+    // Successors hold lines too, so they are loaded even without -sc.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-f", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=60 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(output_str.lines().count(), 10, "Expected only 10 new lines without -sc");
+}
+
+#[test]
+fn test_on_full_fail_exits_with_error() {
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-el", "5", "--on-full", "fail"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=20 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+
+    assert_eq!(output.status.code(), Some(2), "Expected exit code 2");
+    assert_eq!(output_str.lines().count(), 5, "Expected lines printed before containers got full");
+}
+
+#[test]
+fn test_on_full_stop_and_rotate() {
+    // Containers which stay full count as a single event, each rotation as another one (lines found in older containers
+    // are promoted into the new ones). Ignored full containers are silent and warning is printed only once.
+    let reports = [
+        ("stop", 5, "Warning: Containers were full, on-full policy \"stop\" was triggered 1 time(s).\n"),
+        ("rotate", 20, "Warning: Containers were full, on-full policy \"rotate\" was triggered 7 time(s).\n"),
+        ("ignore", 35, ""),
+        ("warn", 35, "Warning: All containers are full, new lines will not be stored.\n"),
+    ];
+    for (policy, expected_lines, expected_stderr) in reports {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-el", "5", "--on-full", policy])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in 1..=20 {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        for i in 1..=20 {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+        let stderr_str = String::from_utf8(output.stderr).expect("Stderr not UTF-8");
+        assert_eq!(output_str.lines().count(), expected_lines, "Unexpected number of lines for --on-full {}", policy);
+        assert_eq!(stderr_str, expected_stderr, "Unexpected report for --on-full {}", policy);
+    }
+}
+
+#[test]
+fn test_rotate_removes_expired_files() {
+    let dir = std::env::temp_dir().join(format!("bloom_rotate_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let expired_path = dir.join("seen.2000-01-01.blf");
+    let pattern = format!("{}/seen.{{date}}.blf,daily,7", dir.to_str().unwrap());
+
+    // Creating file for a long expired period.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-w", "-el", "100", "-f", expired_path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=10 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+    child.wait_with_output().expect("Failed to wait on bloom");
+
+    // Running twice, so the second run checks lines against the current period's file.
+    let mut outputs = vec![];
+    for _ in 0..2 {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "-el", "100", "--rotate", &pattern])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in 1..=10 {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        outputs.push(String::from_utf8(output.stdout).expect("Output not UTF-8"));
+    }
+
+    let expired_exists = expired_path.exists();
+    let num_files = std::fs::read_dir(&dir).unwrap().count();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(!expired_exists, "Expected expired file to be removed");
+    assert_eq!(num_files, 1, "Expected only the current period's file");
+    assert_eq!(outputs[0].lines().count(), 10, "Expected expired file to be ignored");
+    assert_eq!(outputs[1].lines().count(), 0, "Expected lines to be found in the current period's file");
+}
+
+#[test]
+fn test_memory_budget_counts_retained_rotation_files() {
+    let dir = std::env::temp_dir().join(format!("bloom_rotate_budget_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let retained_path = dir.join("seen.2000-01-01.blf");
+    // Files are retained for much longer than since 2000.
+    let pattern = format!("{}/seen.{{date}}.blf,daily,100000", dir.to_str().unwrap());
+
+    // Creating file of the previous period, which takes 16 MiB.
+    let output = Command::new("./target/debug/bloom")
+        .args(["-w", "-el", "1M", "-f", retained_path.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    assert!(output.status.success(), "Expected retained file to be created");
+
+    // Budget fits the current period's file alone, but not together with the retained one.
+    let output = Command::new("./target/debug/bloom")
+        .args(["-w", "-el", "1M", "--rotate", &pattern, "--memory-budget", "24Mb"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(output.status.code(), Some(1), "Expected retained file to count against the budget");
+    // Containers are refused before loading any file, not only after they are open.
+    assert!(stderr_str.contains("Existing and fixed-size containers need 32.00 MiB"), "Expected error about exceeded budget: {}", stderr_str);
+}
+
+#[test]
+fn test_ttl_with_timestamp_field() {
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["--ttl", "10m", "--ttl-field", "1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    writeln!(stdin, "2024-01-01T00:00:00Z disk full").unwrap();
+    writeln!(stdin, "2024-01-01T00:05:00Z disk full").unwrap();
+    writeln!(stdin, "2024-01-01T00:09:59Z disk full").unwrap();
+    writeln!(stdin, "2024-01-01T00:10:00Z disk full").unwrap();
+    writeln!(stdin, "2024-01-01T00:12:00Z disk full").unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+
+    // Line is suppressed for 10 minutes after it was printed, then it's printed again.
+    let output_lines: Vec<&str> = output_str.lines().collect();
+    assert_eq!(output_lines, vec!["2024-01-01T00:00:00Z disk full", "2024-01-01T00:10:00Z disk full"]);
+}
+
+#[test]
+fn test_window_forgets_old_lines() {
+    let dir = std::env::temp_dir().join(format!("bloom_window_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("window.blf");
+
+    // Window of 4 lines made of 2 generations: only the last 4 written lines (c, d, e, a) survive the first run.
+    let mut outputs = vec![];
+    for lines in ["a\nb\na\nc\nd\ne\na\n", "a\nb\nc\nd\ne\n"] {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "--window", "4,2", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        write!(stdin, "{}", lines).expect("Failed to write to stdin");
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        outputs.push(String::from_utf8(output.stdout).expect("Output not UTF-8"));
+    }
+
+    let generation_exists = dir.join("window.1.blf").exists();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(generation_exists, "Expected second generation file");
+    assert_eq!(outputs[0].lines().collect::<Vec<&str>>(), vec!["a", "b", "c", "d", "e", "a"]);
+    assert_eq!(outputs[1].lines().collect::<Vec<&str>>(), vec!["b", "c", "d", "e"]);
+}
+
+#[test]
+fn test_window_keeps_unique_lines_at_capacity() {
+    // Generations are sized for 0.1% false positive rate together, so filling the window loses at most 40 lines.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["--window", "40000"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=40000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert!(output_str.lines().count() >= 39960, "Expected at most 40 lost lines, got {}", output_str.lines().count());
+}
+
+#[test]
+fn test_promote_always_and_never() {
+    // Line "1" is found in the first (full) container. Promoting it takes a slot in the second container, so the input
+    // stops one line earlier.
+    for (policy, expected_lines, promoted) in [("always", vec!["1", "2", "3", "4", "5"], 1), ("never", vec!["1", "2", "3", "4", "5", "6"], 0)] {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-d", "-el", "3", "-el", "3", "--on-full", "stop", "--promote", policy])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        write!(stdin, "1\n2\n3\n1\n4\n5\n6\n").expect("Failed to write to stdin");
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+        let stderr_str = String::from_utf8(output.stderr).expect("Stderr not UTF-8");
+        assert_eq!(output_str.lines().collect::<Vec<&str>>(), expected_lines, "Unexpected lines for --promote {}", policy);
+        // Promoted line is counted for the container it was copied into.
+        let status = |path: &str| stderr_str.lines().find(|line| line.starts_with(&format!("- \"{}\"", path))).unwrap_or("").to_string();
+        assert!(status("memory.0.blm").ends_with("promoted: 0 line(s)"), "Unexpected status for --promote {}", policy);
+        assert!(status("memory.1.blm").ends_with(&format!("promoted: {} line(s)", promoted)), "Unexpected status for --promote {}", policy);
+    }
+}
+
+#[test]
+fn test_max_occurrences_persists_counts() {
+    let dir = std::env::temp_dir().join(format!("bloom_max_occurrences_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("counts.blf");
+
+    let mut outputs = vec![];
+    for lines in ["a\na\nb\na\na\nb\nc\n", "a\nb\nc\nc\n"] {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "-cms", "100,64Kb", "--max-occurrences", "3", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        write!(stdin, "{}", lines).expect("Failed to write to stdin");
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        outputs.push(String::from_utf8(output.stdout).expect("Output not UTF-8"));
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(outputs[0].lines().collect::<Vec<&str>>(), vec!["a", "a", "b", "a", "b", "c"]);
+    // Counts of the first run were loaded from the file.
+    assert_eq!(outputs[1].lines().collect::<Vec<&str>>(), vec!["b", "c", "c"]);
+}
+
+#[test]
+fn test_uniq_like_modes() {
+    for (mode, expected_lines) in [
+        ("--repeated", vec!["a", "b"]),
+        ("--all-repeated", vec!["a", "a", "b"]),
+        ("--unique", vec!["c"]),
+        ("--count", vec!["      3 a", "      2 b", "      1 c"]),
+    ] {
+        let mut child = Command::new("./target/debug/bloom")
+            .args([mode, "-cms", "100,64Kb"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        write!(stdin, "a\nb\na\nc\na\nb\n").expect("Failed to write to stdin");
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+        assert_eq!(output_str.lines().collect::<Vec<&str>>(), expected_lines, "Unexpected lines for {}", mode);
+    }
+}
+
+#[test]
+fn test_count_lists_lines_with_overestimated_counts() {
+    // Sketch is far too small, so most of the new lines have non-zero estimates, but all of them are still listed.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["--count", "-sw", "-cms", "1000,64"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 1..=100 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    assert_eq!(output_str.lines().count(), 100, "Expected all 100 distinct lines");
+}
+
+#[test]
+fn test_top_adds_sketches_together() {
+    let dir = std::env::temp_dir().join(format!("bloom_top_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = [dir.join("mon.blf"), dir.join("tue.blf")];
+
+    for (path, lines) in paths.iter().zip(["a\nb\na\nc\na\nb\n", "c\nc\nc\nb\n"]) {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "-cms", "100,64Kb", "--top", "2", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        write!(stdin, "{}", lines).expect("Failed to write to stdin");
+        drop(stdin);
+        child.wait_with_output().expect("Failed to wait on bloom");
+    }
+
+    // Reading both sketches without any input.
+    let output = Command::new("./target/debug/bloom")
+        .args(["--top", "3", "-f", paths[0].to_str().unwrap(), "-f", paths[1].to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(output_str.lines().collect::<Vec<&str>>(), vec!["      4 c", "      3 a", "      3 b"]);
+}
+
+#[test]
+fn test_estimate_distinct_lines() {
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["--estimate"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for _ in 0..2 {
+        for i in 1..=10000 {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+
+    let estimate: f64 = output_str.lines().next().unwrap().trim_start_matches("Estimated distinct lines: ").parse().unwrap();
+    assert!((estimate - 10000.0).abs() < 500.0, "Estimate {} is too far from 10000", estimate);
+    assert!(output_str.contains("-ble "), "Expected recommended Bloom filter parameters");
+}
+
+#[test]
+fn test_plan_prints_arguments() {
+    let output = Command::new("./target/debug/bloom")
+        .args(["plan", "--items", "1M", "--fpr", "0.001"])
+        .output()
+        .expect("Failed to run bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+
+    assert!(output.status.success(), "Expected plan to succeed");
+    assert!(output_str.contains("k = 10"), "Expected optimal number of hash functions");
+    assert!(output_str.contains("-ble 1000000,0.001"), "Expected ready to paste Bloom filter arguments");
+    assert!(output_str.contains("-el 1000000"), "Expected ready to paste exact container arguments");
+
+    // Hybrid container spills into filter with 0.1% false positive rate, so it can't be offered for lower rates.
+    let output = Command::new("./target/debug/bloom")
+        .args(["plan", "--items", "500M", "--fpr", "1e-6"])
+        .output()
+        .expect("Failed to run bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let hybrid_row = output_str.lines().find(|line| line.starts_with("hybrid")).expect("Expected hybrid row");
+
+    assert!(hybrid_row.contains("doesn't reach requested rate"), "Expected hybrid row to be marked: {}", hybrid_row);
+    assert!(!hybrid_row.contains("-hls"), "Expected no hybrid arguments: {}", hybrid_row);
+}
+
+#[test]
+fn test_undersized_bloom_filter_warning() {
+    let output = Command::new("./target/debug/bloom")
+        .args(["-bls", "1M,1Kb"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+
+    assert!(stderr_str.contains("Warning: -bls 1000000,1000"), "Expected warning about undersized filter");
+}
+
+#[test]
+fn test_too_big_exact_limit() {
+    for args in [vec!["-el", "18446744073709551615"], vec!["plan", "--items", "18446744073709551615", "--memory", "1G"]] {
+        let output = Command::new("./target/debug/bloom")
+            .args(&args)
+            .stdin(Stdio::null())
+            .output()
+            .expect("Failed to run bloom");
+        let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+
+        assert_eq!(output.status.code(), Some(1), "Expected {:?} to fail", args);
+        assert!(stderr_str.contains("Error: Exact container with limit 18446744073709551615 is too big."), "Expected error about the limit: {}", stderr_str);
+    }
+}
+
+#[test]
+fn test_memory_budget() {
+    let output = Command::new("./target/debug/bloom")
+        .args(["-el", "1M", "--memory-budget", "1Kb"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+
+    assert_eq!(output.status.code(), Some(1), "Expected exact container not to fit into the budget");
+    assert!(stderr_str.contains("exceeds memory budget"), "Expected error about exceeded budget");
+
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["--memory-budget", "1Mb", "-d"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom");
+
+    let mut stdin = child.stdin.take().expect("Failed to open stdin");
+    for i in [1, 2, 1, 3, 2] {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let output_str = String::from_utf8(output.stdout).expect("Output not UTF-8");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+
+    assert_eq!(output_str, "1\n2\n3\n");
+    assert!(stderr_str.contains("Memory budget: 976.56 KiB"), "Expected memory budget in debug output");
+}
+
+#[test]
+fn test_mapped_file_updated_only_in_write_mode() {
+    let path = std::env::temp_dir().join(format!("bloom_mapped_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let run = |args: &[&str], lines: std::ops::RangeInclusive<u32>| -> String {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(args)
+            .args(["-xls", "1000,10Kb", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in lines {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        String::from_utf8(output.stdout).expect("Output not UTF-8")
+    };
+
+    run(&["-w"], 1..=10);
+    let saved = std::fs::read(&path).expect("Failed to read file");
+
+    // Lines set while only querying the file stay in memory.
+    assert_eq!(run(&[], 5..=12), "11\n12\n");
+    assert_eq!(std::fs::read(&path).expect("Failed to read file"), saved, "Expected file to be left untouched");
+
+    // In write mode, content is modified in place.
+    assert_eq!(run(&["-w"], 5..=12), "11\n12\n");
+    assert_eq!(run(&[], 1..=13), "13\n");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_shared_file_concurrent_writers() {
+    let path = std::env::temp_dir().join(format!("bloom_shared_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // Writers are started together, so they insert into the file at the same time.
+    let writers: Vec<_> = (0..4).map(|writer| {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "--shared", "-bls", "100000,1Mb", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        std::thread::spawn(move || {
+            for i in 0..5000 {
+                writeln!(stdin, "{}", writer * 5000 + i).expect("Failed to write to stdin");
+            }
+        });
+        child
+    }).collect();
+
+    for writer in writers {
+        assert!(writer.wait_with_output().expect("Failed to wait on bloom").status.success());
+    }
+
+    // Lines of all the writers are in the file.
+    let mut child = Command::new("./target/debug/bloom")
+        .args(["-f", path.to_str().unwrap(), "-d"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn bloom process");
+
+    let mut stdin = child.stdin.take().expect("Failed to get stdin");
+    for i in 0..20000 {
+        writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().expect("Failed to wait on bloom");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+    let _ = std::fs::remove_file(&path);
+
+    assert!(output.stdout.is_empty(), "Expected all lines to be found");
+    assert!(stderr_str.contains("line fill: 20 %"), "Expected writes of all the processes to be counted: {}", stderr_str);
+}
+
+#[test]
+fn test_shared_xxh_file_claims_each_line_once() {
+    let path = std::env::temp_dir().join(format!("bloom_shared_xxh_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // All the writers insert the same lines at the same time, so they race for the same slots.
+    let writers: Vec<_> = (0..4).map(|_| {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["-w", "--shared", "-xls", "5000,1Mb", "-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        std::thread::spawn(move || {
+            for i in 0..5000 {
+                writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+            }
+        });
+        child
+    }).collect();
+
+    let mut lines = vec![];
+    for writer in writers {
+        let output = writer.wait_with_output().expect("Failed to wait on bloom");
+        assert!(output.status.success());
+        lines.extend(String::from_utf8(output.stdout).expect("Output not UTF-8").lines().map(str::to_string));
+    }
+
+    // Files whose slots aren't aligned to words (content version 0) can't be shared.
+    let mut content = std::fs::read(&path).unwrap();
+    content[70] = 0;
+    std::fs::write(&path, content).unwrap();
+    let output = Command::new("./target/debug/bloom")
+        .args(["-w", "--shared", "-f", path.to_str().unwrap()])
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run bloom");
+    let stderr_str = String::from_utf8(output.stderr).expect("Output not UTF-8");
+    let _ = std::fs::remove_file(&path);
+
+    let num_lines = lines.len();
+    lines.sort();
+    lines.dedup();
+    assert_eq!(num_lines, 5000, "Expected each line to be claimed by exactly one writer");
+    assert_eq!(lines.len(), 5000, "Expected no line to be printed twice");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_str.contains("can't be shared"), "Expected older file to be refused: {}", stderr_str);
+}
+
+#[test]
+fn test_threads_keep_output_of_single_thread() {
+    // Input spans several chunks, with repeated lines, invalid UTF-8 and the last line without a newline.
+    let mut input = vec![];
+    for i in 0..400000u64 {
+        writeln!(input, "line {}", i * 7919 % 150000).unwrap();
+        if i % 50000 == 0 {
+            input.extend_from_slice(b"invalid \xFF\xFE line\n");
+        }
+    }
+    input.extend_from_slice(b"last line");
+
+    let path = std::env::temp_dir().join(format!("bloom_threads_{}.txt", std::process::id()));
+    std::fs::write(&path, &input).unwrap();
+
+    let run = |threads: &str, from_file: bool, container: &[&str]| {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["--threads", threads])
+            .args(container)
+            .stdin(if from_file { Stdio::from(std::fs::File::open(&path).unwrap()) } else { Stdio::piped() })
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        if !from_file {
+            let mut stdin = child.stdin.take().expect("Failed to get stdin");
+            let input = input.clone();
+            std::thread::spawn(move || stdin.write_all(&input).expect("Failed to write to stdin"));
+        }
+
+        child.wait_with_output().expect("Failed to wait on bloom").stdout
+    };
+
+    let expected = run("1", true, &["-xls", "100000,1Mb"]);
+    let from_file = run("4", true, &["-xls", "100000,1Mb"]);
+    let from_pipe = run("4", false, &["-xls", "100000,1Mb"]);
+
+    // Filter is too small for its limit, so many lines find all their slots occupied. Such lines are found when looked
+    // up, but they can't be written, so they're reported as new.
+    let crowded_expected = run("1", true, &["-xls", "200000,300Kb"]);
+    let crowded = run("4", true, &["-xls", "200000,300Kb"]);
+    let _ = std::fs::remove_file(&path);
+
+    // Filter gets full in the middle of the input, so lines are written into it only until then.
+    assert!(String::from_utf8_lossy(&expected).ends_with("last line\n"));
+    assert!(from_file == expected, "Expected the same output when reading file in 4 threads");
+    assert!(from_pipe == expected, "Expected the same output when reading pipe in 4 threads");
+    assert!(String::from_utf8_lossy(&crowded_expected).lines().count() > 150000, "Expected lines which can't be written to be printed again");
+    assert!(crowded == crowded_expected, "Expected the same output for crowded filter in 4 threads");
+}
+
+#[test]
+fn test_sharded_file_saves_only_modified_shards() {
+    let path = std::env::temp_dir().join(format!("bloom_sharded_{}.blf", std::process::id()));
+    let shard_paths: Vec<_> = (0..4).map(|idx| std::env::temp_dir().join(format!("bloom_sharded_{}.shard-{:04}.blf", std::process::id(), idx))).collect();
+
+    let run = |args: &[&str], lines: std::ops::RangeInclusive<u32>| -> String {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(args)
+            .args(["-f", path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in lines {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        String::from_utf8(output.stdout).expect("Output not UTF-8")
+    };
+
+    assert_eq!(run(&["-w", "--shards", "4", "-el", "1000"], 1..=100).lines().count(), 100);
+    assert!(!path.exists(), "Expected shards to be stored only in their own files");
+    let saved: Vec<Vec<u8>> = shard_paths.iter().map(|path| std::fs::read(path).expect("Failed to read shard file")).collect();
+
+    // Sharded file is opened by its base path and only the shard of the new line (and the first shard holding the
+    // distinct lines estimate) gets rewritten.
+    assert_eq!(run(&["-w"], 95..=101), "101\n");
+    let num_modified = shard_paths.iter().zip(saved.iter()).filter(|(path, saved)| std::fs::read(path).unwrap() != **saved).count();
+    assert!((1..=2).contains(&num_modified), "Expected only modified shards to be saved, {} were", num_modified);
+
+    assert_eq!(run(&[], 1..=102), "102\n");
+    for shard_path in shard_paths {
+        let _ = std::fs::remove_file(shard_path);
+    }
+}
+
+#[test]
+fn test_sharded_container_gets_full_with_its_first_full_shard() {
+    // Block of 64 lines fits into the whole container, but not into the shard which gets more than half of them.
+    let run = |args: &[&str]| -> String {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(["--shards", "2", "-el", "64", "--on-full", "stop"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in 1..=64 {
+            writeln!(stdin, "{}", i).expect("Failed to write to stdin");
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        String::from_utf8(output.stdout).expect("Output not UTF-8")
+    };
+
+    // Debug output processes lines one by one.
+    let expected = run(&["-di"]);
+    assert!(expected.lines().count() < 64, "Expected input to stop once the first shard got full");
+    assert_eq!(run(&[]), expected);
+    assert_eq!(run(&["--threads", "4"]), expected);
+}
+
+#[test]
+fn test_partitions_split_input() {
+    let path = std::env::temp_dir().join(format!("bloom_partition_{}.blf", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let run = |args: &[&str]| -> (bool, String) {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(args)
+            .args(["-xls", "10000,100Kb"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        for i in 0..3000 {
+            // Refused runs exit without reading the input.
+            if writeln!(stdin, "{}", i % 1000).is_err() {
+                break;
+            }
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        (output.status.success(), String::from_utf8(output.stdout).expect("Output not UTF-8"))
+    };
+
+    // Each line is printed once by exactly one of the partitions.
+    let mut lines: Vec<String> = vec![];
+    for partition in ["1/3", "2/3", "3/3"] {
+        let (_, output) = run(&["--partition", partition]);
+        lines.extend(output.lines().map(String::from));
+    }
+    lines.sort_by_key(|line| line.parse::<u32>().unwrap());
+    assert_eq!(lines, (0..1000).map(|i| i.to_string()).collect::<Vec<_>>());
+
+    // Lines of other partitions are passed through as they are.
+    let (_, passed) = run(&["--partition", "1/3", "--foreign-lines", "pass"]);
+    let (_, own) = run(&["--partition", "1/3"]);
+    assert_eq!(passed.lines().count(), 3000 - own.lines().count() * 2);
+
+    // Partition is stored in the file, which is refused with other partition.
+    let (success, _) = run(&["-w", "--partition", "2/3", "-f", path.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(run(&["--partition", "2/3", "-f", path.to_str().unwrap()]), (true, String::new()));
+    assert!(!run(&["--partition", "1/3", "-f", path.to_str().unwrap()]).0, "Expected file of other partition to be refused");
+    assert!(!run(&["-f", path.to_str().unwrap()]).0, "Expected file of a partition to be refused for the whole input");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_merge_combines_filter_files() {
+    let dir = std::env::temp_dir();
+    let path = |name: &str| dir.join(format!("bloom_merge_{}_{}.blf", std::process::id(), name)).to_str().unwrap().to_string();
+
+    let run = |args: &[&str], input: String| -> (bool, String) {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        let _ = stdin.write_all(input.as_bytes());
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        (output.status.success(), String::from_utf8(output.stdout).expect("Output not UTF-8"))
+    };
+    let lines = |range: std::ops::Range<u32>| range.map(|i| format!("{}\n", i)).collect::<String>();
+
+    run(&["-w", "-xls", "10000,100Kb", "-f", &path("a")], lines(0..1000));
+    run(&["-w", "-xls", "10000,100Kb", "-f", &path("b")], lines(500..1500));
+    run(&["-w", "-xls", "10000,200Kb", "-f", &path("c")], lines(0..1000));
+
+    // Lines of both files are found in the union, other lines aren't.
+    assert!(run(&["merge", "-o", &path("union"), &path("a"), &path("b")], String::new()).0);
+    assert_eq!(run(&["-f", &path("union")], lines(0..2000)), (true, lines(1500..2000)));
+
+    // Filters of different sizes are refused.
+    assert!(!run(&["merge", "-o", &path("union"), &path("a"), &path("c")], String::new()).0, "Expected filters of different sizes to be refused");
+
+    for name in ["a", "b", "c", "union"] {
+        let _ = std::fs::remove_file(path(name));
+    }
+}
+
+#[test]
+fn test_intersect_and_subtract_filter_files() {
+    let dir = std::env::temp_dir();
+    let path = |name: &str| dir.join(format!("bloom_sets_{}_{}.blf", std::process::id(), name)).to_str().unwrap().to_string();
+
+    let run = |args: &[&str], input: String| -> (bool, String) {
+        let mut child = Command::new("./target/debug/bloom")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn bloom process");
+
+        let mut stdin = child.stdin.take().expect("Failed to get stdin");
+        let _ = stdin.write_all(input.as_bytes());
+        drop(stdin);
+
+        let output = child.wait_with_output().expect("Failed to wait on bloom");
+        (output.status.success(), String::from_utf8(output.stdout).expect("Output not UTF-8"))
+    };
+    let lines = |range: std::ops::Range<u32>| range.map(|i| format!("{}\n", i)).collect::<String>();
+
+    run(&["-w", "-xls", "10000,100Kb", "-f", &path("a")], lines(0..1000));
+    run(&["-w", "-xls", "10000,100Kb", "-f", &path("b")], lines(500..1500));
+
+    // Only lines of both files are found in the intersection.
+    assert!(run(&["intersect", "-o", &path("intersection"), &path("a"), &path("b")], String::new()).0);
+    assert_eq!(run(&["-f", &path("intersection")], lines(0..2000)), (true, lines(0..500) + &lines(1000..2000)));
+
+    // Only lines of the first file which aren't in the second one are found in the difference.
+    let (success, output) = run(&["subtract", "-o", &path("difference"), &path("a"), &path("b")], String::new());
+    assert!(success);
+    // Keys whose home slot is unknown may be kept more than once, so the estimate is a bit higher.
+    let estimate: f64 = output.split("Estimated lines: ").nth(1).and_then(|rest| rest.split(',').next()).unwrap().parse().unwrap();
+    assert!((500.0 .. 520.0).contains(&estimate), "Unexpected estimate: {}", output);
+    assert_eq!(run(&["-f", &path("difference")], lines(0..2000)), (true, lines(500..2000)));
+
+    for name in ["a", "b", "intersection", "difference"] {
+        let _ = std::fs::remove_file(path(name));
+    }
+}
+
+#[test]
+fn test_compare_estimates_overlap() {
+    let path = |name: &str| temp_path("compare", name);
+
+    // Exact sets are estimated exactly.
+    run(&["-w", "-el", "10000", "-f", &path("a")], lines(0..1000));
+    run(&["-w", "-el", "10000", "-f", &path("b")], lines(500..2000));
+    let (success, output) = run(&["compare", &path("a"), &path("b")], String::new());
+    assert!(success);
+    assert!(output.contains("Common lines: 500\n") && output.contains("Union lines: 2000\n") && output.contains("Jaccard similarity: 0.2500\n"),
+        "Unexpected comparison: {}", output);
+
+    let (success, output) = run(&["compare", "--json", &path("a"), &path("b")], String::new());
+    assert!(success);
+    assert!(output.starts_with("{\"files\": [{\"path\": ") && output.ends_with("\"intersection\": 500, \"union\": 2000, \"jaccard\": 0.250000}\n"),
+        "Unexpected JSON: {}", output);
+
+    // Bloom filters created from the same empty file are estimated from their bit fill.
+    run(&["-w", "-bls", "10000,100Kb", "-f", &path("empty")], String::new());
+    std::fs::copy(path("empty"), path("c")).unwrap();
+    std::fs::copy(path("empty"), path("d")).unwrap();
+    run(&["-w", "-f", &path("c")], lines(0..1000));
+    run(&["-w", "-f", &path("d")], lines(500..2000));
+    let (success, output) = run(&["compare", "--json", &path("c"), &path("d")], String::new());
+    assert!(success);
+    let intersection: f64 = output.split("\"intersection\": ").nth(1).and_then(|rest| rest.split(',').next()).unwrap().parse().unwrap();
+    assert!((450.0 .. 550.0).contains(&intersection), "Unexpected JSON: {}", output);
+
+    // Filters of different types are refused.
+    assert!(!run(&["compare", &path("a"), &path("c")], String::new()).0, "Expected filters of different types to be refused");
+
+    // Lines of a saturated filter can't be estimated.
+    run(&["-w", "-bls", "100,1", "-f", &path("saturated")], lines(0..100));
+    let (success, output) = run(&["compare", "--json", &path("saturated"), &path("saturated")], String::new());
+    assert!(success);
+    assert!(output.contains("\"lines\": null") && output.ends_with("\"intersection\": null, \"union\": null, \"jaccard\": null}\n"),
+        "Unexpected JSON: {}", output);
+    let (_, output) = run(&["compare", &path("saturated"), &path("saturated")], String::new());
+    assert!(output.contains("Common lines: saturated\n") && output.contains("Jaccard similarity: saturated\n"), "Unexpected comparison: {}", output);
+
+    for name in ["a", "b", "c", "d", "empty", "saturated"] {
+        let _ = std::fs::remove_file(path(name));
+    }
+}